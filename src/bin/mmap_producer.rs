@@ -0,0 +1,315 @@
+//! Writes frames into a memory-mapped file using the mmap frame protocol,
+//! standing in for the Unity plugin so the MMAP camera path can be tested
+//! without Unity.
+//!
+//! ```text
+//! mmap_producer <output> [--fps N] [--format F] [--loop] <source>
+//!
+//! sources:
+//!   --video PATH              frames of a video file
+//!   --images DIR [--pattern P]  numbered stills in a folder
+//!   --synthetic               a moving ChArUco board
+//! formats: jpg (default), png, rgb, bgr, rgba, bgra
+//! ```
+//!
+//! Without `--loop` the producer stops after the last frame of a video or
+//! the last image of a folder. The synthetic board never ends.
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use mocap_for_one::{
+    ImageSequence, MmapFrameWriter, PixelFormat, SyntheticConfig,
+    SyntheticRenderer,
+};
+use opencv::core::{Mat, Vector};
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{self, VideoCapture};
+
+const USAGE: &str = "usage: mmap_producer <output> [--fps N] \
+[--format jpg|png|rgb|bgr|rgba|bgra] [--loop] \
+(--video PATH | --images DIR [--pattern P] | --synthetic)
+--loop restarts a video or image folder at its end; --synthetic runs until \
+interrupted";
+
+// 何フレームごとに進捗を出すか
+const REPORT_EVERY: u64 = 100;
+
+enum SourceArg {
+    Video(PathBuf),
+    Images { dir: PathBuf, pattern: String },
+    Synthetic,
+}
+
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Jpeg,
+    Png,
+    Raw(PixelFormat),
+}
+
+struct Args {
+    output: PathBuf,
+    fps: f64,
+    format: OutputFormat,
+    looping: bool,
+    source: SourceArg,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut output = None;
+    let mut fps = 30.0;
+    let mut format = OutputFormat::Jpeg;
+    let mut looping = false;
+    let mut source = None;
+    let mut pattern = "*".to_owned();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| anyhow!("{name} needs a value"))
+        };
+        match arg.as_str() {
+            "--fps" => {
+                fps = value("--fps")?.parse().context("invalid --fps")?;
+                if fps <= 0.0 {
+                    return Err(anyhow!("--fps must be positive"));
+                }
+            }
+            "--format" => {
+                format = match value("--format")?.as_str() {
+                    "jpg" | "jpeg" => OutputFormat::Jpeg,
+                    "png" => OutputFormat::Png,
+                    "rgb" => OutputFormat::Raw(PixelFormat::Rgb8),
+                    "bgr" => OutputFormat::Raw(PixelFormat::Bgr8),
+                    "rgba" => OutputFormat::Raw(PixelFormat::Rgba8),
+                    "bgra" => OutputFormat::Raw(PixelFormat::Bgra8),
+                    other => return Err(anyhow!("unknown format '{other}'")),
+                };
+            }
+            "--loop" => looping = true,
+            "--video" => {
+                source = Some(SourceArg::Video(value("--video")?.into()));
+            }
+            "--images" => {
+                source = Some(SourceArg::Images {
+                    dir: value("--images")?.into(),
+                    pattern: String::new(),
+                });
+            }
+            "--pattern" => pattern = value("--pattern")?,
+            "--synthetic" => source = Some(SourceArg::Synthetic),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") => {
+                return Err(anyhow!("unknown option '{arg}'"));
+            }
+            _ if output.is_none() => output = Some(PathBuf::from(arg)),
+            _ => return Err(anyhow!("unexpected argument '{arg}'")),
+        }
+    }
+
+    let mut source = source.ok_or_else(|| anyhow!("no source given"))?;
+    if let SourceArg::Images { pattern: p, .. } = &mut source {
+        *p = pattern;
+    }
+    Ok(Args {
+        output: output.ok_or_else(|| anyhow!("no output file given"))?,
+        fps,
+        format,
+        looping,
+        source,
+    })
+}
+
+// RGBのフレームを順番に出す。Noneは終端
+enum FrameSource {
+    Video {
+        capture: VideoCapture,
+        looping: bool,
+    },
+    Images {
+        sequence: ImageSequence,
+        // ループしないときに残っている枚数
+        remaining: Option<usize>,
+    },
+    Synthetic {
+        renderer: SyntheticRenderer,
+        index: u64,
+    },
+}
+
+impl FrameSource {
+    fn open(args: &Args) -> Result<Self> {
+        Ok(match &args.source {
+            SourceArg::Video(path) => {
+                let capture = VideoCapture::from_file(
+                    &path.to_string_lossy(),
+                    videoio::CAP_ANY,
+                )?;
+                if !capture.is_opened()? {
+                    return Err(anyhow!(
+                        "failed to open video '{}'",
+                        path.display()
+                    ));
+                }
+                Self::Video {
+                    capture,
+                    looping: args.looping,
+                }
+            }
+            // 間隔はrunで管理するのでImageSequence::readは使わない
+            SourceArg::Images { dir, pattern } => {
+                let sequence = ImageSequence::new(dir, pattern, args.fps)?;
+                let remaining = (!args.looping).then(|| sequence.image_count());
+                Self::Images {
+                    sequence,
+                    remaining,
+                }
+            }
+            SourceArg::Synthetic => Self::Synthetic {
+                renderer: SyntheticRenderer::new(SyntheticConfig {
+                    fps: args.fps,
+                    ..Default::default()
+                })?,
+                index: 0,
+            },
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Mat>> {
+        match self {
+            Self::Video { capture, looping } => {
+                let mut frame = Mat::default();
+                if !capture.read(&mut frame)? || frame.empty() {
+                    if !*looping {
+                        return Ok(None);
+                    }
+                    capture.set(videoio::CAP_PROP_POS_FRAMES, 0.0)?;
+                    if !capture.read(&mut frame)? || frame.empty() {
+                        return Err(anyhow!("video has no frames"));
+                    }
+                }
+                let mut rgb_frame = Mat::default();
+                imgproc::cvt_color(
+                    &frame,
+                    &mut rgb_frame,
+                    imgproc::COLOR_BGR2RGB,
+                    0,
+                    opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+                )?;
+                Ok(Some(rgb_frame))
+            }
+            Self::Images {
+                sequence,
+                remaining,
+            } => {
+                if let Some(remaining) = remaining {
+                    if *remaining == 0 {
+                        return Ok(None);
+                    }
+                    *remaining -= 1;
+                }
+                sequence.next_frame().map(Some)
+            }
+            Self::Synthetic { renderer, index } => {
+                let frame = renderer.render(*index)?;
+                *index += 1;
+                Ok(Some(frame.image))
+            }
+        }
+    }
+}
+
+// RGBのフレームを書き出す形式のバイト列にする
+fn encode(frame: &Mat, format: OutputFormat) -> Result<(PixelFormat, Vec<u8>)> {
+    let convert = |code: i32| -> Result<Mat> {
+        let mut converted = Mat::default();
+        imgproc::cvt_color(
+            frame,
+            &mut converted,
+            code,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
+        Ok(converted)
+    };
+
+    match format {
+        OutputFormat::Jpeg | OutputFormat::Png => {
+            let ext = match format {
+                OutputFormat::Png => ".png",
+                _ => ".jpg",
+            };
+            // imencodeはBGRを期待する
+            let bgr = convert(imgproc::COLOR_RGB2BGR)?;
+            let mut buf = Vector::<u8>::new();
+            imgcodecs::imencode(ext, &bgr, &mut buf, &Vector::new())?;
+            Ok((PixelFormat::Encoded, buf.to_vec()))
+        }
+        OutputFormat::Raw(pixel_format) => {
+            let converted = match pixel_format {
+                PixelFormat::Bgr8 => convert(imgproc::COLOR_RGB2BGR)?,
+                PixelFormat::Rgba8 => convert(imgproc::COLOR_RGB2RGBA)?,
+                PixelFormat::Bgra8 => convert(imgproc::COLOR_RGB2BGRA)?,
+                _ => frame.try_clone()?,
+            };
+            Ok((pixel_format, converted.data_bytes()?.to_vec()))
+        }
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut source = FrameSource::open(&args)?;
+    let mut writer: Option<MmapFrameWriter> = None;
+    let interval = Duration::from_secs_f64(1.0 / args.fps);
+    let mut next_due = Instant::now();
+    let started = Instant::now();
+
+    while let Some(frame) = source.next()? {
+        let (pixel_format, payload) = encode(&frame, args.format)?;
+
+        // 伸ばすたびにマップし直すことになるので、生のRGBAが入る大きさで作る
+        if writer.is_none() {
+            let capacity = (frame.cols() * frame.rows() * 4) as usize + 4096;
+            writer = Some(MmapFrameWriter::create(&args.output, capacity)?);
+        }
+        let writer = writer.as_mut().expect("writer was just created");
+
+        let now = Instant::now();
+        if now < next_due {
+            thread::sleep(next_due - now);
+        }
+        next_due += interval;
+        // 遅れが溜まっても連続で書き込まない
+        if next_due < Instant::now() {
+            next_due = Instant::now() + interval;
+        }
+
+        let sequence = writer.write_frame(
+            frame.cols() as u32,
+            frame.rows() as u32,
+            pixel_format,
+            &payload,
+        )?;
+        if sequence % REPORT_EVERY == 0 {
+            eprintln!(
+                "wrote {} frames ({:.1} fps)",
+                sequence,
+                sequence as f64 / started.elapsed().as_secs_f64()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = parse_args().context(USAGE)?;
+    run(args)
+}
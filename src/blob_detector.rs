@@ -0,0 +1,192 @@
+use anyhow::{Result, anyhow};
+use opencv::core::{CV_32S, Mat, MatTraitConstManual, Point, Scalar};
+use opencv::imgproc;
+use opencv::prelude::MatTraitConst;
+use serde::{Deserialize, Serialize};
+
+/// Parameters of the bright blob detector used for retroreflective or
+/// LED markers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlobDetectorConfig {
+    // 0..=255の輝度しきい値。これより明るい画素をマーカー候補とする
+    pub threshold: f64,
+    // 面積 [px]
+    pub min_area: f64,
+    pub max_area: f64,
+    // 1.0が真円
+    pub min_circularity: f64,
+}
+
+impl Default for BlobDetectorConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 200.0,
+            min_area: 4.0,
+            max_area: 2000.0,
+            min_circularity: 0.6,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Blob {
+    // 輝度で重み付けしたサブピクセル重心
+    pub center: [f64; 2],
+    pub area: f64,
+    pub circularity: f64,
+}
+
+impl Blob {
+    /// Radius of a disk with the same area.
+    pub fn radius(&self) -> f64 {
+        (self.area / std::f64::consts::PI).sqrt()
+    }
+}
+
+#[derive(Default)]
+struct Component {
+    area: f64,
+    // 画素座標の1次・2次モーメント(面積と円形度用)
+    sx: f64,
+    sy: f64,
+    sxx: f64,
+    syy: f64,
+    // 輝度で重み付けした1次モーメント(重心用)
+    w: f64,
+    wx: f64,
+    wy: f64,
+}
+
+impl BlobDetectorConfig {
+    /// Finds bright circular blobs: threshold, 8-connected components,
+    /// then area and circularity filters.
+    ///
+    /// Circularity is `A^2 / (2π (μ20 + μ02))`, which is 1 for a filled
+    /// disk and drops for elongated or ragged regions. Unlike the contour
+    /// perimeter it stays meaningful for blobs only a few pixels wide.
+    pub fn detect(&self, frame: &Mat) -> Result<Vec<Blob>> {
+        if frame.empty() {
+            return Ok(Vec::new());
+        }
+
+        let gray = match frame.channels() {
+            1 => frame.try_clone()?,
+            3 | 4 => {
+                // パイプラインのフレームはRGB順
+                let code = if frame.channels() == 3 {
+                    imgproc::COLOR_RGB2GRAY
+                } else {
+                    imgproc::COLOR_RGBA2GRAY
+                };
+                let mut gray = Mat::default();
+                imgproc::cvt_color_def(frame, &mut gray, code)?;
+                gray
+            }
+            n => return Err(anyhow!("unsupported channel count {n}")),
+        };
+
+        let mut binary = Mat::default();
+        imgproc::threshold(
+            &gray,
+            &mut binary,
+            self.threshold,
+            255.0,
+            imgproc::THRESH_BINARY,
+        )?;
+
+        let mut labels = Mat::default();
+        let count =
+            imgproc::connected_components(&binary, &mut labels, 8, CV_32S)?;
+        if count <= 1 {
+            return Ok(Vec::new());
+        }
+
+        let cols = gray.cols() as usize;
+        let intensities = gray.data_typed::<u8>()?;
+        let labels = labels.data_typed::<i32>()?;
+
+        // ラベル0は背景
+        let mut components: Vec<Component> =
+            (0..count).map(|_| Component::default()).collect();
+        for (i, (&label, &intensity)) in
+            labels.iter().zip(intensities).enumerate()
+        {
+            if label <= 0 {
+                continue;
+            }
+            let (x, y) = ((i % cols) as f64, (i / cols) as f64);
+            let w = intensity as f64;
+            let c = &mut components[label as usize];
+            c.area += 1.0;
+            c.sx += x;
+            c.sy += y;
+            c.sxx += x * x;
+            c.syy += y * y;
+            c.w += w;
+            c.wx += w * x;
+            c.wy += w * y;
+        }
+
+        Ok(components
+            .iter()
+            .skip(1)
+            .filter(|c| c.area >= self.min_area && c.area <= self.max_area)
+            .filter_map(|c| {
+                // 画素を面積1の正方形とみなし、各画素の2次モーメント1/12を足す
+                let mu20 = c.sxx - c.sx * c.sx / c.area + c.area / 12.0;
+                let mu02 = c.syy - c.sy * c.sy / c.area + c.area / 12.0;
+                let circularity = (c.area * c.area
+                    / (2.0 * std::f64::consts::PI * (mu20 + mu02)))
+                    .min(1.0);
+                (circularity >= self.min_circularity).then(|| Blob {
+                    center: [c.wx / c.w, c.wy / c.w],
+                    area: c.area,
+                    circularity,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Draws each blob as a circle with a cross at its centroid.
+pub fn draw_detected_blobs(
+    image: &mut Mat,
+    blobs: &[Blob],
+    color: Scalar,
+) -> opencv::Result<()> {
+    // shiftで小数点以下4bitまで座標を指定する
+    const SHIFT: i32 = 4;
+    let scale = (1 << SHIFT) as f64;
+
+    for blob in blobs {
+        let center = Point::new(
+            (blob.center[0] * scale).round() as i32,
+            (blob.center[1] * scale).round() as i32,
+        );
+        let radius = ((blob.radius() + 2.0) * scale).round() as i32;
+        imgproc::circle(
+            image,
+            center,
+            radius,
+            color,
+            1,
+            imgproc::LINE_AA,
+            SHIFT,
+        )?;
+
+        let arm = (3.0 * scale) as i32;
+        for (dx, dy) in [(arm, 0), (0, arm)] {
+            imgproc::line(
+                image,
+                Point::new(center.x - dx, center.y - dy),
+                Point::new(center.x + dx, center.y + dy),
+                color,
+                1,
+                imgproc::LINE_AA,
+                SHIFT,
+            )?;
+        }
+    }
+
+    Ok(())
+}
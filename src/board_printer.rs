@@ -0,0 +1,714 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use opencv::core::{CV_8UC1, Mat, Point, Rect, Scalar, Vector, VectorToVec};
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::prelude::{BoardTraitConst, DictionaryTraitConst, MatTraitConst};
+use serde::{Deserialize, Serialize};
+
+use crate::CharucoBoardConfig;
+
+const MM_PER_INCH: f64 = 25.4;
+const PT_PER_INCH: f64 = 72.0;
+const PAGE_MARGIN_MM: f64 = 10.0;
+const FOOTER_HEIGHT_MM: f64 = 24.0;
+const MAX_RULER_LENGTH_MM: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PaperSize {
+    A4,
+    A3,
+    Letter,
+    Custom { width_mm: f64, height_mm: f64 },
+}
+
+impl PaperSize {
+    pub const PRESETS: [PaperSize; 3] =
+        [PaperSize::A4, PaperSize::A3, PaperSize::Letter];
+
+    pub fn name(&self) -> String {
+        match self {
+            PaperSize::A4 => "A4".to_owned(),
+            PaperSize::A3 => "A3".to_owned(),
+            PaperSize::Letter => "Letter".to_owned(),
+            PaperSize::Custom {
+                width_mm,
+                height_mm,
+            } => format!("Custom ({width_mm} x {height_mm} mm)"),
+        }
+    }
+
+    /// Width and height in portrait orientation.
+    pub fn dimensions_mm(&self) -> (f64, f64) {
+        match *self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::Custom {
+                width_mm,
+                height_mm,
+            } => (width_mm.min(height_mm), width_mm.max(height_mm)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardPrintConfig {
+    pub board: CharucoBoardConfig,
+    pub paper: PaperSize,
+    pub dpi: f64,
+}
+
+impl Default for BoardPrintConfig {
+    fn default() -> Self {
+        Self {
+            board: CharucoBoardConfig::default(),
+            paper: PaperSize::A4,
+            dpi: 300.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BoardPrintOutput {
+    pub png_path: PathBuf,
+    pub svg_path: PathBuf,
+    pub pdf_path: PathBuf,
+    pub landscape: bool,
+}
+
+// 印刷物の図形はすべてmm単位・左上原点(y軸下向き)で表す
+#[derive(Debug, Clone)]
+enum Shape {
+    Rect {
+        x: f64,
+        y: f64,
+        w: f64,
+        h: f64,
+    },
+    Line {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        width: f64,
+    },
+    // (x, y)は文字列のベースライン左端
+    Text {
+        x: f64,
+        y: f64,
+        size: f64,
+        text: String,
+    },
+}
+
+struct PrintLayout {
+    width_mm: f64,
+    height_mm: f64,
+    landscape: bool,
+    shapes: Vec<Shape>,
+}
+
+/// Writes `<stem>.png`, `<stem>.svg` and `<stem>.pdf` for the board.
+/// All three are laid out on the same page so that, printed at 100%
+/// scale, every square measures exactly `square_length`.
+pub fn generate_printable_board<P: AsRef<Path>>(
+    config: &BoardPrintConfig,
+    output_stem: P,
+) -> Result<BoardPrintOutput> {
+    if config.dpi.is_nan() || config.dpi < 72.0 {
+        return Err(anyhow!("dpi must be at least 72, got {}", config.dpi));
+    }
+
+    let layout = layout_board(config)?;
+
+    let stem = output_stem.as_ref();
+    if let Some(parent) = stem.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create '{}'", parent.display())
+            })?;
+        }
+    }
+
+    let png_path = append_extension(stem, "png");
+    let svg_path = append_extension(stem, "svg");
+    let pdf_path = append_extension(stem, "pdf");
+
+    fs::write(&png_path, render_png(&layout, config.dpi)?)
+        .with_context(|| format!("failed to write '{}'", png_path.display()))?;
+    fs::write(&svg_path, render_svg(&layout))
+        .with_context(|| format!("failed to write '{}'", svg_path.display()))?;
+    fs::write(&pdf_path, render_pdf(&layout))
+        .with_context(|| format!("failed to write '{}'", pdf_path.display()))?;
+
+    Ok(BoardPrintOutput {
+        png_path,
+        svg_path,
+        pdf_path,
+        landscape: layout.landscape,
+    })
+}
+
+// with_extensionは"board_a4.v2"の".v2"を置き換えてしまうので、
+// ファイル名の末尾に拡張子を付け足す
+fn append_extension(stem: &Path, extension: &str) -> PathBuf {
+    let mut path = stem.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+fn layout_board(config: &BoardPrintConfig) -> Result<PrintLayout> {
+    let board_config = &config.board;
+    let board = board_config.build()?;
+
+    let square_mm = board_config.square_length as f64 * 1000.0;
+    let marker_mm = board_config.marker_length as f64 * 1000.0;
+    let board_w = board_config.squares_x as f64 * square_mm;
+    let board_h = board_config.squares_y as f64 * square_mm;
+
+    // 縦向きで入らなければ横向きを試す
+    let (paper_w, paper_h) = config.paper.dimensions_mm();
+    let fits = |w: f64, h: f64| {
+        board_w <= w - 2.0 * PAGE_MARGIN_MM
+            && board_h <= h - 2.0 * PAGE_MARGIN_MM - FOOTER_HEIGHT_MM
+    };
+    let (width_mm, height_mm, landscape) = if fits(paper_w, paper_h) {
+        (paper_w, paper_h, false)
+    } else if fits(paper_h, paper_w) {
+        (paper_h, paper_w, true)
+    } else {
+        return Err(anyhow!(
+            "{:.1} x {:.1} mm board does not fit on {} paper",
+            board_w,
+            board_h,
+            config.paper.name()
+        ));
+    };
+
+    let origin_x = (width_mm - board_w) / 2.0;
+    let origin_y = PAGE_MARGIN_MM
+        + (height_mm - 2.0 * PAGE_MARGIN_MM - FOOTER_HEIGHT_MM - board_h) / 2.0;
+
+    let mut shapes = Vec::new();
+
+    // マーカーの入っているマス以外が黒マス
+    let square_m = board_config.square_length;
+    let obj_points = board.get_obj_points()?;
+    let ids = board.get_ids()?;
+    let dictionary = board.get_dictionary()?;
+    let cells = dictionary.marker_size() + 2;
+    let cell_mm = marker_mm / cells as f64;
+
+    let mut marker_squares = HashSet::new();
+    for (i, corners) in obj_points.iter().enumerate() {
+        let min_x = corners.iter().map(|p| p.x).fold(f32::INFINITY, f32::min);
+        let min_y = corners.iter().map(|p| p.y).fold(f32::INFINITY, f32::min);
+        marker_squares.insert((
+            (min_x / square_m).floor() as i32,
+            (min_y / square_m).floor() as i32,
+        ));
+
+        let mut marker = Mat::default();
+        dictionary.generate_image_marker(ids.get(i)?, cells, &mut marker, 1)?;
+
+        let marker_x = origin_x + min_x as f64 * 1000.0;
+        let marker_y = origin_y + min_y as f64 * 1000.0;
+        for row in 0..cells {
+            let mut col = 0;
+            while col < cells {
+                if *marker.at_2d::<u8>(row, col)? >= 128 {
+                    col += 1;
+                    continue;
+                }
+                let start = col;
+                while col < cells && *marker.at_2d::<u8>(row, col)? < 128 {
+                    col += 1;
+                }
+                shapes.push(Shape::Rect {
+                    x: marker_x + start as f64 * cell_mm,
+                    y: marker_y + row as f64 * cell_mm,
+                    w: (col - start) as f64 * cell_mm,
+                    h: cell_mm,
+                });
+            }
+        }
+    }
+
+    for sy in 0..board_config.squares_y {
+        for sx in 0..board_config.squares_x {
+            if marker_squares.contains(&(sx, sy)) {
+                continue;
+            }
+            shapes.push(Shape::Rect {
+                x: origin_x + sx as f64 * square_mm,
+                y: origin_y + sy as f64 * square_mm,
+                w: square_mm,
+                h: square_mm,
+            });
+        }
+    }
+
+    // 印刷倍率の確認用の定規
+    let ruler_len = (MAX_RULER_LENGTH_MM.min(width_mm - 2.0 * PAGE_MARGIN_MM)
+        / 10.0)
+        .floor()
+        * 10.0;
+    let ruler_x = PAGE_MARGIN_MM;
+    let ruler_y = height_mm - PAGE_MARGIN_MM - FOOTER_HEIGHT_MM + 4.0;
+    shapes.push(Shape::Line {
+        x1: ruler_x,
+        y1: ruler_y,
+        x2: ruler_x + ruler_len,
+        y2: ruler_y,
+        width: 0.2,
+    });
+    for mm in 0..=(ruler_len as i32) {
+        let tick = if mm % 10 == 0 {
+            5.0
+        } else if mm % 5 == 0 {
+            3.5
+        } else {
+            2.0
+        };
+        let x = ruler_x + mm as f64;
+        shapes.push(Shape::Line {
+            x1: x,
+            y1: ruler_y,
+            x2: x,
+            y2: ruler_y + tick,
+            width: 0.15,
+        });
+        if mm % 10 == 0 {
+            shapes.push(Shape::Text {
+                x: x - 0.8,
+                y: ruler_y + 8.0,
+                size: 2.5,
+                text: format!("{}", mm / 10),
+            });
+        }
+    }
+    shapes.push(Shape::Text {
+        x: ruler_x + ruler_len + 2.0,
+        y: ruler_y + 5.0,
+        size: 2.5,
+        text: format!("cm (must measure {} mm)", ruler_len),
+    });
+
+    shapes.push(Shape::Text {
+        x: PAGE_MARGIN_MM,
+        y: ruler_y + 14.0,
+        size: 3.0,
+        text: format!(
+            "ChArUco {}x{} | square {:.2} mm | marker {:.2} mm | {} | legacy pattern: {}",
+            board_config.squares_x,
+            board_config.squares_y,
+            square_mm,
+            marker_mm,
+            board_config.dictionary.name(),
+            if board_config.legacy_pattern {
+                "yes"
+            } else {
+                "no"
+            },
+        ),
+    });
+    shapes.push(Shape::Text {
+        x: PAGE_MARGIN_MM,
+        y: ruler_y + 19.0,
+        size: 2.5,
+        text: format!(
+            "{} {} | print at 100% (actual size), no fit-to-page | raster {} dpi",
+            config.paper.name(),
+            if landscape { "landscape" } else { "portrait" },
+            config.dpi
+        ),
+    });
+
+    Ok(PrintLayout {
+        width_mm,
+        height_mm,
+        landscape,
+        shapes,
+    })
+}
+
+fn render_png(layout: &PrintLayout, dpi: f64) -> Result<Vec<u8>> {
+    let px_per_mm = dpi / MM_PER_INCH;
+    let px = |v: f64| (v * px_per_mm).round() as i32;
+
+    let mut img = Mat::new_rows_cols_with_default(
+        px(layout.height_mm),
+        px(layout.width_mm),
+        CV_8UC1,
+        Scalar::all(255.0),
+    )?;
+    let black = Scalar::all(0.0);
+
+    for shape in &layout.shapes {
+        match shape {
+            // 隣接するマスの境界が一致するよう、幅ではなく両端を丸める
+            Shape::Rect { x, y, w, h } => {
+                let (x0, y0) = (px(*x), px(*y));
+                let (x1, y1) = (px(x + w), px(y + h));
+                imgproc::rectangle(
+                    &mut img,
+                    Rect::new(x0, y0, x1 - x0, y1 - y0),
+                    black,
+                    imgproc::FILLED,
+                    imgproc::LINE_8,
+                    0,
+                )?;
+            }
+            Shape::Line {
+                x1,
+                y1,
+                x2,
+                y2,
+                width,
+            } => {
+                imgproc::line(
+                    &mut img,
+                    Point::new(px(*x1), px(*y1)),
+                    Point::new(px(*x2), px(*y2)),
+                    black,
+                    px(*width).max(1),
+                    imgproc::LINE_8,
+                    0,
+                )?;
+            }
+            Shape::Text { x, y, size, text } => {
+                // Hershey Simplexの大文字の高さはfont_scale=1で約22px
+                let font_scale = size * px_per_mm * 0.7 / 22.0;
+                imgproc::put_text(
+                    &mut img,
+                    text,
+                    Point::new(px(*x), px(*y)),
+                    imgproc::FONT_HERSHEY_SIMPLEX,
+                    font_scale,
+                    black,
+                    ((font_scale * 1.5).round() as i32).max(1),
+                    imgproc::LINE_AA,
+                    false,
+                )?;
+            }
+        }
+    }
+
+    let mut buf = Vector::<u8>::new();
+    imgcodecs::imencode(".png", &img, &mut buf, &Vector::new())?;
+
+    Ok(with_png_dpi(buf.to_vec(), dpi))
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// OpenCVはPNGに解像度を書き込まないので、pHYsチャンクを最初のIDATの前に挿入する
+fn with_png_dpi(mut png: Vec<u8>, dpi: f64) -> Vec<u8> {
+    let Some(chunks) = png_chunks(&png) else {
+        return png;
+    };
+    if chunks.iter().any(|(kind, _)| kind == b"pHYs") {
+        return png;
+    }
+    let Some(&(_, idat)) = chunks.iter().find(|(kind, _)| kind == b"IDAT")
+    else {
+        return png;
+    };
+
+    let pixels_per_meter = (dpi / MM_PER_INCH * 1000.0).round() as u32;
+    let mut body = Vec::with_capacity(13);
+    body.extend_from_slice(b"pHYs");
+    body.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    body.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    body.push(1);
+
+    let mut chunk = Vec::with_capacity(21);
+    chunk.extend_from_slice(&9u32.to_be_bytes());
+    chunk.extend_from_slice(&body);
+    chunk.extend_from_slice(&crc32(&body).to_be_bytes());
+
+    png.splice(idat..idat, chunk);
+    png
+}
+
+// PNGのチャンクを (種類, 先頭の位置) の列にする。壊れていればNone
+fn png_chunks(png: &[u8]) -> Option<Vec<([u8; 4], usize)>> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut at = PNG_SIGNATURE.len();
+    while at < png.len() {
+        // 長さ、種類、データ、CRCの順
+        let header = png.get(at..at + 8)?;
+        let length =
+            u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = [header[4], header[5], header[6], header[7]];
+        chunks.push((kind, at));
+        at = at.checked_add(length as usize)?.checked_add(12)?;
+        if at > png.len() {
+            return None;
+        }
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Some(chunks)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn render_svg(layout: &PrintLayout) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+        w = layout.width_mm,
+        h = layout.height_mm
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{}" height="{}" fill="white"/>"#,
+        layout.width_mm, layout.height_mm
+    );
+
+    let _ = writeln!(svg, r#"<g fill="black" shape-rendering="crispEdges">"#);
+    for shape in &layout.shapes {
+        if let Shape::Rect { x, y, w, h } = shape {
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{x:.4}" y="{y:.4}" width="{w:.4}" height="{h:.4}"/>"#
+            );
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+
+    let _ = writeln!(svg, r#"<g stroke="black" fill="none">"#);
+    for shape in &layout.shapes {
+        if let Shape::Line {
+            x1,
+            y1,
+            x2,
+            y2,
+            width,
+        } = shape
+        {
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{x1:.4}" y1="{y1:.4}" x2="{x2:.4}" y2="{y2:.4}" stroke-width="{width}"/>"#
+            );
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+
+    let _ = writeln!(
+        svg,
+        r#"<g fill="black" font-family="Helvetica, Arial, sans-serif">"#
+    );
+    for shape in &layout.shapes {
+        if let Shape::Text { x, y, size, text } = shape {
+            let escaped = text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            let _ = writeln!(
+                svg,
+                r#"<text x="{x:.4}" y="{y:.4}" font-size="{size}">{escaped}</text>"#
+            );
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+    let _ = writeln!(svg, "</svg>");
+
+    svg
+}
+
+fn render_pdf(layout: &PrintLayout) -> Vec<u8> {
+    let pt = |mm: f64| mm / MM_PER_INCH * PT_PER_INCH;
+    // PDFは左下原点・y軸上向きなので反転する
+    let flip = |y_mm: f64| pt(layout.height_mm - y_mm);
+
+    let mut content = String::from("0 g 0 G\n");
+    for shape in &layout.shapes {
+        match shape {
+            Shape::Rect { x, y, w, h } => {
+                let _ = writeln!(
+                    content,
+                    "{:.4} {:.4} {:.4} {:.4} re f",
+                    pt(*x),
+                    flip(y + h),
+                    pt(*w),
+                    pt(*h)
+                );
+            }
+            Shape::Line {
+                x1,
+                y1,
+                x2,
+                y2,
+                width,
+            } => {
+                let _ = writeln!(
+                    content,
+                    "{:.4} w {:.4} {:.4} m {:.4} {:.4} l S",
+                    pt(*width),
+                    pt(*x1),
+                    flip(*y1),
+                    pt(*x2),
+                    flip(*y2)
+                );
+            }
+            Shape::Text { x, y, size, text } => {
+                let escaped = text
+                    .replace('\\', "\\\\")
+                    .replace('(', "\\(")
+                    .replace(')', "\\)");
+                let _ = writeln!(
+                    content,
+                    "BT /F1 {:.4} Tf {:.4} {:.4} Td ({}) Tj ET",
+                    pt(*size),
+                    pt(*x),
+                    flip(*y),
+                    escaped
+                );
+            }
+        }
+    }
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_owned(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.4} {:.4}] \
+             /Resources << /Font << /F1 5 0 R >> >> /Contents 4 0 R >>",
+            pt(layout.width_mm),
+            pt(layout.height_mm)
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_owned(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(
+            format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes(),
+        );
+    }
+
+    let xref_offset = pdf.len();
+    let mut trailer =
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = writeln!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF",
+        objects.len() + 1,
+        xref_offset
+    );
+    pdf.extend_from_slice(trailer.as_bytes());
+
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut body = kind.to_vec();
+        body.extend_from_slice(data);
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(&body);
+        chunk.extend_from_slice(&crc32(&body).to_be_bytes());
+        chunk
+    }
+
+    // 1x1のPNG。IDATのデータにはたまたま"pHYs"と同じバイト列が含まれる
+    fn png(extra: &[Vec<u8>]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]));
+        for chunk in extra {
+            png.extend_from_slice(chunk);
+        }
+        png.extend(chunk(b"IDAT", b"xx pHYs xx"));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    // (種類, データ) の列。CRCも確かめる
+    fn parse(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        png_chunks(png)
+            .expect("valid PNG")
+            .into_iter()
+            .map(|(kind, at)| {
+                let length =
+                    u32::from_be_bytes(png[at..at + 4].try_into().unwrap())
+                        as usize;
+                let body = &png[at + 4..at + 8 + length];
+                let crc = &png[at + 8 + length..at + 12 + length];
+                assert_eq!(crc, crc32(body).to_be_bytes());
+                (kind, body[4..].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inserts_phys_before_first_idat() {
+        let chunks = parse(&with_png_dpi(png(&[]), 300.0));
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, [b"IHDR", b"pHYs", b"IDAT", b"IEND"]);
+
+        // 300 dpi = 11811 px/m、単位はメートル
+        let ppm = 11811u32.to_be_bytes();
+        let phys: Vec<u8> = [&ppm[..], &ppm[..], &[1]].concat();
+        assert_eq!(chunks[1].1, phys);
+    }
+
+    #[test]
+    fn keeps_existing_phys_and_malformed_png() {
+        let existing = png(&[chunk(b"pHYs", &[0, 0, 0, 1, 0, 0, 0, 1, 0])]);
+        assert_eq!(with_png_dpi(existing.clone(), 300.0), existing);
+
+        let mut truncated = png(&[]);
+        truncated.truncate(truncated.len() - 6);
+        assert_eq!(with_png_dpi(truncated.clone(), 300.0), truncated);
+        assert_eq!(with_png_dpi(b"GIF89a".to_vec(), 300.0), b"GIF89a");
+    }
+
+    #[test]
+    fn appends_extension_after_existing_one() {
+        assert_eq!(
+            append_extension(Path::new("out/board_a4.v2"), "png"),
+            Path::new("out/board_a4.v2.png")
+        );
+        assert_eq!(
+            append_extension(Path::new("board"), "pdf"),
+            Path::new("board.pdf")
+        );
+    }
+}
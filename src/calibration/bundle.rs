@@ -0,0 +1,693 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::geometry::solve_cholesky;
+use crate::calibration::{BoardObservation, Intrinsics, Pose};
+
+const INTRINSIC_PARAMS: usize = 9;
+const POSE_PARAMS: usize = 6;
+const CAMERA_PARAMS: usize = INTRINSIC_PARAMS + POSE_PARAMS;
+
+// カメラパラメータはintrinsics(9) + world->cameraの姿勢(6)の順に並べる
+type CameraParams = [f64; CAMERA_PARAMS];
+type BoardParams = [f64; POSE_PARAMS];
+
+/// Robust loss applied to the squared pixel distance of each corner.
+/// The parameter is the pixel distance above which a residual is
+/// treated as an outlier.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RobustLoss {
+    Squared,
+    Huber(f64),
+    Cauchy(f64),
+}
+
+impl RobustLoss {
+    fn cost(&self, s: f64) -> f64 {
+        match *self {
+            RobustLoss::Squared => s,
+            RobustLoss::Huber(d) => {
+                if s <= d * d {
+                    s
+                } else {
+                    2.0 * d * s.sqrt() - d * d
+                }
+            }
+            RobustLoss::Cauchy(c) => c * c * (s / (c * c)).ln_1p(),
+        }
+    }
+
+    // IRLSの重み dρ/ds
+    fn weight(&self, s: f64) -> f64 {
+        match *self {
+            RobustLoss::Squared => 1.0,
+            RobustLoss::Huber(d) => {
+                if s <= d * d {
+                    1.0
+                } else {
+                    d / s.sqrt()
+                }
+            }
+            RobustLoss::Cauchy(c) => 1.0 / (1.0 + s / (c * c)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BundleAdjustmentOptions {
+    pub fix_intrinsics: bool,
+    pub loss: RobustLoss,
+    pub max_iterations: usize,
+    // 相対的なコスト減少量がこれを下回ったら収束とみなす
+    pub function_tolerance: f64,
+}
+
+impl Default for BundleAdjustmentOptions {
+    fn default() -> Self {
+        Self {
+            fix_intrinsics: false,
+            loss: RobustLoss::Huber(1.0),
+            max_iterations: 100,
+            function_tolerance: 1e-10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BundleAdjustmentReport {
+    pub initial_rms: f64,
+    pub rms: f64,
+    pub per_camera_rms: Vec<Option<f64>>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// Jointly refines camera intrinsics, camera extrinsics and board poses
+/// with a sparse Levenberg-Marquardt solver.
+///
+/// Board poses are eliminated with the Schur complement so that only a
+/// dense system over the camera parameters has to be factorized. The
+/// reference camera's pose is held fixed to pin down the world frame.
+pub fn bundle_adjust(
+    observations: &[BoardObservation],
+    intrinsics: &mut [Intrinsics],
+    camera_poses: &mut [Option<Pose>],
+    board_poses: &mut BTreeMap<usize, Pose>,
+    reference: usize,
+    options: &BundleAdjustmentOptions,
+) -> BundleAdjustmentReport {
+    let camera_count = intrinsics.len().min(camera_poses.len());
+    let observations: Vec<&BoardObservation> = observations
+        .iter()
+        .filter(|o| {
+            o.camera < camera_count
+                && camera_poses[o.camera].is_some()
+                && board_poses.contains_key(&o.capture)
+        })
+        .collect();
+
+    let mut cameras: Vec<CameraParams> = (0..camera_count)
+        .map(|c| {
+            let mut p = [0.0; CAMERA_PARAMS];
+            p[..INTRINSIC_PARAMS].copy_from_slice(&intrinsics[c].to_params());
+            p[INTRINSIC_PARAMS..].copy_from_slice(
+                &camera_poses[c].unwrap_or_default().to_params(),
+            );
+            p
+        })
+        .collect();
+    let mut boards: BTreeMap<usize, BoardParams> = board_poses
+        .iter()
+        .map(|(capture, pose)| (*capture, pose.to_params()))
+        .collect();
+
+    let mut views_per_capture: BTreeMap<usize, usize> = BTreeMap::new();
+    for obs in &observations {
+        *views_per_capture.entry(obs.capture).or_default() += 1;
+    }
+
+    // 最適化対象のパラメータ(カメラごとのCameraParams内の添字)
+    let mut free: Vec<Vec<usize>> = vec![Vec::new(); camera_count];
+    let mut offsets = vec![0; camera_count];
+    let mut camera_dim = 0;
+    for c in 0..camera_count {
+        if !observations.iter().any(|o| o.camera == c) {
+            continue;
+        }
+        if !options.fix_intrinsics {
+            free[c].extend(0..INTRINSIC_PARAMS);
+        }
+        // 他のカメラと同時に見たボードがなければ姿勢は決まらないので固定する
+        let shared = observations
+            .iter()
+            .any(|o| o.camera == c && views_per_capture[&o.capture] > 1);
+        if c != reference && shared {
+            free[c].extend(INTRINSIC_PARAMS..CAMERA_PARAMS);
+        }
+        offsets[c] = camera_dim;
+        camera_dim += free[c].len();
+    }
+
+    let initial_rms = rms(&observations, &cameras, &boards, None);
+    let mut current_cost =
+        robust_cost(&observations, &cameras, &boards, options.loss);
+    let mut lambda = 1e-4;
+    let mut iterations = 0;
+    let mut converged = false;
+
+    while iterations < options.max_iterations {
+        iterations += 1;
+
+        let normal = NormalEquations::build(
+            &observations,
+            &cameras,
+            &boards,
+            &free,
+            &offsets,
+            camera_dim,
+            options.loss,
+        );
+
+        let mut improved = false;
+        while lambda < 1e12 {
+            let Some((delta_cameras, delta_boards)) =
+                normal.solve(lambda, &free, &offsets, camera_dim)
+            else {
+                lambda *= 10.0;
+                continue;
+            };
+
+            let mut candidate_cameras = cameras.clone();
+            for (c, indices) in free.iter().enumerate() {
+                for (j, &i) in indices.iter().enumerate() {
+                    candidate_cameras[c][i] += delta_cameras[offsets[c] + j];
+                }
+            }
+            let mut candidate_boards = boards.clone();
+            for (capture, delta) in &delta_boards {
+                if let Some(board) = candidate_boards.get_mut(capture) {
+                    for (p, d) in board.iter_mut().zip(delta) {
+                        *p += d;
+                    }
+                }
+            }
+
+            let candidate_cost = robust_cost(
+                &observations,
+                &candidate_cameras,
+                &candidate_boards,
+                options.loss,
+            );
+            if candidate_cost < current_cost {
+                let relative = (current_cost - candidate_cost) / current_cost;
+                cameras = candidate_cameras;
+                boards = candidate_boards;
+                current_cost = candidate_cost;
+                lambda = (lambda / 10.0).max(1e-12);
+                improved = true;
+                converged = relative < options.function_tolerance;
+                break;
+            }
+            lambda *= 10.0;
+        }
+
+        if !improved {
+            // これ以上コストを下げるステップが見つからない
+            converged = true;
+            break;
+        }
+        if converged {
+            break;
+        }
+    }
+
+    for c in 0..camera_count {
+        if free[c].is_empty() {
+            continue;
+        }
+        if !options.fix_intrinsics {
+            intrinsics[c] =
+                Intrinsics::from_params(&cameras[c][..INTRINSIC_PARAMS]);
+        }
+        if c != reference {
+            camera_poses[c] =
+                Some(Pose::from_params(&cameras[c][INTRINSIC_PARAMS..]));
+        }
+    }
+    for (capture, pose) in board_poses.iter_mut() {
+        if let Some(board) = boards.get(capture) {
+            *pose = Pose::from_params(board);
+        }
+    }
+
+    BundleAdjustmentReport {
+        initial_rms,
+        rms: rms(&observations, &cameras, &boards, None),
+        per_camera_rms: (0..camera_count)
+            .map(|c| {
+                let r = rms(&observations, &cameras, &boards, Some(c));
+                (!r.is_nan()).then_some(r)
+            })
+            .collect(),
+        iterations,
+        converged,
+    }
+}
+
+fn residual(
+    camera: &CameraParams,
+    board: &BoardParams,
+    object_point: &[f64; 3],
+    image_point: &[f64; 2],
+) -> Option<[f64; 2]> {
+    let intrinsics = Intrinsics::from_params(&camera[..INTRINSIC_PARAMS]);
+    let world_to_camera = Pose::from_params(&camera[INTRINSIC_PARAMS..]);
+    let board_to_world = Pose::from_params(board);
+    let p = world_to_camera
+        .transform_point(&board_to_world.transform_point(object_point));
+    let uv = intrinsics.project(&p)?;
+    Some([uv[0] - image_point[0], uv[1] - image_point[1]])
+}
+
+fn for_each_residual(
+    observations: &[&BoardObservation],
+    cameras: &[CameraParams],
+    boards: &BTreeMap<usize, BoardParams>,
+    mut f: impl FnMut(usize, [f64; 2]),
+) {
+    for obs in observations {
+        let Some(board) = boards.get(&obs.capture) else {
+            continue;
+        };
+        for (x, uv) in obs.object_points.iter().zip(&obs.image_points) {
+            if let Some(r) = residual(&cameras[obs.camera], board, x, uv) {
+                f(obs.camera, r);
+            }
+        }
+    }
+}
+
+fn robust_cost(
+    observations: &[&BoardObservation],
+    cameras: &[CameraParams],
+    boards: &BTreeMap<usize, BoardParams>,
+    loss: RobustLoss,
+) -> f64 {
+    let mut sum = 0.0;
+    for_each_residual(observations, cameras, boards, |_, r| {
+        sum += loss.cost(r[0] * r[0] + r[1] * r[1]);
+    });
+    sum
+}
+
+fn rms(
+    observations: &[&BoardObservation],
+    cameras: &[CameraParams],
+    boards: &BTreeMap<usize, BoardParams>,
+    camera: Option<usize>,
+) -> f64 {
+    let mut sum = 0.0;
+    let mut count = 0;
+    for_each_residual(observations, cameras, boards, |c, r| {
+        if camera.is_none_or(|camera| camera == c) {
+            sum += r[0] * r[0] + r[1] * r[1];
+            count += 1;
+        }
+    });
+
+    if count == 0 {
+        return f64::NAN;
+    }
+    (sum / count as f64).sqrt()
+}
+
+fn numeric_step(value: f64) -> f64 {
+    1e-6 * value.abs().max(1.0)
+}
+
+// ガウス・ニュートン近似の正規方程式をカメラ・ボードのブロックごとに保持する
+//   [U  W] [dc]   [-gc]
+//   [W' V] [dk] = [-gk]
+struct NormalEquations {
+    u: Vec<Vec<f64>>,
+    gc: Vec<f64>,
+    v: BTreeMap<usize, [f64; POSE_PARAMS * POSE_PARAMS]>,
+    gk: BTreeMap<usize, BoardParams>,
+    // (camera, capture) -> dim(camera) x 6
+    w: BTreeMap<(usize, usize), Vec<f64>>,
+}
+
+impl NormalEquations {
+    fn build(
+        observations: &[&BoardObservation],
+        cameras: &[CameraParams],
+        boards: &BTreeMap<usize, BoardParams>,
+        free: &[Vec<usize>],
+        offsets: &[usize],
+        camera_dim: usize,
+        loss: RobustLoss,
+    ) -> Self {
+        let mut u: Vec<Vec<f64>> =
+            free.iter().map(|f| vec![0.0; f.len() * f.len()]).collect();
+        let mut gc = vec![0.0; camera_dim];
+        let mut v = BTreeMap::new();
+        let mut gk = BTreeMap::new();
+        let mut w = BTreeMap::new();
+
+        for obs in observations {
+            let c = obs.camera;
+            let Some(board) = boards.get(&obs.capture) else {
+                continue;
+            };
+            let camera = &cameras[c];
+            let dim = free[c].len();
+
+            let v_k = v
+                .entry(obs.capture)
+                .or_insert([0.0; POSE_PARAMS * POSE_PARAMS]);
+            let g_k = gk.entry(obs.capture).or_insert([0.0; POSE_PARAMS]);
+            let w_ck = w
+                .entry((c, obs.capture))
+                .or_insert_with(|| vec![0.0; dim * POSE_PARAMS]);
+
+            for (x, uv) in obs.object_points.iter().zip(&obs.image_points) {
+                let Some(r) = residual(camera, board, x, uv) else {
+                    continue;
+                };
+                let weight = loss.weight(r[0] * r[0] + r[1] * r[1]);
+
+                // 中心差分による数値微分
+                let jc: Vec<[f64; 2]> = free[c]
+                    .iter()
+                    .map(|&i| {
+                        let h = numeric_step(camera[i]);
+                        let (mut plus, mut minus) = (*camera, *camera);
+                        plus[i] += h;
+                        minus[i] -= h;
+                        central_difference(
+                            residual(&plus, board, x, uv),
+                            residual(&minus, board, x, uv),
+                            h,
+                        )
+                    })
+                    .collect();
+                let jk: [[f64; 2]; POSE_PARAMS] = std::array::from_fn(|i| {
+                    let h = numeric_step(board[i]);
+                    let (mut plus, mut minus) = (*board, *board);
+                    plus[i] += h;
+                    minus[i] -= h;
+                    central_difference(
+                        residual(camera, &plus, x, uv),
+                        residual(camera, &minus, x, uv),
+                        h,
+                    )
+                });
+
+                let dot = |a: &[f64; 2], b: &[f64; 2]| {
+                    weight * (a[0] * b[0] + a[1] * b[1])
+                };
+
+                for a in 0..dim {
+                    gc[offsets[c] + a] += dot(&jc[a], &r);
+                    for b in 0..dim {
+                        u[c][a * dim + b] += dot(&jc[a], &jc[b]);
+                    }
+                    for b in 0..POSE_PARAMS {
+                        w_ck[a * POSE_PARAMS + b] += dot(&jc[a], &jk[b]);
+                    }
+                }
+                for a in 0..POSE_PARAMS {
+                    g_k[a] += dot(&jk[a], &r);
+                    for b in 0..POSE_PARAMS {
+                        v_k[a * POSE_PARAMS + b] += dot(&jk[a], &jk[b]);
+                    }
+                }
+            }
+        }
+
+        Self { u, gc, v, gk, w }
+    }
+
+    // 減衰係数lambdaでのステップを求める。ボード姿勢はシューア補元で消去する
+    #[allow(clippy::type_complexity)]
+    fn solve(
+        &self,
+        lambda: f64,
+        free: &[Vec<usize>],
+        offsets: &[usize],
+        camera_dim: usize,
+    ) -> Option<(Vec<f64>, BTreeMap<usize, BoardParams>)> {
+        let mut s = vec![0.0; camera_dim * camera_dim];
+        for (c, u) in self.u.iter().enumerate() {
+            let dim = free[c].len();
+            for a in 0..dim {
+                for b in 0..dim {
+                    s[(offsets[c] + a) * camera_dim + offsets[c] + b] =
+                        u[a * dim + b];
+                }
+                s[(offsets[c] + a) * camera_dim + offsets[c] + a] +=
+                    lambda * u[a * dim + a] + 1e-12;
+            }
+        }
+        let mut b: Vec<f64> = self.gc.iter().map(|g| -g).collect();
+
+        let mut v_inv = BTreeMap::new();
+        for (capture, v) in &self.v {
+            let mut damped = *v;
+            for i in 0..POSE_PARAMS {
+                damped[i * POSE_PARAMS + i] +=
+                    lambda * v[i * POSE_PARAMS + i] + 1e-12;
+            }
+            v_inv.insert(*capture, invert_spd(&damped)?);
+        }
+
+        let mut cameras_of_board: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (camera, capture) in self.w.keys() {
+            cameras_of_board.entry(*capture).or_default().push(*camera);
+        }
+
+        // S = U - W V^-1 W',  b = -gc + W V^-1 gk
+        for (capture, cams) in &cameras_of_board {
+            let vi = &v_inv[capture];
+            let gk = &self.gk[capture];
+            for &c1 in cams {
+                let w1 = &self.w[&(c1, *capture)];
+                let dim1 = free[c1].len();
+                // y = W_c1 V^-1  (dim1 x 6)
+                let mut y = vec![0.0; dim1 * POSE_PARAMS];
+                for a in 0..dim1 {
+                    for j in 0..POSE_PARAMS {
+                        y[a * POSE_PARAMS + j] = (0..POSE_PARAMS)
+                            .map(|k| {
+                                w1[a * POSE_PARAMS + k]
+                                    * vi[k * POSE_PARAMS + j]
+                            })
+                            .sum();
+                    }
+                    b[offsets[c1] + a] += (0..POSE_PARAMS)
+                        .map(|j| y[a * POSE_PARAMS + j] * gk[j])
+                        .sum::<f64>();
+                }
+
+                for &c2 in cams {
+                    let w2 = &self.w[&(c2, *capture)];
+                    let dim2 = free[c2].len();
+                    for a in 0..dim1 {
+                        for bb in 0..dim2 {
+                            let value: f64 = (0..POSE_PARAMS)
+                                .map(|j| {
+                                    y[a * POSE_PARAMS + j]
+                                        * w2[bb * POSE_PARAMS + j]
+                                })
+                                .sum();
+                            s[(offsets[c1] + a) * camera_dim
+                                + offsets[c2]
+                                + bb] -= value;
+                        }
+                    }
+                }
+            }
+        }
+
+        solve_cholesky(&mut s, &mut b)?;
+        let delta_cameras = b;
+
+        // dk = V^-1 (-gk - W' dc)
+        let mut delta_boards = BTreeMap::new();
+        for (capture, vi) in &v_inv {
+            let mut rhs: BoardParams = self.gk[capture].map(|g| -g);
+            for &c in cameras_of_board.get(capture).into_iter().flatten() {
+                let w_ck = &self.w[&(c, *capture)];
+                for a in 0..free[c].len() {
+                    for j in 0..POSE_PARAMS {
+                        rhs[j] -= w_ck[a * POSE_PARAMS + j]
+                            * delta_cameras[offsets[c] + a];
+                    }
+                }
+            }
+            let delta: BoardParams = std::array::from_fn(|i| {
+                (0..POSE_PARAMS).map(|j| vi[i * POSE_PARAMS + j] * rhs[j]).sum()
+            });
+            delta_boards.insert(*capture, delta);
+        }
+
+        Some((delta_cameras, delta_boards))
+    }
+}
+
+fn central_difference(
+    plus: Option<[f64; 2]>,
+    minus: Option<[f64; 2]>,
+    h: f64,
+) -> [f64; 2] {
+    match (plus, minus) {
+        (Some(p), Some(m)) => {
+            [(p[0] - m[0]) / (2.0 * h), (p[1] - m[1]) / (2.0 * h)]
+        }
+        _ => [0.0, 0.0],
+    }
+}
+
+fn invert_spd(
+    a: &[f64; POSE_PARAMS * POSE_PARAMS],
+) -> Option<[f64; POSE_PARAMS * POSE_PARAMS]> {
+    let mut inv = [0.0; POSE_PARAMS * POSE_PARAMS];
+    for col in 0..POSE_PARAMS {
+        let mut m = *a;
+        let mut e = [0.0; POSE_PARAMS];
+        e[col] = 1.0;
+        solve_cholesky(&mut m, &mut e)?;
+        for row in 0..POSE_PARAMS {
+            inv[row * POSE_PARAMS + col] = e[row];
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_points() -> Vec<[f64; 3]> {
+        (0..4)
+            .flat_map(|y| {
+                (0..5).map(move |x| [x as f64 * 0.04, y as f64 * 0.04, 0.0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bundle_adjust_reduces_reprojection_error() {
+        let truth = Intrinsics {
+            fx: 800.0,
+            fy: 790.0,
+            cx: 320.0,
+            cy: 240.0,
+            dist: [-0.05, 0.01, 0.0, 0.0, 0.0],
+        };
+        let true_cameras = [
+            Pose::identity(),
+            Pose {
+                rotation: [0.0, -0.3, 0.02],
+                translation: [0.3, 0.0, 0.05],
+            },
+            Pose {
+                rotation: [0.05, 0.3, 0.0],
+                translation: [-0.3, 0.02, 0.05],
+            },
+        ];
+        let true_boards: BTreeMap<usize, Pose> = (0..6)
+            .map(|capture| {
+                let t = capture as f64;
+                let pose = Pose {
+                    rotation: [0.2 - 0.08 * t, 0.1 * t - 0.25, 0.05 * t],
+                    translation: [0.03 * t - 0.15, -0.08, 0.9 + 0.05 * t],
+                };
+                (capture, pose)
+            })
+            .collect();
+
+        let mut observations = Vec::new();
+        for (capture, board_to_world) in &true_boards {
+            for (camera, world_to_camera) in true_cameras.iter().enumerate() {
+                let board_to_camera = world_to_camera.compose(board_to_world);
+                let object_points = board_points();
+                let image_points = object_points
+                    .iter()
+                    .map(|x| truth.project(&board_to_camera.transform_point(x)))
+                    .collect::<Option<Vec<_>>>()
+                    .expect("board is in front of every camera");
+                observations.push(BoardObservation {
+                    camera,
+                    capture: *capture,
+                    corner_ids: (0..object_points.len() as i32).collect(),
+                    object_points,
+                    image_points,
+                    board_to_camera,
+                });
+            }
+        }
+
+        // 真値からずらした初期値から始める
+        let mut intrinsics = vec![
+            Intrinsics {
+                fx: 830.0,
+                fy: 770.0,
+                cx: 330.0,
+                cy: 232.0,
+                dist: [0.0; 5],
+            };
+            3
+        ];
+        let mut camera_poses: Vec<Option<Pose>> = true_cameras
+            .iter()
+            .enumerate()
+            .map(|(c, pose)| {
+                let mut pose = *pose;
+                if c != 0 {
+                    pose.rotation[1] += 0.02;
+                    pose.translation[0] -= 0.01;
+                }
+                Some(pose)
+            })
+            .collect();
+        let mut board_poses: BTreeMap<usize, Pose> = true_boards
+            .iter()
+            .map(|(capture, pose)| {
+                let mut pose = *pose;
+                pose.rotation[0] += 0.01;
+                pose.translation[2] += 0.02;
+                (*capture, pose)
+            })
+            .collect();
+
+        let report = bundle_adjust(
+            &observations,
+            &mut intrinsics,
+            &mut camera_poses,
+            &mut board_poses,
+            0,
+            &BundleAdjustmentOptions {
+                loss: RobustLoss::Squared,
+                ..Default::default()
+            },
+        );
+
+        assert!(report.initial_rms > 1.0, "{report:?}");
+        assert!(report.rms < 1e-3, "{report:?}");
+        assert!(
+            report
+                .per_camera_rms
+                .iter()
+                .all(|rms| rms.is_some_and(|r| r < 1e-3)),
+            "{report:?}"
+        );
+        for k in &intrinsics {
+            assert!((k.fx - truth.fx).abs() < 0.1, "{k:?}");
+            assert!((k.fy - truth.fy).abs() < 0.1, "{k:?}");
+            assert!((k.cx - truth.cx).abs() < 0.1, "{k:?}");
+            assert!((k.cy - truth.cy).abs() < 0.1, "{k:?}");
+        }
+    }
+}
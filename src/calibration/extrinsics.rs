@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow};
+use opencv::calib3d;
+use opencv::core::{
+    Mat, MatTraitConst, MatTraitConstManual, Point2f, Point3f, Vector,
+};
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::BoardTraitConst;
+
+use crate::CameraParameter;
+use crate::calibration::{
+    BundleAdjustmentOptions, Intrinsics, Pose, RobustLoss, average_rotations,
+    bundle_adjust, mat3_identity, vec3_add, vec3_scale,
+};
+
+// 平面ボードのPnPに最低限必要なコーナー数
+const MIN_CORNERS: usize = 4;
+
+/// ChArUco corners seen by one camera in one synchronized capture,
+/// together with the board pose estimated from them.
+#[derive(Debug, Clone)]
+pub struct BoardObservation {
+    pub camera: usize,
+    pub capture: usize,
+    pub corner_ids: Vec<i32>,
+    pub object_points: Vec<[f64; 3]>,
+    pub image_points: Vec<[f64; 2]>,
+    pub board_to_camera: Pose,
+}
+
+impl BoardObservation {
+    /// Runs PnP on detected ChArUco corners. Returns `None` when too few
+    /// corners were detected to estimate the board pose.
+    pub fn from_charuco(
+        camera: usize,
+        capture: usize,
+        board: &CharucoBoard,
+        camera_parameter: &CameraParameter,
+        charuco_corners: &Mat,
+        charuco_ids: &Mat,
+    ) -> Result<Option<Self>> {
+        if charuco_ids.empty() {
+            return Ok(None);
+        }
+
+        let mut obj_points = Vector::<Point3f>::new();
+        let mut img_points = Vector::<Point2f>::new();
+        board.match_image_points(
+            charuco_corners,
+            charuco_ids,
+            &mut obj_points,
+            &mut img_points,
+        )?;
+
+        if obj_points.len() < MIN_CORNERS {
+            return Ok(None);
+        }
+
+        let mut rvec = Mat::default();
+        let mut tvec = Mat::default();
+        let solved = calib3d::solve_pnp(
+            &obj_points,
+            &img_points,
+            &camera_parameter.camera_matrix,
+            &camera_parameter.dist_coeffs,
+            &mut rvec,
+            &mut tvec,
+            false,
+            calib3d::SOLVEPNP_ITERATIVE,
+        )?;
+        if !solved {
+            return Ok(None);
+        }
+
+        let r = rvec.data_typed::<f64>()?;
+        let t = tvec.data_typed::<f64>()?;
+
+        Ok(Some(Self {
+            camera,
+            capture,
+            corner_ids: charuco_ids.data_typed::<i32>()?.to_vec(),
+            object_points: obj_points
+                .iter()
+                .map(|p| [p.x as f64, p.y as f64, p.z as f64])
+                .collect(),
+            image_points: img_points
+                .iter()
+                .map(|p| [p.x as f64, p.y as f64])
+                .collect(),
+            board_to_camera: Pose {
+                rotation: [r[0], r[1], r[2]],
+                translation: [t[0], t[1], t[2]],
+            },
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtrinsicCalibration {
+    pub reference_camera: usize,
+    // world(=基準カメラの座標系)からカメラ座標系への変換。
+    // 他のカメラとボードを共有できなかったカメラはNone
+    pub camera_poses: Vec<Option<Pose>>,
+    // capture -> board->world
+    pub board_poses: BTreeMap<usize, Pose>,
+    pub initial_rms: f64,
+    pub rms: f64,
+    pub per_camera_rms: Vec<Option<f64>>,
+}
+
+/// Solves every camera's pose in the reference camera's frame.
+///
+/// Relative poses between camera pairs are averaged over all captures in
+/// which both cameras saw the board, chained outwards from the reference
+/// along the pairs with the most shared captures, and finally refined
+/// jointly with the board poses by minimizing the reprojection error.
+pub fn solve_extrinsics(
+    observations: &[BoardObservation],
+    intrinsics: &[Intrinsics],
+    reference: usize,
+) -> Result<ExtrinsicCalibration> {
+    let camera_count = intrinsics.len();
+    if reference >= camera_count {
+        return Err(anyhow!("reference camera {reference} does not exist"));
+    }
+    if let Some(obs) = observations.iter().find(|o| o.camera >= camera_count) {
+        return Err(anyhow!("observation refers to camera {}", obs.camera));
+    }
+
+    let mut by_capture: BTreeMap<usize, Vec<&BoardObservation>> =
+        BTreeMap::new();
+    for obs in observations {
+        by_capture.entry(obs.capture).or_default().push(obs);
+    }
+
+    // (a, b) -> bから見たaの姿勢 (a->b) のサンプル
+    let mut pair_samples: BTreeMap<(usize, usize), Vec<Pose>> = BTreeMap::new();
+    for views in by_capture.values() {
+        for a in views {
+            for b in views {
+                if a.camera == b.camera {
+                    continue;
+                }
+                pair_samples.entry((a.camera, b.camera)).or_default().push(
+                    b.board_to_camera.compose(&a.board_to_camera.inverse()),
+                );
+            }
+        }
+    }
+
+    let mut camera_poses: Vec<Option<Pose>> = vec![None; camera_count];
+    camera_poses[reference] = Some(Pose::identity());
+
+    // 共有キャプチャ数が最大の辺から順に基準カメラの座標系へ連結していく
+    loop {
+        let next = pair_samples
+            .iter()
+            .filter(|((a, b), _)| {
+                camera_poses[*a].is_some() && camera_poses[*b].is_none()
+            })
+            .max_by_key(|(_, samples)| samples.len());
+
+        let Some(((a, b), samples)) = next else {
+            break;
+        };
+
+        let a_to_b = average_poses(samples);
+        let world_to_a = camera_poses[*a].expect("filtered above");
+        camera_poses[*b] = Some(a_to_b.compose(&world_to_a));
+    }
+
+    if camera_poses.iter().filter(|p| p.is_some()).count() < 2 {
+        return Err(anyhow!(
+            "no camera shares a board view with the reference camera"
+        ));
+    }
+
+    let mut board_poses = initial_board_poses(observations, &camera_poses);
+
+    // 内部パラメータは固定してカメラ姿勢とボード姿勢だけを最適化する
+    let mut intrinsics = intrinsics.to_vec();
+    let report = bundle_adjust(
+        observations,
+        &mut intrinsics,
+        &mut camera_poses,
+        &mut board_poses,
+        reference,
+        &BundleAdjustmentOptions {
+            fix_intrinsics: true,
+            loss: RobustLoss::Squared,
+            ..Default::default()
+        },
+    );
+
+    Ok(ExtrinsicCalibration {
+        reference_camera: reference,
+        camera_poses,
+        board_poses,
+        initial_rms: report.initial_rms,
+        rms: report.rms,
+        per_camera_rms: report.per_camera_rms,
+    })
+}
+
+/// Board pose (board->world) of every capture, taken from the camera with
+/// a known pose that saw the most corners in that capture.
+pub fn initial_board_poses(
+    observations: &[BoardObservation],
+    camera_poses: &[Option<Pose>],
+) -> BTreeMap<usize, Pose> {
+    let mut best: BTreeMap<usize, &BoardObservation> = BTreeMap::new();
+    for obs in observations {
+        if camera_poses.get(obs.camera).is_none_or(Option::is_none) {
+            continue;
+        }
+        let entry = best.entry(obs.capture).or_insert(obs);
+        if obs.object_points.len() > entry.object_points.len() {
+            *entry = obs;
+        }
+    }
+
+    best.into_iter()
+        .map(|(capture, obs)| {
+            let world_to_camera = camera_poses[obs.camera].expect("filtered");
+            (
+                capture,
+                world_to_camera.inverse().compose(&obs.board_to_camera),
+            )
+        })
+        .collect()
+}
+
+fn average_poses(samples: &[Pose]) -> Pose {
+    let rotations: Vec<_> = samples.iter().map(Pose::rotation_matrix).collect();
+    let rotation = average_rotations(&rotations).unwrap_or_else(mat3_identity);
+    let translation =
+        samples.iter().fold([0.0; 3], |acc, p| vec3_add(&acc, &p.translation));
+
+    Pose::from_matrix(
+        &rotation,
+        vec3_scale(&translation, 1.0 / samples.len().max(1) as f64),
+    )
+}
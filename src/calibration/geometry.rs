@@ -0,0 +1,435 @@
+use anyhow::{Result, anyhow};
+use opencv::core::{CV_64F, Mat, MatTraitConst, MatTraitConstManual};
+use serde::{Deserialize, Serialize};
+
+use crate::CameraParameter;
+
+pub type Vec3 = [f64; 3];
+pub type Mat3 = [[f64; 3]; 3];
+
+pub fn mat3_identity() -> Mat3 {
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+}
+
+pub fn mat3_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    std::array::from_fn(|i| {
+        std::array::from_fn(|j| {
+            a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j]
+        })
+    })
+}
+
+pub fn mat3_transpose(a: &Mat3) -> Mat3 {
+    std::array::from_fn(|i| std::array::from_fn(|j| a[j][i]))
+}
+
+pub fn mat3_mul_vec(a: &Mat3, v: &Vec3) -> Vec3 {
+    [
+        a[0][0] * v[0] + a[0][1] * v[1] + a[0][2] * v[2],
+        a[1][0] * v[0] + a[1][1] * v[1] + a[1][2] * v[2],
+        a[2][0] * v[0] + a[2][1] * v[1] + a[2][2] * v[2],
+    ]
+}
+
+pub fn vec3_add(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn vec3_sub(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn vec3_scale(a: &Vec3, s: f64) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn vec3_norm(a: &Vec3) -> f64 {
+    (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt()
+}
+
+/// Rotation vector (axis * angle, as used by `cv::Rodrigues`) to matrix.
+pub fn rodrigues_to_matrix(r: &Vec3) -> Mat3 {
+    let theta = vec3_norm(r);
+    if theta < 1e-12 {
+        return [[1.0, -r[2], r[1]], [r[2], 1.0, -r[0]], [-r[1], r[0], 1.0]];
+    }
+
+    let k = vec3_scale(r, 1.0 / theta);
+    let (s, c) = theta.sin_cos();
+    let v = 1.0 - c;
+    [
+        [
+            c + k[0] * k[0] * v,
+            k[0] * k[1] * v - k[2] * s,
+            k[0] * k[2] * v + k[1] * s,
+        ],
+        [
+            k[1] * k[0] * v + k[2] * s,
+            c + k[1] * k[1] * v,
+            k[1] * k[2] * v - k[0] * s,
+        ],
+        [
+            k[2] * k[0] * v - k[1] * s,
+            k[2] * k[1] * v + k[0] * s,
+            c + k[2] * k[2] * v,
+        ],
+    ]
+}
+
+pub fn matrix_to_rodrigues(m: &Mat3) -> Vec3 {
+    let cos = (m[0][0] + m[1][1] + m[2][2] - 1.0) / 2.0;
+    let axis = [m[2][1] - m[1][2], m[0][2] - m[2][0], m[1][0] - m[0][1]];
+    let sin = vec3_norm(&axis) / 2.0;
+    // acosはθ≈πで精度が落ちるのでatan2で角度を求める
+    let theta = sin.atan2(cos);
+
+    if theta < 1e-8 {
+        return vec3_scale(&axis, 0.5);
+    }
+
+    if sin > 1e-6 {
+        return vec3_scale(&axis, theta / (2.0 * sin));
+    }
+
+    // θ≈πではR = 2kk^T - Iとなるので対角成分から回転軸を求める
+    let i = (0..3).max_by(|&a, &b| m[a][a].total_cmp(&m[b][b])).unwrap_or(0);
+    let ki = ((m[i][i] + 1.0) / 2.0).max(0.0).sqrt();
+    let k: Vec3 = std::array::from_fn(|j| {
+        if j == i {
+            ki
+        } else {
+            (m[i][j] + m[j][i]) / (4.0 * ki)
+        }
+    });
+    let dot = k[0] * axis[0] + k[1] * axis[1] + k[2] * axis[2];
+    let n = vec3_norm(&k) * if dot < 0.0 { -1.0 } else { 1.0 };
+    vec3_scale(&k, theta / n)
+}
+
+/// Rigid transform that maps points from a source frame into a target
+/// frame: `p_target = R * p_source + t`, with `R` stored as a rotation
+/// vector. Matches the `rvec`/`tvec` convention of `cv::solvePnP`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pose {
+    pub rotation: Vec3,
+    pub translation: Vec3,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Pose {
+    pub fn identity() -> Self {
+        Self {
+            rotation: [0.0; 3],
+            translation: [0.0; 3],
+        }
+    }
+
+    pub fn from_matrix(rotation: &Mat3, translation: Vec3) -> Self {
+        Self {
+            rotation: matrix_to_rodrigues(rotation),
+            translation,
+        }
+    }
+
+    pub fn rotation_matrix(&self) -> Mat3 {
+        rodrigues_to_matrix(&self.rotation)
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        vec3_add(&mat3_mul_vec(&self.rotation_matrix(), p), &self.translation)
+    }
+
+    /// `self ∘ other`: applies `other` first, then `self`.
+    pub fn compose(&self, other: &Pose) -> Pose {
+        let r = self.rotation_matrix();
+        Pose::from_matrix(
+            &mat3_mul(&r, &other.rotation_matrix()),
+            vec3_add(&mat3_mul_vec(&r, &other.translation), &self.translation),
+        )
+    }
+
+    pub fn inverse(&self) -> Pose {
+        let rt = mat3_transpose(&self.rotation_matrix());
+        Pose::from_matrix(
+            &rt,
+            vec3_scale(&mat3_mul_vec(&rt, &self.translation), -1.0),
+        )
+    }
+
+    /// Origin of the target frame expressed in the source frame, i.e. the
+    /// camera center in world coordinates when `self` is world->camera.
+    pub fn center(&self) -> Vec3 {
+        self.inverse().translation
+    }
+
+    pub fn to_params(&self) -> [f64; 6] {
+        [
+            self.rotation[0],
+            self.rotation[1],
+            self.rotation[2],
+            self.translation[0],
+            self.translation[1],
+            self.translation[2],
+        ]
+    }
+
+    pub fn from_params(p: &[f64]) -> Pose {
+        Pose {
+            rotation: [p[0], p[1], p[2]],
+            translation: [p[3], p[4], p[5]],
+        }
+    }
+}
+
+/// Karcher mean of rotations, computed in the tangent space of the
+/// running estimate.
+pub fn average_rotations(rotations: &[Mat3]) -> Option<Mat3> {
+    let mut mean = *rotations.first()?;
+    for _ in 0..20 {
+        let mut delta = [0.0; 3];
+        for r in rotations {
+            let d = matrix_to_rodrigues(&mat3_mul(&mat3_transpose(&mean), r));
+            delta = vec3_add(&delta, &d);
+        }
+        delta = vec3_scale(&delta, 1.0 / rotations.len() as f64);
+        mean = mat3_mul(&mean, &rodrigues_to_matrix(&delta));
+        if vec3_norm(&delta) < 1e-12 {
+            break;
+        }
+    }
+    Some(mean)
+}
+
+/// Pinhole intrinsics with OpenCV's 5 parameter distortion model
+/// (k1, k2, p1, p2, k3).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Intrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    pub dist: [f64; 5],
+}
+
+impl Intrinsics {
+    /// Projects a point in camera coordinates to pixels.
+    /// Returns `None` for points behind the camera.
+    pub fn project(&self, p: &Vec3) -> Option<[f64; 2]> {
+        if p[2] <= 1e-9 {
+            return None;
+        }
+
+        let (x, y) = (p[0] / p[2], p[1] / p[2]);
+        let [xd, yd] = self.distort(x, y);
+        Some([self.fx * xd + self.cx, self.fy * yd + self.cy])
+    }
+
+    fn distort(&self, x: f64, y: f64) -> [f64; 2] {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        [
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        ]
+    }
+
+    /// Removes lens distortion from a pixel and returns normalized image
+    /// coordinates (`z = 1`), iterating like `cv::undistortPoints`.
+    pub fn undistort(&self, uv: &[f64; 2]) -> [f64; 2] {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        let x0 = (uv[0] - self.cx) / self.fx;
+        let y0 = (uv[1] - self.cy) / self.fy;
+        let (mut x, mut y) = (x0, y0);
+        for _ in 0..20 {
+            let r2 = x * x + y * y;
+            let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+            let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+            let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+            let (nx, ny) = ((x0 - dx) / radial, (y0 - dy) / radial);
+            let converged = (nx - x).abs() + (ny - y).abs() < 1e-12;
+            (x, y) = (nx, ny);
+            if converged {
+                break;
+            }
+        }
+        [x, y]
+    }
+
+    pub fn to_params(&self) -> [f64; 9] {
+        let [k1, k2, p1, p2, k3] = self.dist;
+        [self.fx, self.fy, self.cx, self.cy, k1, k2, p1, p2, k3]
+    }
+
+    pub fn from_params(p: &[f64]) -> Intrinsics {
+        Intrinsics {
+            fx: p[0],
+            fy: p[1],
+            cx: p[2],
+            cy: p[3],
+            dist: [p[4], p[5], p[6], p[7], p[8]],
+        }
+    }
+}
+
+impl TryFrom<&CameraParameter> for Intrinsics {
+    type Error = anyhow::Error;
+
+    fn try_from(param: &CameraParameter) -> Result<Self> {
+        let mut k = Mat::default();
+        param.camera_matrix.convert_to_def(&mut k, CV_64F)?;
+        let mut d = Mat::default();
+        param.dist_coeffs.convert_to_def(&mut d, CV_64F)?;
+
+        let k = k.data_typed::<f64>()?;
+        if k.len() != 9 {
+            return Err(anyhow!("camera matrix must be 3x3"));
+        }
+
+        // OpenCVの歪み係数は4,5,8,12,14個のいずれか。先頭5つのみ使う
+        let mut dist = [0.0; 5];
+        for (dst, src) in dist.iter_mut().zip(d.data_typed::<f64>()?) {
+            *dst = *src;
+        }
+
+        Ok(Intrinsics {
+            fx: k[0],
+            fy: k[4],
+            cx: k[2],
+            cy: k[5],
+            dist,
+        })
+    }
+}
+
+impl TryFrom<&Intrinsics> for CameraParameter {
+    type Error = opencv::Error;
+
+    fn try_from(intrinsics: &Intrinsics) -> opencv::Result<Self> {
+        let k = [
+            intrinsics.fx,
+            0.0,
+            intrinsics.cx,
+            0.0,
+            intrinsics.fy,
+            intrinsics.cy,
+            0.0,
+            0.0,
+            1.0,
+        ];
+        Ok(CameraParameter {
+            camera_matrix: Mat::new_rows_cols_with_data(3, 3, &k[..])?
+                .try_clone()?,
+            dist_coeffs: Mat::new_rows_cols_with_data(
+                1,
+                5,
+                &intrinsics.dist[..],
+            )?
+            .try_clone()?,
+        })
+    }
+}
+
+/// Solves the symmetric positive definite system `a * x = b` in place
+/// with a Cholesky decomposition. `a` is row-major `n x n`.
+pub(crate) fn solve_cholesky(a: &mut [f64], b: &mut [f64]) -> Option<()> {
+    let n = b.len();
+
+    for j in 0..n {
+        let mut d = a[j * n + j];
+        for k in 0..j {
+            d -= a[j * n + k] * a[j * n + k];
+        }
+        if d.is_nan() || d <= 0.0 {
+            return None;
+        }
+        let d = d.sqrt();
+        a[j * n + j] = d;
+        for i in (j + 1)..n {
+            let mut s = a[i * n + j];
+            for k in 0..j {
+                s -= a[i * n + k] * a[j * n + k];
+            }
+            a[i * n + j] = s / d;
+        }
+    }
+
+    for i in 0..n {
+        let mut s = b[i];
+        for k in 0..i {
+            s -= a[i * n + k] * b[k];
+        }
+        b[i] = s / a[i * n + i];
+    }
+    for i in (0..n).rev() {
+        let mut s = b[i];
+        for k in (i + 1)..n {
+            s -= a[k * n + i] * b[k];
+        }
+        b[i] = s / a[i * n + i];
+    }
+
+    Some(())
+}
+
+/// Eigen decomposition of a symmetric row-major `n x n` matrix with the
+/// cyclic Jacobi method. Returns `(eigenvalue, eigenvector)` pairs sorted
+/// by ascending eigenvalue.
+pub(crate) fn symmetric_eigen(a: &[f64], n: usize) -> Vec<(f64, Vec<f64>)> {
+    let mut a = a.to_vec();
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+
+    for _ in 0..100 {
+        let off: f64 = (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        if off < 1e-30 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let apq = a[p * n + q];
+                if apq.abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum()
+                    / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut pairs: Vec<(f64, Vec<f64>)> = (0..n)
+        .map(|i| (a[i * n + i], (0..n).map(|k| v[k * n + i]).collect()))
+        .collect();
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    pairs
+}
@@ -0,0 +1,8 @@
+pub mod geometry;
+pub use geometry::*;
+
+pub mod extrinsics;
+pub use extrinsics::*;
+
+pub mod bundle;
+pub use bundle::*;
@@ -2,16 +2,66 @@ use anyhow::Result;
 use eframe::egui::{self, ColorImage};
 use opencv::core::{Mat, MatTraitConst, MatTraitConstManual};
 use serde::{Deserialize, Serialize};
-use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::watch;
 
-use crate::{VideoSource, VideoSourceConfig};
+use crate::{
+    Frame, FrameMeta, MmapStatusHandle, PlaybackHandle, SourceFrame, StreamId,
+    VideoSource, VideoSourceConfig, VideoSourceError,
+};
+
+// ライブソースからこれだけフレームが来なければ止まったとみなす
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+// 切断やエラーの後、読み直すまで待つ時間
+const ERROR_WAIT: Duration = Duration::from_millis(100);
+
+/// Health of a [`CameraStream`], as shown on its tab.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StreamState {
+    // まだ最初のフレームが来ていない
+    #[default]
+    Connecting,
+    Streaming,
+    // ライブソースからしばらくフレームが来ていない
+    Stalled,
+    Disconnected,
+    Error(String),
+}
+
+/// Reads the [`StreamState`] of a stream from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct StreamStatusHandle(Arc<Mutex<StreamState>>);
+
+impl StreamStatusHandle {
+    pub fn state(&self) -> StreamState {
+        self.0.lock().unwrap().clone()
+    }
+
+    // 状態が変わったときだけtrueを返す
+    fn set(&self, state: StreamState) -> bool {
+        let mut current = self.0.lock().unwrap();
+        if *current == state {
+            return false;
+        }
+        *current = state;
+        true
+    }
+}
 
 #[derive(Debug)]
 pub struct CameraStream {
     pub name: String,
-    r: watch::Receiver<Mat>,
+    pub id: StreamId,
+    r: watch::Receiver<Frame>,
     pub video_source_config: VideoSourceConfig,
+    pub playback: Option<PlaybackHandle>,
+    pub mmap_status: Option<MmapStatusHandle>,
+    pub status: StreamStatusHandle,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -22,31 +72,125 @@ pub struct CameraStreamConfig {
 
 impl CameraStream {
     pub fn new(name: String, config: VideoSourceConfig) -> Result<Self> {
-        let video_source_config = config.clone();
-        let mut vsrc = VideoSource::try_from(config)?;
-        let (s, r) = watch::channel(Mat::default());
-
-        // rustのthreadはjoin handleをとらなければ自動的にデタッチされ
-        // threadが終了した時点でリソースが解放される
-        // CameraStreamがdropされたらchannelが閉じられてfinishするのでメモリリークしないはず
-        let _ = thread::spawn(move || {
-            loop {
-                if let Ok(frame) = vsrc.read() {
-                    s.send(frame).expect("Failed to send frame");
-                }
-            }
-        });
+        let vsrc = VideoSource::try_from(config.clone())?;
+        // 開いたときに解決したデバイスの識別情報を保存する
+        let video_source_config = vsrc.capture_config().unwrap_or(config);
+        let playback = vsrc.playback_handle();
+        let mmap_status = vsrc.mmap_status_handle();
+        let id = StreamId::next();
+        let (s, r) = watch::channel(Frame::default());
+        let status = StreamStatusHandle::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let name = name.clone();
+            let status = status.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                run(&name, vsrc, id, &s, &status, &stop);
+            })
+        };
 
         Ok(Self {
             name,
+            id,
             r: r.clone(),
             video_source_config,
+            playback,
+            mmap_status,
+            status,
+            stop,
+            handle: Some(handle),
         })
     }
 
-    pub fn get_latest_image(&self) -> Mat {
+    /// Stops the capture thread and releases the source. Returns once the
+    /// device can be opened again.
+    pub fn close(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            eprintln!("Capture thread of {} panicked", self.name);
+        }
+    }
+
+    pub fn get_latest_frame(&self) -> Frame {
         self.r.borrow().clone()
     }
+
+    /// Receiver of every frame read from the source.
+    pub fn subscribe(&self) -> watch::Receiver<Frame> {
+        self.r.clone()
+    }
+}
+
+// キャプチャスレッドの本体。sourceはここでdropされるので、
+// joinが返った時点でデバイスを開き直せる
+fn run(
+    name: &str,
+    mut source: VideoSource,
+    id: StreamId,
+    s: &watch::Sender<Frame>,
+    status: &StreamStatusHandle,
+    stop: &AtomicBool,
+) {
+    let live = source.is_live();
+    let mut sequence = 0;
+    let mut last_frame = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        let err = match source.read() {
+            Ok(SourceFrame {
+                image,
+                ground_truth,
+                recorded_at,
+            }) => {
+                sequence += 1;
+                let meta = FrameMeta {
+                    stream_id: id,
+                    sequence,
+                    captured_at: Instant::now(),
+                    wall_clock: SystemTime::now(),
+                    recorded_at,
+                };
+                s.send_replace(Frame {
+                    image,
+                    meta,
+                    ground_truth,
+                });
+                last_frame = meta.captured_at;
+                status.set(StreamState::Streaming);
+                continue;
+            }
+            Err(VideoSourceError::NotReady) => {
+                if live
+                    && last_frame.elapsed() > STALL_TIMEOUT
+                    && status.state() == StreamState::Streaming
+                {
+                    status.set(StreamState::Stalled);
+                }
+                continue;
+            }
+            Err(err) => err,
+        };
+
+        let state = match &err {
+            VideoSourceError::Disconnected(_) => StreamState::Disconnected,
+            err => StreamState::Error(err.to_string()),
+        };
+        // 同じエラーが続く間は1度だけ出す
+        if status.set(state) {
+            eprintln!("Camera {name}: {err}");
+        }
+        thread::sleep(ERROR_WAIT);
+    }
+}
+
+impl Drop for CameraStream {
+    fn drop(&mut self) {
+        self.close();
+    }
 }
 
 impl TryFrom<CameraStreamConfig> for CameraStream {
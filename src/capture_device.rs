@@ -0,0 +1,516 @@
+#[cfg(target_os = "linux")]
+use std::collections::BTreeMap;
+#[cfg(target_os = "linux")]
+use std::fs::{self, File};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+use opencv::prelude::*;
+use opencv::videoio::{self, VideoCapture};
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "linux")]
+const V4L2_SYSFS: &str = "/sys/class/video4linux";
+#[cfg(target_os = "linux")]
+const V4L2_BY_ID: &str = "/dev/v4l/by-id";
+#[cfg(target_os = "linux")]
+const V4L2_BY_PATH: &str = "/dev/v4l/by-path";
+// これ以上のランクは同じ機種の別の個体にも当たりうる
+#[cfg(target_os = "linux")]
+const WEAK_MATCH: u8 = 3;
+
+// linux/videodev2.h
+// _IOR('V', 0, struct v4l2_capability)
+#[cfg(target_os = "linux")]
+const VIDIOC_QUERYCAP: libc::c_ulong = 0x8068_5600;
+#[cfg(target_os = "linux")]
+const V4L2_CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+#[cfg(target_os = "linux")]
+const V4L2_CAP_VIDEO_CAPTURE_MPLANE: u32 = 0x0000_1000;
+#[cfg(target_os = "linux")]
+const V4L2_CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+
+/// Identifies a physical capture device independently of the OpenCV index
+/// it happens to get, which changes between boots, backends and when
+/// devices are plugged in or out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptureDeviceId {
+    pub name: String,
+    // USBポートなどの接続先。同じ名前のカメラを区別する
+    #[serde(default)]
+    pub bus_path: Option<String>,
+    // /dev/v4l/by-id のリンク。シリアル番号を含むので挿し直しても変わらない
+    #[serde(default)]
+    pub by_id: Option<String>,
+    // /dev/v4l/by-path のリンク。同じポートに挿す限り変わらない
+    #[serde(default)]
+    pub by_path: Option<String>,
+    // USBのidVendor, idProduct (16進)
+    #[serde(default)]
+    pub vendor_id: Option<String>,
+    #[serde(default)]
+    pub product_id: Option<String>,
+    #[serde(default)]
+    pub serial: Option<String>,
+}
+
+/// A camera found by [`enumerate_capture_devices`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureDeviceInfo {
+    // OpenCVに渡す番号
+    pub index: i32,
+    pub name: String,
+    // ドライバが報告する接続先 (usb-0000:00:14.0-2 など)
+    pub bus_info: Option<String>,
+    // 映像を取り込めるノードか。問い合わせられなければNone
+    pub can_capture: Option<bool>,
+    pub id: Option<CaptureDeviceId>,
+}
+
+/// Lists the V4L2 devices under `/dev/video*` with their names from sysfs
+/// and capabilities queried from the driver, without OpenCV or Python.
+/// Returns an empty list on other platforms.
+pub fn enumerate_capture_devices() -> Vec<CaptureDeviceInfo> {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(entries) = fs::read_dir("/dev") else {
+            return Vec::new();
+        };
+        let mut numbers: Vec<u32> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix("video")?.parse().ok()
+            })
+            .collect();
+        numbers.sort();
+
+        let links = v4l2_links();
+        numbers
+            .into_iter()
+            .map(|number| {
+                let node = v4l2_node(number, &links);
+                let caps =
+                    query_capability(Path::new(&format!("/dev/video{number}")));
+                let name = node
+                    .as_ref()
+                    .map(|node| node.id.name.clone())
+                    .or_else(|| caps.as_ref().map(|caps| caps.card.clone()))
+                    .unwrap_or_else(|| format!("/dev/video{number}"));
+                CaptureDeviceInfo {
+                    // OpenCVのCAP_V4L2で開く番号
+                    index: videoio::CAP_V4L2 + number as i32,
+                    name,
+                    bus_info: caps.as_ref().map(|caps| caps.bus_info.clone()),
+                    can_capture: caps.map(|caps| caps.can_capture),
+                    id: node.map(|node| node.id),
+                }
+            })
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Vec::new()
+    }
+}
+
+// 列挙する手段が無いときに開いて確かめる番号の数
+const MAX_PROBED_CAMERAS: i32 = 8;
+
+/// Which enumerator listed the cameras in a [`CameraList`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraEnumerator {
+    // /dev/video* とsysfs
+    V4l2,
+    // 埋め込みPythonのcv2_enumerate_cameras (pythonフィーチャー)
+    Python,
+    // 番号を順に開いてみる。名前は分からない
+    OpenCvProbe,
+}
+
+impl CameraEnumerator {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CameraEnumerator::V4l2 => "V4L2 (sysfs)",
+            CameraEnumerator::Python => "Python (cv2_enumerate_cameras)",
+            CameraEnumerator::OpenCvProbe => "OpenCV index probe",
+        }
+    }
+}
+
+/// Cameras as `(OpenCV index, label)` and the enumerator that found them.
+#[derive(Clone, Debug)]
+pub struct CameraList {
+    pub enumerator: CameraEnumerator,
+    pub cameras: Vec<(i32, String)>,
+}
+
+/// Lists cameras with the best enumerator of this platform and build: V4L2
+/// on Linux, then Python when the `python` feature is enabled, and
+/// otherwise by opening the first few OpenCV indices.
+pub fn list_cameras() -> CameraList {
+    #[cfg(target_os = "linux")]
+    {
+        let cameras = enumerate_capture_devices()
+            .into_iter()
+            // メタデータ用のノードなどは開いても映らない
+            .filter(|device| device.can_capture != Some(false))
+            .map(|device| {
+                let label = match &device.bus_info {
+                    Some(bus) => format!("{} ({bus})", device.name),
+                    None => device.name,
+                };
+                (device.index, label)
+            })
+            .collect();
+        CameraList {
+            enumerator: CameraEnumerator::V4l2,
+            cameras,
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        #[cfg(feature = "python")]
+        match crate::enumerate_cameras() {
+            Ok(cameras) => {
+                return CameraList {
+                    enumerator: CameraEnumerator::Python,
+                    cameras,
+                };
+            }
+            Err(err) => eprintln!("Failed to enumerate cameras: {err}"),
+        }
+        CameraList {
+            enumerator: CameraEnumerator::OpenCvProbe,
+            cameras: probe_cameras(),
+        }
+    }
+}
+
+/// Opens OpenCV indices `0..8` and lists those that open. Cameras already
+/// in use may not open and are then missing.
+pub fn probe_cameras() -> Vec<(i32, String)> {
+    (0..MAX_PROBED_CAMERAS)
+        .filter(|index| {
+            VideoCapture::new(*index, videoio::CAP_ANY)
+                .and_then(|mut vcap| {
+                    let opened = vcap.is_opened()?;
+                    vcap.release()?;
+                    Ok(opened)
+                })
+                .unwrap_or(false)
+        })
+        .map(|index| (index, format!("Camera {index}")))
+        .collect()
+}
+
+/// Splits an OpenCV capture index into its backend (`CAP_*`, a multiple of
+/// 100) and the device number within that backend.
+pub fn split_capture_index(index: i32) -> (i32, i32) {
+    (index - index % 100, index % 100)
+}
+
+impl CaptureDeviceId {
+    /// Whether devices can be identified on this platform. Elsewhere
+    /// stored indices are used as they are.
+    pub fn supported() -> bool {
+        cfg!(target_os = "linux")
+    }
+
+    /// Identifies the device currently at OpenCV `index`. Only V4L2
+    /// devices on Linux can be identified.
+    pub fn of_index(index: i32) -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let (_, number) = split_capture_index(index);
+            v4l2_node(number as u32, &v4l2_links()).map(|node| node.id)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = index;
+            None
+        }
+    }
+
+    /// The OpenCV index the device has now, keeping the backend of
+    /// `index`. Falls back to weaker matches (same port, same name) when
+    /// the device was moved, as long as they are unambiguous.
+    pub fn find_index(&self, index: i32) -> Option<i32> {
+        #[cfg(target_os = "linux")]
+        {
+            let (backend, _) = split_capture_index(index);
+            let nodes = v4l2_nodes();
+            let ranked: Vec<(u8, &V4l2Node)> = nodes
+                .iter()
+                .filter_map(|node| Some((self.match_rank(&node.id)?, node)))
+                .collect();
+            let best = ranked.iter().map(|(rank, _)| *rank).min()?;
+            let candidates: Vec<&V4l2Node> = ranked
+                .into_iter()
+                .filter(|(rank, _)| *rank == best)
+                .map(|(_, node)| node)
+                .collect();
+            // 弱い一致で別々のデバイスが複数当たったら決められない
+            if best >= WEAK_MATCH
+                && candidates
+                    .iter()
+                    .any(|node| node.id.bus_path != candidates[0].id.bus_path)
+            {
+                return None;
+            }
+            // 1台のカメラが複数のノード(メタデータ用など)を持つので
+            // デバイス内で最初のノードを使う
+            candidates
+                .into_iter()
+                .min_by_key(|node| (node.node_index, node.number))
+                .map(|node| backend + node.number as i32)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = index;
+            None
+        }
+    }
+
+    /// Whether both ids describe the same physical device. `None` when
+    /// neither carries more than a name, which does not tell identical
+    /// models apart.
+    pub fn same_device(&self, other: &Self) -> Option<bool> {
+        if let (Some(a), Some(b)) = (&self.by_id, &other.by_id) {
+            return Some(a == b);
+        }
+        if self.serial.is_some() && other.serial.is_some() {
+            return Some(self.usb_id() == other.usb_id());
+        }
+        if let (Some(a), Some(b)) = (&self.bus_path, &other.bus_path) {
+            return Some(a == b);
+        }
+        None
+    }
+
+    fn usb_id(&self) -> (&Option<String>, &Option<String>, &Option<String>) {
+        (&self.vendor_id, &self.product_id, &self.serial)
+    }
+
+    // 小さいほど確かな一致。一致しなければNone
+    #[cfg(target_os = "linux")]
+    fn match_rank(&self, other: &Self) -> Option<u8> {
+        let same =
+            |a: &Option<String>, b: &Option<String>| a.is_some() && a == b;
+        // 別の機種だと分かっていれば一致させない
+        let other_model = (self.vendor_id.is_some()
+            && other.vendor_id.is_some())
+            && (self.vendor_id != other.vendor_id
+                || self.product_id != other.product_id);
+
+        if same(&self.by_id, &other.by_id) {
+            Some(0)
+        } else if other_model {
+            None
+        } else if same(&self.serial, &other.serial) {
+            Some(1)
+        } else if same(&self.bus_path, &other.bus_path)
+            && self.name == other.name
+        {
+            Some(2)
+        } else if same(&self.by_path, &other.by_path) {
+            Some(WEAK_MATCH)
+        } else if self.name == other.name {
+            Some(WEAK_MATCH + 1)
+        } else {
+            None
+        }
+    }
+}
+
+// /dev/videoN 1つ分
+#[cfg(target_os = "linux")]
+struct V4l2Node {
+    number: u32,
+    id: CaptureDeviceId,
+    // 同じデバイスのノードの中での番号
+    node_index: u32,
+}
+
+// by-id, by-pathのリンク。デバイスノード -> (by-id, by-path)
+#[cfg(target_os = "linux")]
+type V4l2Links = BTreeMap<PathBuf, (Option<String>, Option<String>)>;
+
+#[cfg(target_os = "linux")]
+fn v4l2_links() -> V4l2Links {
+    let mut links = V4l2Links::new();
+    for (dir, by_id) in [(V4L2_BY_ID, true), (V4L2_BY_PATH, false)] {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let link = entry.path();
+            let Ok(target) = fs::canonicalize(&link) else {
+                continue;
+            };
+            let link = Some(link.to_string_lossy().into_owned());
+            let slot = links.entry(target).or_default();
+            if by_id {
+                slot.0 = link;
+            } else {
+                slot.1 = link;
+            }
+        }
+    }
+    links
+}
+
+#[cfg(target_os = "linux")]
+fn v4l2_node(number: u32, links: &V4l2Links) -> Option<V4l2Node> {
+    let dir = Path::new(V4L2_SYSFS).join(format!("video{number}"));
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .ok()
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+    };
+    let name = read(&dir.join("name"))?;
+    let device = fs::canonicalize(dir.join("device")).ok();
+    let node_index =
+        read(&dir.join("index")).and_then(|s| s.parse().ok()).unwrap_or(0);
+
+    // deviceはUSBインターフェースを指すので、idVendorなどはその親にある
+    let usb = device.as_ref().and_then(|device| {
+        [device.as_path(), device.parent()?]
+            .into_iter()
+            .find(|dir| dir.join("idVendor").exists())
+            .map(Path::to_path_buf)
+    });
+    let usb_attr =
+        |file: &str| usb.as_ref().and_then(|usb| read(&usb.join(file)));
+
+    let (by_id, by_path) = links
+        .get(Path::new(&format!("/dev/video{number}")))
+        .cloned()
+        .unwrap_or_default();
+
+    Some(V4l2Node {
+        number,
+        id: CaptureDeviceId {
+            name,
+            bus_path: device.map(|path| path.to_string_lossy().into_owned()),
+            by_id,
+            by_path,
+            vendor_id: usb_attr("idVendor"),
+            product_id: usb_attr("idProduct"),
+            serial: usb_attr("serial"),
+        },
+        node_index,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn v4l2_nodes() -> Vec<V4l2Node> {
+    let Ok(entries) = fs::read_dir(V4L2_SYSFS) else {
+        return Vec::new();
+    };
+    let links = v4l2_links();
+    let mut nodes: Vec<V4l2Node> = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            let number = name.to_str()?.strip_prefix("video")?.parse().ok()?;
+            v4l2_node(number, &links)
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.number);
+    nodes
+}
+
+// struct v4l2_capability。使わないフィールドもレイアウトのために要る
+#[cfg(target_os = "linux")]
+#[allow(dead_code)]
+#[repr(C)]
+struct V4l2Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+#[cfg(target_os = "linux")]
+struct Capability {
+    card: String,
+    bus_info: String,
+    can_capture: bool,
+}
+
+// 開くだけではストリームは始まらないので、使用中のカメラにも問い合わせられる
+#[cfg(target_os = "linux")]
+fn query_capability(path: &Path) -> Option<Capability> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .ok()?;
+    let mut cap = V4l2Capability {
+        driver: [0; 16],
+        card: [0; 32],
+        bus_info: [0; 32],
+        version: 0,
+        capabilities: 0,
+        device_caps: 0,
+        reserved: [0; 3],
+    };
+    // ioctlのrequestはglibcではc_ulong、muslではc_int
+    #[cfg(target_env = "musl")]
+    let request = VIDIOC_QUERYCAP as libc::c_int;
+    #[cfg(not(target_env = "musl"))]
+    let request = VIDIOC_QUERYCAP;
+    // Safety: capはVIDIOC_QUERYCAPが書き込むstruct v4l2_capabilityと
+    // 同じレイアウトで、呼び出しの間fileは開いたまま
+    let result = unsafe {
+        libc::ioctl(file.as_raw_fd(), request, &mut cap as *mut V4l2Capability)
+    };
+    if result < 0 {
+        return None;
+    }
+
+    // device_capsがあればこのノード自体の能力を表す
+    let caps = if cap.capabilities & V4L2_CAP_DEVICE_CAPS != 0 {
+        cap.device_caps
+    } else {
+        cap.capabilities
+    };
+    let text = |bytes: &[u8]| {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    Some(Capability {
+        card: text(&cap.card),
+        bus_info: text(&cap.bus_info),
+        can_capture: caps
+            & (V4L2_CAP_VIDEO_CAPTURE | V4L2_CAP_VIDEO_CAPTURE_MPLANE)
+            != 0,
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn querycap_request_matches_videodev2() {
+        // struct v4l2_capabilityは104バイト
+        let size = std::mem::size_of::<V4l2Capability>();
+        assert_eq!(size, 104);
+
+        // _IOR(type, nr, size) = (READ << 30) | (size << 16) | (type << 8) | nr
+        const IOC_READ: libc::c_ulong = 2;
+        let request = (IOC_READ << 30)
+            | ((size as libc::c_ulong) << 16)
+            | ((b'V' as libc::c_ulong) << 8);
+        assert_eq!(VIDIOC_QUERYCAP, request);
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{Result, anyhow};
+use opencv::core::Size;
+use opencv::objdetect::{
+    CharucoBoard, PredefinedDictionaryType, get_predefined_dictionary,
+};
+use opencv::prelude::CharucoBoardTrait;
+use serde::{Deserialize, Serialize};
+
+/// ArUco dictionaries usable for ChArUco boards.
+/// Variant names follow OpenCV's `PredefinedDictionaryType` so that
+/// saved configs read the same as the OpenCV documentation.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArucoDictionary {
+    DICT_4X4_50,
+    DICT_4X4_100,
+    DICT_4X4_250,
+    DICT_4X4_1000,
+    DICT_5X5_50,
+    DICT_5X5_100,
+    DICT_5X5_250,
+    DICT_5X5_1000,
+    DICT_6X6_50,
+    DICT_6X6_100,
+    DICT_6X6_250,
+    DICT_6X6_1000,
+    DICT_7X7_50,
+    DICT_7X7_100,
+    DICT_7X7_250,
+    DICT_7X7_1000,
+    DICT_ARUCO_ORIGINAL,
+}
+
+impl ArucoDictionary {
+    pub const ALL: [ArucoDictionary; 17] = [
+        Self::DICT_4X4_50,
+        Self::DICT_4X4_100,
+        Self::DICT_4X4_250,
+        Self::DICT_4X4_1000,
+        Self::DICT_5X5_50,
+        Self::DICT_5X5_100,
+        Self::DICT_5X5_250,
+        Self::DICT_5X5_1000,
+        Self::DICT_6X6_50,
+        Self::DICT_6X6_100,
+        Self::DICT_6X6_250,
+        Self::DICT_6X6_1000,
+        Self::DICT_7X7_50,
+        Self::DICT_7X7_100,
+        Self::DICT_7X7_250,
+        Self::DICT_7X7_1000,
+        Self::DICT_ARUCO_ORIGINAL,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DICT_4X4_50 => "DICT_4X4_50",
+            Self::DICT_4X4_100 => "DICT_4X4_100",
+            Self::DICT_4X4_250 => "DICT_4X4_250",
+            Self::DICT_4X4_1000 => "DICT_4X4_1000",
+            Self::DICT_5X5_50 => "DICT_5X5_50",
+            Self::DICT_5X5_100 => "DICT_5X5_100",
+            Self::DICT_5X5_250 => "DICT_5X5_250",
+            Self::DICT_5X5_1000 => "DICT_5X5_1000",
+            Self::DICT_6X6_50 => "DICT_6X6_50",
+            Self::DICT_6X6_100 => "DICT_6X6_100",
+            Self::DICT_6X6_250 => "DICT_6X6_250",
+            Self::DICT_6X6_1000 => "DICT_6X6_1000",
+            Self::DICT_7X7_50 => "DICT_7X7_50",
+            Self::DICT_7X7_100 => "DICT_7X7_100",
+            Self::DICT_7X7_250 => "DICT_7X7_250",
+            Self::DICT_7X7_1000 => "DICT_7X7_1000",
+            Self::DICT_ARUCO_ORIGINAL => "DICT_ARUCO_ORIGINAL",
+        }
+    }
+
+    /// Number of distinct marker ids available in the dictionary.
+    pub fn marker_count(&self) -> i32 {
+        match self {
+            Self::DICT_4X4_50
+            | Self::DICT_5X5_50
+            | Self::DICT_6X6_50
+            | Self::DICT_7X7_50 => 50,
+            Self::DICT_4X4_100
+            | Self::DICT_5X5_100
+            | Self::DICT_6X6_100
+            | Self::DICT_7X7_100 => 100,
+            Self::DICT_4X4_250
+            | Self::DICT_5X5_250
+            | Self::DICT_6X6_250
+            | Self::DICT_7X7_250 => 250,
+            Self::DICT_4X4_1000
+            | Self::DICT_5X5_1000
+            | Self::DICT_6X6_1000
+            | Self::DICT_7X7_1000 => 1000,
+            Self::DICT_ARUCO_ORIGINAL => 1024,
+        }
+    }
+
+    fn predefined_type(&self) -> PredefinedDictionaryType {
+        match self {
+            Self::DICT_4X4_50 => PredefinedDictionaryType::DICT_4X4_50,
+            Self::DICT_4X4_100 => PredefinedDictionaryType::DICT_4X4_100,
+            Self::DICT_4X4_250 => PredefinedDictionaryType::DICT_4X4_250,
+            Self::DICT_4X4_1000 => PredefinedDictionaryType::DICT_4X4_1000,
+            Self::DICT_5X5_50 => PredefinedDictionaryType::DICT_5X5_50,
+            Self::DICT_5X5_100 => PredefinedDictionaryType::DICT_5X5_100,
+            Self::DICT_5X5_250 => PredefinedDictionaryType::DICT_5X5_250,
+            Self::DICT_5X5_1000 => PredefinedDictionaryType::DICT_5X5_1000,
+            Self::DICT_6X6_50 => PredefinedDictionaryType::DICT_6X6_50,
+            Self::DICT_6X6_100 => PredefinedDictionaryType::DICT_6X6_100,
+            Self::DICT_6X6_250 => PredefinedDictionaryType::DICT_6X6_250,
+            Self::DICT_6X6_1000 => PredefinedDictionaryType::DICT_6X6_1000,
+            Self::DICT_7X7_50 => PredefinedDictionaryType::DICT_7X7_50,
+            Self::DICT_7X7_100 => PredefinedDictionaryType::DICT_7X7_100,
+            Self::DICT_7X7_250 => PredefinedDictionaryType::DICT_7X7_250,
+            Self::DICT_7X7_1000 => PredefinedDictionaryType::DICT_7X7_1000,
+            Self::DICT_ARUCO_ORIGINAL => {
+                PredefinedDictionaryType::DICT_ARUCO_ORIGINAL
+            }
+        }
+    }
+}
+
+/// Physical definition of a ChArUco board. Lengths are in meters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CharucoBoardConfig {
+    pub squares_x: i32,
+    pub squares_y: i32,
+    pub square_length: f32,
+    pub marker_length: f32,
+    pub dictionary: ArucoDictionary,
+    // OpenCV 4.6以前で生成したボード(偶数行の場合に左上が白マスから始まる)
+    // を使う場合はtrueにする
+    pub legacy_pattern: bool,
+}
+
+impl Default for CharucoBoardConfig {
+    fn default() -> Self {
+        Self {
+            squares_x: 3,
+            squares_y: 3,
+            square_length: 0.3,
+            marker_length: 0.15,
+            dictionary: ArucoDictionary::DICT_6X6_250,
+            legacy_pattern: false,
+        }
+    }
+}
+
+impl CharucoBoardConfig {
+    /// Number of markers printed on the board.
+    pub fn marker_count(&self) -> i32 {
+        (self.squares_x * self.squares_y) / 2
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.squares_x < 2 || self.squares_y < 2 {
+            return Err(anyhow!(
+                "board needs at least 2x2 squares, got {}x{}",
+                self.squares_x,
+                self.squares_y
+            ));
+        }
+
+        if self.square_length <= 0.0 || self.marker_length <= 0.0 {
+            return Err(anyhow!("square and marker lengths must be positive"));
+        }
+
+        if self.marker_length >= self.square_length {
+            return Err(anyhow!(
+                "marker length ({} m) must be smaller than square length ({} m)",
+                self.marker_length,
+                self.square_length
+            ));
+        }
+
+        if self.marker_count() > self.dictionary.marker_count() {
+            return Err(anyhow!(
+                "{} has only {} markers but the board needs {}",
+                self.dictionary.name(),
+                self.dictionary.marker_count(),
+                self.marker_count()
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<CharucoBoard> {
+        self.validate()?;
+
+        let dictionary =
+            get_predefined_dictionary(self.dictionary.predefined_type())?;
+        let mut board = CharucoBoard::new_def(
+            Size::new(self.squares_x, self.squares_y),
+            self.square_length,
+            self.marker_length,
+            &dictionary,
+        )?;
+        board.set_legacy_pattern(self.legacy_pattern)?;
+
+        Ok(board)
+    }
+}
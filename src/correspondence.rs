@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::calibration::{
+    Mat3, Vec3, mat3_mul, mat3_mul_vec, mat3_transpose, vec3_sub,
+};
+use crate::{
+    CalibratedCamera, PointObservation, TriangulationOptions, triangulate,
+};
+
+/// Fundamental matrix between two calibrated cameras, such that
+/// `x_toᵀ F x_from = 0` for undistorted homogeneous pixels.
+pub fn fundamental_matrix(
+    from: &CalibratedCamera,
+    to: &CalibratedCamera,
+) -> Mat3 {
+    // from -> to の相対姿勢
+    let r_from = from.pose.rotation_matrix();
+    let r_to = to.pose.rotation_matrix();
+    let r = mat3_mul(&r_to, &mat3_transpose(&r_from));
+    let t = vec3_sub(
+        &to.pose.translation,
+        &mat3_mul_vec(&r, &from.pose.translation),
+    );
+
+    let t_cross = [[0.0, -t[2], t[1]], [t[2], 0.0, -t[0]], [-t[1], t[0], 0.0]];
+    let essential = mat3_mul(&t_cross, &r);
+
+    let k_inv = |c: &CalibratedCamera| {
+        let i = &c.intrinsics;
+        [
+            [1.0 / i.fx, 0.0, -i.cx / i.fx],
+            [0.0, 1.0 / i.fy, -i.cy / i.fy],
+            [0.0, 0.0, 1.0],
+        ]
+    };
+    mat3_mul(
+        &mat3_mul(&mat3_transpose(&k_inv(to)), &essential),
+        &k_inv(from),
+    )
+}
+
+/// Symmetric epipolar distance in pixels: the mean distance of each point
+/// to the epipolar line of the other.
+pub fn epipolar_distance(f: &Mat3, x_from: &Vec3, x_to: &Vec3) -> f64 {
+    let line_to = mat3_mul_vec(f, x_from);
+    let line_from = mat3_mul_vec(&mat3_transpose(f), x_to);
+    let e = x_to[0] * line_to[0] + x_to[1] * line_to[1] + x_to[2] * line_to[2];
+
+    let d_to = e.abs() / line_to[0].hypot(line_to[1]).max(1e-12);
+    let d_from = e.abs() / line_from[0].hypot(line_from[1]).max(1e-12);
+    (d_to + d_from) / 2.0
+}
+
+// 歪みを除去したピンホールモデル上の画素(同次座標)
+fn ideal_pixel(camera: &CalibratedCamera, uv: &[f64; 2]) -> Vec3 {
+    let [x, y] = camera.intrinsics.undistort(uv);
+    let i = &camera.intrinsics;
+    [i.fx * x + i.cx, i.fy * y + i.cy, 1.0]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorrespondenceOptions {
+    // エピポーラ線・再投影点からこの距離 [px] 以内を同じマーカーとみなす
+    pub max_distance: f64,
+    pub triangulation: TriangulationOptions,
+}
+
+impl Default for CorrespondenceOptions {
+    fn default() -> Self {
+        Self {
+            max_distance: 3.0,
+            triangulation: TriangulationOptions::default(),
+        }
+    }
+}
+
+/// One camera's detection that contributed to a marker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarkerView {
+    pub camera: usize,
+    // detections[camera]内の添字
+    pub detection: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabeledMarker {
+    // フレーム内での通し番号。フレームをまたいだ同一性は保証しない
+    pub label: usize,
+    pub position: Vec3,
+    pub views: Vec<MarkerView>,
+    pub rms: f64,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkerCloud {
+    pub markers: Vec<LabeledMarker>,
+}
+
+struct Hypothesis {
+    views: Vec<MarkerView>,
+    error: f64,
+}
+
+/// Matches unlabeled 2D detections (`detections[camera]`) across
+/// calibrated cameras and triangulates them into a labeled marker cloud.
+///
+/// Every pair of detections from two cameras within `max_distance` of each
+/// other's epipolar line seeds a hypothesis, which collects the closest
+/// reprojected detection from every other camera. Hypotheses are accepted
+/// greedily, most views and lowest error first, without reusing a
+/// detection. A two-view match is only accepted when neither detection had
+/// a competing epipolar candidate, since nothing else can tell the true
+/// match from a ghost.
+pub fn match_markers(
+    cameras: &[CalibratedCamera],
+    detections: &[Vec<[f64; 2]>],
+    options: &CorrespondenceOptions,
+) -> MarkerCloud {
+    let camera_count = cameras.len().min(detections.len());
+    let ideal: Vec<Vec<Vec3>> = (0..camera_count)
+        .map(|c| {
+            detections[c]
+                .iter()
+                .map(|uv| ideal_pixel(&cameras[c], uv))
+                .collect()
+        })
+        .collect();
+
+    let observation = |view: &MarkerView| PointObservation {
+        camera: view.camera,
+        point: detections[view.camera][view.detection],
+    };
+    let seed_options = TriangulationOptions {
+        refine: false,
+        inlier_threshold: options.max_distance,
+        min_views: 2,
+    };
+
+    let mut hypotheses = Vec::new();
+    // ((camera, detection), 相手のcamera) -> エピポーラ線上の候補数
+    let mut candidate_counts: BTreeMap<((usize, usize), usize), usize> =
+        BTreeMap::new();
+
+    for a in 0..camera_count {
+        for b in (a + 1)..camera_count {
+            let f = fundamental_matrix(&cameras[a], &cameras[b]);
+            for (i, xa) in ideal[a].iter().enumerate() {
+                for (j, xb) in ideal[b].iter().enumerate() {
+                    if epipolar_distance(&f, xa, xb) > options.max_distance {
+                        continue;
+                    }
+                    *candidate_counts.entry(((a, i), b)).or_default() += 1;
+                    *candidate_counts.entry(((b, j), a)).or_default() += 1;
+
+                    let seed = [
+                        MarkerView {
+                            camera: a,
+                            detection: i,
+                        },
+                        MarkerView {
+                            camera: b,
+                            detection: j,
+                        },
+                    ];
+                    let Some(point) = triangulate(
+                        cameras,
+                        &seed.map(|v| observation(&v)),
+                        &seed_options,
+                    ) else {
+                        continue;
+                    };
+
+                    let mut views = seed.to_vec();
+                    let mut error_sum = point.rms * point.rms * 2.0;
+                    for c in (0..camera_count).filter(|c| *c != a && *c != b) {
+                        let nearest = detections[c]
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(k, uv)| {
+                                let e = cameras[c]
+                                    .reprojection_error(&point.position, uv)?;
+                                Some((k, e))
+                            })
+                            .min_by(|x, y| x.1.total_cmp(&y.1));
+                        if let Some((k, e)) = nearest
+                            && e <= options.max_distance
+                        {
+                            views.push(MarkerView {
+                                camera: c,
+                                detection: k,
+                            });
+                            error_sum += e * e;
+                        }
+                    }
+
+                    hypotheses.push(Hypothesis {
+                        error: (error_sum / views.len() as f64).sqrt(),
+                        views,
+                    });
+                }
+            }
+        }
+    }
+
+    hypotheses.sort_by(|x, y| {
+        y.views.len().cmp(&x.views.len()).then(x.error.total_cmp(&y.error))
+    });
+
+    let mut used: BTreeSet<(usize, usize)> = BTreeSet::new();
+    let mut markers = Vec::new();
+    for hypothesis in hypotheses {
+        if hypothesis
+            .views
+            .iter()
+            .any(|v| used.contains(&(v.camera, v.detection)))
+        {
+            continue;
+        }
+        // 2視点しかない対応はエピポーラ候補が一意な場合のみ採用する
+        if let [a, b] = hypothesis.views[..] {
+            let ambiguous = |v: MarkerView, other: MarkerView| {
+                candidate_counts
+                    .get(&((v.camera, v.detection), other.camera))
+                    .is_some_and(|count| *count > 1)
+            };
+            if ambiguous(a, b) || ambiguous(b, a) {
+                continue;
+            }
+        }
+
+        let observations: Vec<PointObservation> =
+            hypothesis.views.iter().map(observation).collect();
+        let Some(point) =
+            triangulate(cameras, &observations, &options.triangulation)
+        else {
+            continue;
+        };
+
+        let views: Vec<MarkerView> = hypothesis
+            .views
+            .iter()
+            .zip(&point.inliers)
+            .filter(|(_, inlier)| **inlier)
+            .map(|(v, _)| *v)
+            .collect();
+        for v in &views {
+            used.insert((v.camera, v.detection));
+        }
+        markers.push(LabeledMarker {
+            label: markers.len(),
+            position: point.position,
+            views,
+            rms: point.rms,
+            confidence: point.confidence,
+        });
+    }
+
+    MarkerCloud { markers }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Intrinsics, Pose};
+
+    // 原点を左右から見込む2台のカメラ。エピポーラ線はほぼ水平になる
+    fn cameras(count: usize) -> Vec<CalibratedCamera> {
+        let intrinsics = Intrinsics {
+            fx: 700.0,
+            fy: 710.0,
+            cx: 320.0,
+            cy: 240.0,
+            dist: [-0.08, 0.02, 0.001, -0.001, 0.0],
+        };
+        [(0.3, -0.6), (-0.3, 0.6), (0.0, 0.0)]
+            .iter()
+            .take(count)
+            .enumerate()
+            .map(|(i, &(yaw, x))| CalibratedCamera {
+                intrinsics,
+                pose: Pose {
+                    // 3台目だけ上から見下ろし、左右の2台とは別の線を引く
+                    rotation: [if i == 2 { 0.5 } else { 0.0 }, yaw, 0.0],
+                    translation: [x, if i == 2 { -1.0 } else { 0.0 }, 2.0],
+                },
+            })
+            .collect()
+    }
+
+    // 高さの違う点。左右のカメラでエピポーラ線が重ならない
+    const POINTS: [Vec3; 4] = [
+        [0.0, -0.3, 0.0],
+        [0.2, -0.1, 0.1],
+        [-0.2, 0.1, -0.1],
+        [0.1, 0.3, 0.05],
+    ];
+
+    fn project(camera: &CalibratedCamera, points: &[Vec3]) -> Vec<[f64; 2]> {
+        points
+            .iter()
+            .map(|p| camera.project(p).expect("point is in front"))
+            .collect()
+    }
+
+    fn distance(a: &Vec3, b: &Vec3) -> f64 {
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2))
+            .sqrt()
+    }
+
+    #[test]
+    fn projections_lie_on_epipolar_lines() {
+        let cameras = cameras(2);
+        let f = fundamental_matrix(&cameras[0], &cameras[1]);
+        let a = project(&cameras[0], &POINTS);
+        let b = project(&cameras[1], &POINTS);
+        for i in 0..POINTS.len() {
+            for j in 0..POINTS.len() {
+                let d = epipolar_distance(
+                    &f,
+                    &ideal_pixel(&cameras[0], &a[i]),
+                    &ideal_pixel(&cameras[1], &b[j]),
+                );
+                if i == j {
+                    assert!(d < 1e-6, "{i}: {d}");
+                } else {
+                    assert!(d > 10.0, "{i} {j}: {d}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_shuffled_detections_across_two_views() {
+        let cameras = cameras(2);
+        let a = project(&cameras[0], &POINTS);
+        // 2台目は検出順が違い、どの点にも対応しない誤検出を含む
+        let order = [2, 0, 3, 1];
+        let mut b: Vec<[f64; 2]> =
+            order.iter().map(|&i| project(&cameras[1], &POINTS)[i]).collect();
+        b.push([600.0, 20.0]);
+
+        let cloud =
+            match_markers(&cameras, &[a, b], &CorrespondenceOptions::default());
+        assert_eq!(cloud.markers.len(), POINTS.len());
+        for marker in &cloud.markers {
+            let [va, vb] = marker.views[..] else {
+                panic!("expected two views: {marker:?}");
+            };
+            assert_eq!((va.camera, vb.camera), (0, 1));
+            assert_eq!(order[vb.detection], va.detection);
+            assert!(distance(&marker.position, &POINTS[va.detection]) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn ambiguous_pairs_need_a_third_view() {
+        // 左右のカメラの基線上に並ぶ2点は、同じエピポーラ線に乗る
+        let points = [[-0.2, 0.0, 0.0], [0.2, 0.0, 0.0]];
+        let two = cameras(2);
+        let detections: Vec<Vec<[f64; 2]>> =
+            two.iter().map(|c| project(c, &points)).collect();
+        let cloud =
+            match_markers(&two, &detections, &CorrespondenceOptions::default());
+        assert!(cloud.markers.is_empty());
+
+        let three = cameras(3);
+        let detections: Vec<Vec<[f64; 2]>> =
+            three.iter().map(|c| project(c, &points)).collect();
+        let cloud = match_markers(
+            &three,
+            &detections,
+            &CorrespondenceOptions::default(),
+        );
+        assert_eq!(cloud.markers.len(), 2);
+        for marker in &cloud.markers {
+            assert_eq!(marker.views.len(), 3);
+            let p = &points[marker.views[0].detection];
+            assert!(distance(&marker.position, p) < 1e-6);
+            assert!(
+                marker
+                    .views
+                    .iter()
+                    .all(|v| v.detection == marker.views[0].detection)
+            );
+        }
+    }
+}
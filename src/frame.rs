@@ -0,0 +1,83 @@
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use opencv::core::Mat;
+use opencv::prelude::MatTraitConst;
+
+use crate::calibration::Pose;
+
+/// Identifies one camera stream for the lifetime of the process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId(pub u64);
+
+impl StreamId {
+    pub fn next() -> Self {
+        // 0は「まだどのストリームでもない」に使う
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// When and where a frame was captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameMeta {
+    pub stream_id: StreamId,
+    // ストリームごとに1から単調に増える。0はまだフレームが無い
+    pub sequence: u64,
+    // ソースから読み出した時刻
+    pub captured_at: Instant,
+    pub wall_clock: SystemTime,
+    // 再生ソースのみ。収録開始からの経過時間
+    pub recorded_at: Option<Duration>,
+}
+
+impl Default for FrameMeta {
+    fn default() -> Self {
+        Self {
+            stream_id: StreamId::default(),
+            sequence: 0,
+            captured_at: Instant::now(),
+            wall_clock: SystemTime::now(),
+            recorded_at: None,
+        }
+    }
+}
+
+impl FrameMeta {
+    /// The time to compare across cameras. Replayed frames use their
+    /// recorded time, so cameras of a session line up however far apart
+    /// they were opened.
+    pub fn sync_time(&self) -> Instant {
+        // 収録時刻はどのセッションでも同じ起点からの時刻として扱う
+        static RECORDED_EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+        match self.recorded_at {
+            Some(recorded_at) => *RECORDED_EPOCH + recorded_at,
+            None => self.captured_at,
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.captured_at.elapsed()
+    }
+
+    /// Frames lost between `previous` and this one of the same stream.
+    pub fn dropped_since(&self, previous: &FrameMeta) -> u64 {
+        self.sequence.saturating_sub(previous.sequence + 1)
+    }
+}
+
+/// An RGB image together with its [`FrameMeta`].
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub image: Mat,
+    pub meta: FrameMeta,
+    // 合成ソースが描画に使ったboard->cameraの姿勢。実カメラではNone
+    pub ground_truth: Option<Pose>,
+}
+
+impl Frame {
+    pub fn is_empty(&self) -> bool {
+        self.image.empty()
+    }
+}
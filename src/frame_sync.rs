@@ -0,0 +1,629 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::future::{Future, poll_fn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use opencv::core::Point2f;
+use opencv::prelude::MatTraitConst;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{CharucoMarker, DetectedFrame, Frame, FrameMeta};
+
+// フレームを待つ間もこの間隔でstopを確認する
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Anything published per frame that carries its [`FrameMeta`].
+pub trait Timestamped: Clone + Send + Sync + 'static {
+    fn meta(&self) -> &FrameMeta;
+}
+
+impl Timestamped for Frame {
+    fn meta(&self) -> &FrameMeta {
+        &self.meta
+    }
+}
+
+impl Timestamped for DetectedFrame {
+    fn meta(&self) -> &FrameMeta {
+        &self.frame.meta
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default,
+)]
+pub enum SyncMode {
+    // 各カメラで基準時刻に一番近いフレームを使う
+    #[default]
+    LatestComplete,
+    // 基準時刻を挟む2フレームを組にし、補間係数を付ける
+    Interpolated,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SyncConfig {
+    pub mode: SyncMode,
+    // 基準時刻からこれ以上離れたフレームしか無いカメラがあれば組にしない [s]
+    pub tolerance: f64,
+    // カメラごとに保持するフレーム数
+    pub history: usize,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            mode: SyncMode::LatestComplete,
+            // 30fpsのフレーム間隔の半分強
+            tolerance: 0.02,
+            history: 30,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Checks that the tolerance is a non-negative, finite duration.
+    pub fn validate(&self) -> Result<()> {
+        Duration::try_from_secs_f64(self.tolerance).map_err(|_| {
+            anyhow!("invalid sync tolerance {} s", self.tolerance)
+        })?;
+        Ok(())
+    }
+
+    // 設定ファイルの不正な値でもスレッドを止めないよう、組にしない0として扱う
+    fn tolerance(&self) -> Duration {
+        Duration::try_from_secs_f64(self.tolerance).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// One camera's contribution to a [`SyncedFrameSet`]. Frames are shared
+/// with the synchronizer's history, so cloning a view is cheap.
+#[derive(Clone, Debug)]
+pub struct SyncedView<T> {
+    // 基準時刻の直前のフレーム。LatestCompleteでは一番近いフレーム
+    pub frame: Arc<T>,
+    // Interpolatedで基準時刻の直後のフレーム
+    pub next: Option<Arc<T>>,
+    // frameからnextへの補間係数 (0..=1)
+    pub alpha: f64,
+}
+
+impl<T: Timestamped> SyncedView<T> {
+    /// The frame closest to the set's instant.
+    pub fn nearest(&self) -> &T {
+        match &self.next {
+            Some(next) if self.alpha > 0.5 => next.as_ref(),
+            _ => self.frame.as_ref(),
+        }
+    }
+}
+
+impl SyncedView<DetectedFrame> {
+    /// ChArUco corners at the set's instant, keyed by corner id. Corners
+    /// seen in both frames of an interpolated view are blended, the others
+    /// come from the nearest frame.
+    pub fn charuco_corners(&self) -> opencv::Result<BTreeMap<i32, [f64; 2]>> {
+        let Some(next) = &self.next else {
+            return corners_by_id(&self.frame.charuco_marker);
+        };
+        let before = corners_by_id(&self.frame.charuco_marker)?;
+        let after = corners_by_id(&next.charuco_marker)?;
+        let nearest = if self.alpha > 0.5 { &after } else { &before };
+        Ok(nearest
+            .iter()
+            .map(|(id, p)| {
+                let point = match (before.get(id), after.get(id)) {
+                    (Some(a), Some(b)) => [
+                        a[0] + (b[0] - a[0]) * self.alpha,
+                        a[1] + (b[1] - a[1]) * self.alpha,
+                    ],
+                    _ => *p,
+                };
+                (*id, point)
+            })
+            .collect())
+    }
+}
+
+fn corners_by_id(
+    marker: &CharucoMarker,
+) -> opencv::Result<BTreeMap<i32, [f64; 2]>> {
+    if marker.charuco_ids.empty() {
+        return Ok(BTreeMap::new());
+    }
+    let ids = marker.charuco_ids.data_typed::<i32>()?;
+    let corners = marker.charuco_corners.data_typed::<Point2f>()?;
+    Ok(ids
+        .iter()
+        .zip(corners)
+        .map(|(id, p)| (*id, [p.x as f64, p.y as f64]))
+        .collect())
+}
+
+/// Frames of every camera captured at (about) the same instant. `views`
+/// follow the order of the streams given to the synchronizer.
+#[derive(Clone, Debug)]
+pub struct SyncedFrameSet<T> {
+    // オフセットで補正した時刻。各フレームのmetaは補正前のまま
+    pub time: Instant,
+    pub views: Vec<SyncedView<T>>,
+    // 各カメラの一番近いフレームの補正後の時刻の最大差 [s]
+    pub skew: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncStats {
+    pub sets: u64,
+    // 許容範囲内のフレームが揃わなかった回数
+    pub incomplete: u64,
+    // 以下は組にしたフレームのskew [s]
+    pub last_skew: f64,
+    pub mean_skew: f64,
+    pub max_skew: f64,
+}
+
+impl SyncStats {
+    fn add(&mut self, skew: f64) {
+        self.sets += 1;
+        self.last_skew = skew;
+        self.mean_skew += (skew - self.mean_skew) / self.sets as f64;
+        self.max_skew = self.max_skew.max(skew);
+    }
+}
+
+/// Groups the frames of several streams into [`SyncedFrameSet`]s by
+/// capture time, on a background thread.
+pub struct FrameSynchronizer<T: Timestamped> {
+    r_set: watch::Receiver<Option<SyncedFrameSet<T>>>,
+    s_config: watch::Sender<SyncConfig>,
+    s_offsets: watch::Sender<Vec<f64>>,
+    stats: Arc<Mutex<SyncStats>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: Timestamped> FrameSynchronizer<T> {
+    /// `offsets[i]` [s] is subtracted from the capture times of stream `i`
+    /// before grouping, to compensate for its latency.
+    pub fn new(
+        receivers: Vec<watch::Receiver<T>>,
+        offsets: Vec<f64>,
+        config: SyncConfig,
+    ) -> Self {
+        let (s_set, r_set) = watch::channel(None);
+        let (s_config, r_config) = watch::channel(config);
+        let (s_offsets, r_offsets) = watch::channel(offsets);
+        let stats = Arc::new(Mutex::new(SyncStats::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let stats = stats.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                run(receivers, r_config, r_offsets, s_set, &stats, &stop);
+            })
+        };
+
+        Self {
+            r_set,
+            s_config,
+            s_offsets,
+            stats,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// The most recent complete set, if any has been formed yet.
+    pub fn latest_set(&self) -> Option<SyncedFrameSet<T>> {
+        self.r_set.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<SyncedFrameSet<T>>> {
+        self.r_set.clone()
+    }
+
+    pub fn stats(&self) -> SyncStats {
+        *self.stats.lock().unwrap()
+    }
+
+    pub fn set_config(&self, config: SyncConfig) -> Result<()> {
+        config.validate()?;
+        self.s_config.send_replace(config);
+        Ok(())
+    }
+
+    pub fn set_offsets(&self, offsets: Vec<f64>) {
+        self.s_offsets.send_replace(offsets);
+    }
+}
+
+impl<T: Timestamped> Drop for FrameSynchronizer<T> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run<T: Timestamped>(
+    mut receivers: Vec<watch::Receiver<T>>,
+    mut r_config: watch::Receiver<SyncConfig>,
+    mut r_offsets: watch::Receiver<Vec<f64>>,
+    s_set: watch::Sender<Option<SyncedFrameSet<T>>>,
+    stats: &Mutex<SyncStats>,
+    stop: &AtomicBool,
+) {
+    // watchのchangedを待つためだけのランタイム
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("Failed to build synchronizer runtime");
+    // Matの複製は深いコピーなので、履歴と組ではArcで共有する
+    let mut histories: Vec<VecDeque<Arc<T>>> =
+        receivers.iter().map(|_| VecDeque::new()).collect();
+    // 最後に組にした基準フレーム (カメラ, sequence)
+    let mut last_reference = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        // どれかのストリームに新しいフレームが来るまで眠る
+        match runtime.block_on(async {
+            tokio::time::timeout(
+                STOP_CHECK_INTERVAL,
+                any_changed(&mut receivers),
+            )
+            .await
+        }) {
+            Ok(true) => {}
+            Err(_) => continue,
+            // ストリームが終了した
+            Ok(false) => return,
+        }
+        let config = *r_config.borrow_and_update();
+
+        let mut changed = false;
+        for (r, history) in receivers.iter_mut().zip(&mut histories) {
+            match r.has_changed() {
+                Ok(true) => {}
+                Ok(false) => continue,
+                // ストリームが終了した
+                Err(_) => return,
+            }
+            let item = Arc::new(r.borrow_and_update().clone());
+            let sequence = item.meta().sequence;
+            // 同じフレームが何度も送られてくることがある
+            if sequence == 0
+                || history.back().is_some_and(|b| b.meta().sequence == sequence)
+            {
+                continue;
+            }
+            // 再生がループやシークで戻ったら、時刻順が崩れるので履歴を捨てる
+            if history
+                .back()
+                .is_some_and(|b| b.meta().sync_time() > item.meta().sync_time())
+            {
+                history.clear();
+            }
+            history.push_back(item);
+            while history.len() > config.history.max(2) {
+                history.pop_front();
+            }
+            changed = true;
+        }
+        if !changed {
+            continue;
+        }
+
+        let offsets = r_offsets.borrow_and_update().clone();
+        let Some((reference, set)) = build_set(&histories, &offsets, &config)
+        else {
+            stats.lock().unwrap().incomplete += 1;
+            continue;
+        };
+        if last_reference == Some(reference) {
+            continue;
+        }
+        last_reference = Some(reference);
+        stats.lock().unwrap().add(set.skew);
+        s_set.send_replace(Some(set));
+    }
+}
+
+// どれかのReceiverに新しい値が届くまで待つ。送り手が居なくなればfalse。
+// changedは届いた値を既読にするので、呼び出し元が読めるよう未読に戻す
+async fn any_changed<T>(receivers: &mut [watch::Receiver<T>]) -> bool {
+    let ready = {
+        let mut changed: Vec<_> =
+            receivers.iter_mut().map(|r| Box::pin(r.changed())).collect();
+        poll_fn(|cx| {
+            for (i, future) in changed.iter_mut().enumerate() {
+                if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                    return Poll::Ready(result.map(|()| i));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    };
+    match ready {
+        Ok(i) => {
+            receivers[i].mark_changed();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+// 一番遅れているカメラの最新フレームの時刻を基準に、各カメラから
+// その時刻のフレームを選ぶ。時刻はすべてoffsetsで補正してから比べる
+fn build_set<T: Timestamped>(
+    histories: &[VecDeque<Arc<T>>],
+    offsets: &[f64],
+    config: &SyncConfig,
+) -> Option<((usize, u64), SyncedFrameSet<T>)> {
+    let corrected = |camera: usize, f: &Arc<T>| {
+        let offset = offsets.get(camera).copied().unwrap_or(0.0);
+        shift(f.meta().sync_time(), -offset)
+    };
+
+    let (reference_camera, reference) = histories
+        .iter()
+        .enumerate()
+        .map(|(i, h)| Some((i, h.back()?)))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min_by_key(|(i, f)| corrected(*i, f))?;
+    let time = corrected(reference_camera, reference);
+    let tolerance = config.tolerance();
+
+    let mut views = Vec::with_capacity(histories.len());
+    let mut times = Vec::with_capacity(histories.len());
+    for (camera, history) in histories.iter().enumerate() {
+        let at = |f: &Arc<T>| corrected(camera, f);
+        // timeより後の最初のフレーム
+        let after = history.partition_point(|f| at(f) <= time);
+        let before = after.checked_sub(1).map(|i| &history[i]);
+        let after = history.get(after);
+
+        let distance = |f: &Arc<T>| abs_diff(at(f), time);
+        let nearest = [before, after]
+            .into_iter()
+            .flatten()
+            .min_by_key(|f| distance(f))?;
+        if distance(nearest) > tolerance {
+            return None;
+        }
+        times.push(at(nearest));
+
+        views.push(match (config.mode, before, after) {
+            (SyncMode::Interpolated, Some(before), Some(after)) => {
+                let span = at(after) - at(before);
+                SyncedView {
+                    frame: before.clone(),
+                    next: Some(after.clone()),
+                    alpha: (time - at(before)).as_secs_f64()
+                        / span.as_secs_f64(),
+                }
+            }
+            _ => SyncedView {
+                frame: nearest.clone(),
+                next: None,
+                alpha: 0.0,
+            },
+        });
+    }
+
+    let skew = match (times.iter().min(), times.iter().max()) {
+        (Some(min), Some(max)) => (*max - *min).as_secs_f64(),
+        _ => 0.0,
+    };
+
+    Some((
+        (reference_camera, reference.meta().sequence),
+        SyncedFrameSet { time, views, skew },
+    ))
+}
+
+// 負の秒数も扱えるInstantの加算。表せない値ならずらさない
+fn shift(t: Instant, seconds: f64) -> Instant {
+    let Ok(d) = Duration::try_from_secs_f64(seconds.abs()) else {
+        return t;
+    };
+    if seconds >= 0.0 {
+        t.checked_add(d)
+    } else {
+        t.checked_sub(d)
+    }
+    .unwrap_or(t)
+}
+
+fn abs_diff(a: Instant, b: Instant) -> Duration {
+    if a > b { a - b } else { b - a }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct Stamp(FrameMeta);
+
+    impl Timestamped for Stamp {
+        fn meta(&self) -> &FrameMeta {
+            &self.0
+        }
+    }
+
+    fn stamp(base: Instant, sequence: u64, ms: u64) -> Stamp {
+        Stamp(FrameMeta {
+            sequence,
+            captured_at: base + Duration::from_millis(ms),
+            ..Default::default()
+        })
+    }
+
+    // sequenceは1から順に振る
+    fn history(base: Instant, times_ms: &[u64]) -> VecDeque<Arc<Stamp>> {
+        times_ms
+            .iter()
+            .enumerate()
+            .map(|(i, ms)| Arc::new(stamp(base, i as u64 + 1, *ms)))
+            .collect()
+    }
+
+    fn config(mode: SyncMode, tolerance: f64) -> SyncConfig {
+        SyncConfig {
+            mode,
+            tolerance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn latest_complete_picks_nearest_frames() {
+        let base = Instant::now();
+        // 30fpsで10msずれた2台
+        let histories =
+            [history(base, &[0, 33, 66]), history(base, &[10, 43, 76])];
+
+        let (reference, set) = build_set(
+            &histories,
+            &[0.0, 0.0],
+            &config(SyncMode::LatestComplete, 0.02),
+        )
+        .unwrap();
+        // 一番遅れているカメラの最新フレームが基準になる
+        assert_eq!(reference, (0, 3));
+        assert_eq!(set.time, base + Duration::from_millis(66));
+        let sequences: Vec<u64> =
+            set.views.iter().map(|v| v.nearest().0.sequence).collect();
+        assert_eq!(sequences, vec![3, 3]);
+        assert!((set.skew - 0.010).abs() < 1e-9);
+
+        // 許容範囲より離れていれば組にしない
+        assert!(
+            build_set(
+                &histories,
+                &[0.0, 0.0],
+                &config(SyncMode::LatestComplete, 0.005),
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn interpolated_views_blend_neighbours() {
+        let base = Instant::now();
+        let histories =
+            [history(base, &[0, 33, 66]), history(base, &[10, 43, 76])];
+
+        let (_, set) = build_set(
+            &histories,
+            &[0.0, 0.0],
+            &config(SyncMode::Interpolated, 0.02),
+        )
+        .unwrap();
+        // 基準カメラは直後のフレームが無いので補間しない
+        assert!(set.views[0].next.is_none());
+        let view = &set.views[1];
+        assert_eq!(view.frame.0.sequence, 2);
+        assert_eq!(view.next.as_ref().unwrap().0.sequence, 3);
+        assert!((view.alpha - 23.0 / 33.0).abs() < 1e-9);
+        assert_eq!(view.nearest().0.sequence, 3);
+    }
+
+    #[test]
+    fn offsets_align_delayed_cameras() {
+        let base = Instant::now();
+        let histories =
+            [history(base, &[0, 33, 66]), history(base, &[10, 43, 76])];
+
+        // 2台目の10msの遅れを差し引けば時刻が揃う
+        let (_, set) = build_set(
+            &histories,
+            &[0.0, 0.010],
+            &config(SyncMode::LatestComplete, 0.001),
+        )
+        .unwrap();
+        assert!(set.skew.abs() < 1e-9);
+        // 負のオフセットも扱える
+        let (_, set) = build_set(
+            &histories,
+            &[-0.010, 0.0],
+            &config(SyncMode::LatestComplete, 0.001),
+        )
+        .unwrap();
+        assert!(set.skew.abs() < 1e-9);
+        assert_eq!(set.time, base + Duration::from_millis(76));
+    }
+
+    #[test]
+    fn invalid_values_do_not_panic() {
+        let t = Instant::now() + Duration::from_secs(10);
+        assert_eq!(shift(t, -0.5), t - Duration::from_millis(500));
+        assert_eq!(shift(t, 0.5), t + Duration::from_millis(500));
+        for seconds in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300] {
+            assert_eq!(shift(t, seconds), t);
+        }
+
+        for tolerance in [-0.01, f64::NAN, f64::INFINITY] {
+            assert!(
+                config(SyncMode::LatestComplete, tolerance).validate().is_err()
+            );
+        }
+        assert!(SyncConfig::default().validate().is_ok());
+
+        // 設定ファイルの不正な許容範囲は0として扱う
+        let base = Instant::now();
+        let histories = [history(base, &[0]), history(base, &[10])];
+        assert!(
+            build_set(
+                &histories,
+                &[0.0, 0.0],
+                &config(SyncMode::LatestComplete, f64::NAN),
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn synchronizer_publishes_sets_from_streams() {
+        let base = Instant::now();
+        let (s0, r0) = watch::channel(stamp(base, 0, 0));
+        let (s1, r1) = watch::channel(stamp(base, 0, 0));
+        let synchronizer = FrameSynchronizer::new(
+            vec![r0, r1],
+            vec![0.0, 0.0],
+            SyncConfig::default(),
+        );
+        let mut r_set = synchronizer.subscribe();
+
+        s0.send_replace(stamp(base, 1, 0));
+        s1.send_replace(stamp(base, 1, 5));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let set = runtime
+            .block_on(async {
+                tokio::time::timeout(
+                    Duration::from_secs(5),
+                    r_set.wait_for(Option::is_some),
+                )
+                .await
+            })
+            .unwrap()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(set.views.len(), 2);
+        assert!((set.skew - 0.005).abs() < 1e-9);
+        assert_eq!(synchronizer.stats().sets, 1);
+    }
+}
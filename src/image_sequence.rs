@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use opencv::core::Mat;
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::VideoSourceError;
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+// 待機中に眠る上限
+const MAX_WAIT: Duration = Duration::from_millis(20);
+
+/// Numbered PNG/JPEG stills in a folder, played in order at a fixed rate
+/// and looped.
+pub struct ImageSequence {
+    paths: Vec<PathBuf>,
+    position: usize,
+    interval: Duration,
+    next_due: Instant,
+}
+
+impl ImageSequence {
+    /// Lists the images in `dir` whose file name matches `pattern`, where
+    /// `*` matches any run of characters and `?` any single character.
+    pub fn new(dir: impl AsRef<Path>, pattern: &str, fps: f64) -> Result<Self> {
+        let dir = dir.as_ref();
+        if fps <= 0.0 {
+            return Err(anyhow!("fps must be positive"));
+        }
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                let is_image = path.extension().is_some_and(|ext| {
+                    let ext = ext.to_string_lossy().to_lowercase();
+                    IMAGE_EXTENSIONS.contains(&ext.as_str())
+                });
+                let matches = path.file_name().is_some_and(|name| {
+                    wildcard_match(pattern, &name.to_string_lossy())
+                });
+                is_image && matches && path.is_file()
+            })
+            .collect();
+        if paths.is_empty() {
+            return Err(anyhow!(
+                "no images matching '{}' in '{}'",
+                pattern,
+                dir.display()
+            ));
+        }
+        // IMG_9.JPGがIMG_10.JPGより先に来るよう番号の数値で並べる
+        paths.sort_by_cached_key(|path| {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            (frame_number(&name), name)
+        });
+
+        Ok(Self {
+            paths,
+            position: 0,
+            interval: Duration::from_secs_f64(1.0 / fps),
+            next_due: Instant::now(),
+        })
+    }
+
+    pub fn read(&mut self) -> Result<Mat, VideoSourceError> {
+        let now = Instant::now();
+        if now < self.next_due {
+            thread::sleep((self.next_due - now).min(MAX_WAIT));
+            return Err(VideoSourceError::NotReady);
+        }
+        self.next_due += self.interval;
+        // 遅れが溜まっても連続で吐き出さない
+        if self.next_due < now {
+            self.next_due = now + self.interval;
+        }
+
+        self.next_frame().map_err(|err| VideoSourceError::Read(err.to_string()))
+    }
+
+    /// Number of images in the folder, i.e. the length of one loop.
+    pub fn image_count(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Loads the next image right away, ignoring the frame rate.
+    pub fn next_frame(&mut self) -> Result<Mat> {
+        let path = &self.paths[self.position];
+        self.position = (self.position + 1) % self.paths.len();
+
+        // IMREAD_COLOR loads as BGR
+        let frame = imgcodecs::imread(
+            &path.to_string_lossy(),
+            imgcodecs::IMREAD_COLOR,
+        )?;
+        if frame.empty() {
+            return Err(anyhow!("failed to read '{}'", path.display()));
+        }
+
+        // Convert BGR to RGB like MMAPCam::read
+        let mut rgb_frame = Mat::default();
+        imgproc::cvt_color(
+            &frame,
+            &mut rgb_frame,
+            imgproc::COLOR_BGR2RGB,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
+
+        Ok(rgb_frame)
+    }
+}
+
+// ファイル名に含まれる最後の数字列。無ければ先頭に並べる
+fn frame_number(name: &str) -> Option<u64> {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let digits: String = stem
+        .chars()
+        .rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.chars().rev().collect::<String>().parse().ok()
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // 最後の*の位置と、そこから再開する名前側の位置
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
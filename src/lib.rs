@@ -7,7 +7,9 @@ pub use camera_stream::*;
 pub mod pipeline;
 pub use pipeline::*;
 
+#[cfg(feature = "python")]
 pub mod python_utils;
+#[cfg(feature = "python")]
 pub use python_utils::*;
 
 pub mod workload;
@@ -15,3 +17,51 @@ pub use workload::*;
 
 pub mod opencv_cam;
 pub use opencv_cam::*;
+
+pub mod charuco_board;
+pub use charuco_board::*;
+
+pub mod board_printer;
+pub use board_printer::*;
+
+pub mod calibration;
+pub use calibration::*;
+
+pub mod triangulation;
+pub use triangulation::*;
+
+pub mod blob_detector;
+pub use blob_detector::*;
+
+pub mod correspondence;
+pub use correspondence::*;
+
+pub mod tracker;
+pub use tracker::*;
+
+pub mod session;
+pub use session::*;
+
+pub mod playback;
+pub use playback::*;
+
+pub mod image_sequence;
+pub use image_sequence::*;
+
+pub mod synthetic;
+pub use synthetic::*;
+
+pub mod mmap_frame;
+pub use mmap_frame::*;
+
+pub mod frame;
+pub use frame::*;
+
+pub mod frame_sync;
+pub use frame_sync::*;
+
+pub mod time_offset;
+pub use time_offset::*;
+
+pub mod capture_device;
+pub use capture_device::*;
@@ -2,7 +2,10 @@ mod app_state;
 use app_state::{AppState, deserialize_app_state, serialize_app_state};
 use eframe::egui;
 use egui_tabs::Tabs;
-use mocap_for_one::{Workloads, mat_to_color_image};
+use mocap_for_one::{
+    SyntheticConfig, Workloads, draw_detected_blobs, generate_printable_board,
+    mat_to_color_image,
+};
 use opencv::core::Scalar;
 use opencv::{core::MatTraitConst, objdetect::draw_detected_markers};
 use std::{
@@ -16,10 +19,17 @@ use anyhow::{Context, Result};
 
 mod widgets;
 use mocap_for_one::workload::WorkLoad;
-use widgets::VideoCaptureModal;
+use widgets::{
+    BoardGeneratorModal, CalibrationModal, ImageSequenceModal,
+    MarkerCloudWindow, PlaybackModal, VideoCaptureModal, stream_state_text,
+};
 
 use crate::widgets::{
-    VideoViewer, unity_camera_modal::UnityCameraModalEffect,
+    VideoViewer, board_generator_modal::BoardGeneratorModalEffect,
+    calibration_modal::CalibrationModalEffect,
+    image_sequence_modal::ImageSequenceModalEffect,
+    playback_modal::PlaybackModalEffect,
+    unity_camera_modal::UnityCameraModalEffect,
     video_capture_modal::VideoCaptureModalEffect,
     video_viewer::VideoViewerEffect,
 };
@@ -27,23 +37,211 @@ use crate::widgets::{
 struct App {
     state: AppState,
     video_modal: VideoCaptureModal,
+    board_generator_modal: BoardGeneratorModal,
+    calibration_modal: CalibrationModal,
+    marker_cloud_window: MarkerCloudWindow,
+    playback_modal: PlaybackModal,
+    image_sequence_modal: ImageSequenceModal,
     status_message: Option<String>,
+    // 読み込めなかった設定ファイルを空の設定で上書きしないよう覚えておく
+    config_load_failed: bool,
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.video_modal.show(ctx).map(|eff| match eff {
             VideoCaptureModalEffect::OnOpenCamera(cam) => {
-                self.state.workload.add_camera_stream(cam);
+                if let Err(err) = self.state.workload.add_camera_stream(cam) {
+                    self.status_message =
+                        Some(format!("Failed to add camera: {}", err));
+                } else {
+                    self.status_message =
+                        duplicate_device_message(&self.state.workload);
+                }
             }
         });
 
         self.state.unity_modal.show(ctx).map(|eff| match eff {
             UnityCameraModalEffect::OnOpenCamera(cam) => {
-                self.state.workload.add_camera_stream(cam);
+                if let Err(err) = self.state.workload.add_camera_stream(cam) {
+                    self.status_message =
+                        Some(format!("Failed to add camera: {}", err));
+                }
             }
         });
 
+        self.playback_modal.show(ctx).map(|eff| match eff {
+            PlaybackModalEffect::OnOpenSession(dir) => {
+                self.status_message =
+                    match self.state.workload.open_session(&dir) {
+                        Ok(count) => Some(format!(
+                            "Opened {} cameras from {}.",
+                            count,
+                            dir.display()
+                        )),
+                        Err(err) => {
+                            Some(format!("Failed to open session: {}", err))
+                        }
+                    };
+            }
+            PlaybackModalEffect::OnOpenCamera(cam) => {
+                if let Err(err) = self.state.workload.add_camera_stream(cam) {
+                    self.status_message =
+                        Some(format!("Failed to add camera: {}", err));
+                }
+            }
+            PlaybackModalEffect::OnError(message) => {
+                self.status_message = Some(message);
+            }
+        });
+
+        self.image_sequence_modal.show(ctx).map(|eff| match eff {
+            ImageSequenceModalEffect::OnOpenCamera(cam) => {
+                if let Err(err) = self.state.workload.add_camera_stream(cam) {
+                    self.status_message =
+                        Some(format!("Failed to add camera: {}", err));
+                }
+            }
+            ImageSequenceModalEffect::OnError(message) => {
+                self.status_message = Some(message);
+            }
+        });
+
+        self.board_generator_modal.show(ctx).map(|eff| match eff {
+            BoardGeneratorModalEffect::OnGenerate(config, output_stem) => {
+                self.status_message =
+                    match generate_printable_board(&config, &output_stem) {
+                        Ok(output) => Some(format!(
+                            "Board written to {}, {} and {}.",
+                            output.png_path.display(),
+                            output.svg_path.display(),
+                            output.pdf_path.display()
+                        )),
+                        Err(err) => {
+                            Some(format!("Failed to generate board: {}", err))
+                        }
+                    };
+            }
+        });
+
+        // 別スレッドでの時刻オフセット推定が終わったら反映する
+        match self.state.workload.poll_time_offsets() {
+            Some(Ok(count)) => {
+                self.status_message = Some(format!(
+                    "Estimated time offsets of {} cameras.",
+                    count
+                ));
+            }
+            Some(Err(err)) => {
+                self.status_message =
+                    Some(format!("Failed to estimate time offsets: {}", err));
+            }
+            None if self.state.workload.is_estimating_time_offsets() => {
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            None => {}
+        }
+
+        self.calibration_modal
+            .show(ctx, &self.state.workload)
+            .map(|eff| match eff {
+                CalibrationModalEffect::OnCaptureAll => {
+                    if let Err(err) =
+                        self.state.workload.capture_synchronized_frames()
+                    {
+                        self.status_message =
+                            Some(format!("Failed to capture frames: {}", err));
+                    }
+                }
+                CalibrationModalEffect::OnSyncConfigChanged(config) => {
+                    if let Err(err) =
+                        self.state.workload.set_sync_config(config)
+                    {
+                        self.status_message = Some(format!(
+                            "Failed to set sync config: {}",
+                            err
+                        ));
+                    }
+                }
+                CalibrationModalEffect::OnEstimateTimeOffsets(
+                    dir,
+                    reference,
+                    options,
+                ) => {
+                    self.status_message = match self
+                        .state
+                        .workload
+                        .estimate_time_offsets(&dir, reference, &options)
+                    {
+                        Ok(()) => Some(format!(
+                            "Estimating time offsets from {}...",
+                            dir.display()
+                        )),
+                        Err(err) => Some(format!(
+                            "Failed to estimate time offsets: {}",
+                            err
+                        )),
+                    };
+                }
+                CalibrationModalEffect::OnClearCaptures => {
+                    self.state.workload.clear_synchronized_captures();
+                }
+                CalibrationModalEffect::OnSolveExtrinsics(reference) => {
+                    self.status_message = match self
+                        .state
+                        .workload
+                        .calibrate_extrinsics(reference)
+                    {
+                        Ok(calibration) => Some(format!(
+                            "Extrinsics solved: RMS {:.3} px (initial {:.3} px). {}",
+                            calibration.rms,
+                            calibration.initial_rms,
+                            per_camera_rms_text(
+                                &self.state.workload,
+                                &calibration.per_camera_rms
+                            )
+                        )),
+                        Err(err) => Some(format!(
+                            "Failed to solve extrinsics: {}",
+                            err
+                        )),
+                    };
+                }
+                CalibrationModalEffect::OnRefineCalibration(
+                    reference,
+                    options,
+                ) => {
+                    self.status_message = match self
+                        .state
+                        .workload
+                        .refine_calibration(reference, &options)
+                    {
+                        Ok(report) => Some(format!(
+                            "Bundle adjustment: RMS {:.3} px (initial {:.3} px), {} iterations. {}",
+                            report.rms,
+                            report.initial_rms,
+                            report.iterations,
+                            per_camera_rms_text(
+                                &self.state.workload,
+                                &report.per_camera_rms
+                            )
+                        )),
+                        Err(err) => Some(format!(
+                            "Failed to run bundle adjustment: {}",
+                            err
+                        )),
+                    };
+                }
+            });
+
+        // トラッカーは描画ではなく新しい同期セットごとに進める
+        let tracked = self.state.workload.update_tracking().cloned();
+        self.marker_cloud_window.show(
+            ctx,
+            &mut self.state.workload,
+            tracked.as_ref(),
+        );
+
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
                 if ui.button("Add Video Capture").clicked() {
@@ -54,10 +252,50 @@ impl eframe::App for App {
                     self.state.unity_modal.open();
                 }
 
+                if ui.button("Add Synthetic Camera").clicked() {
+                    let name = format!(
+                        "Synthetic {}",
+                        self.state.workload.opencv_cams.len()
+                    );
+                    if let Err(err) = self
+                        .state
+                        .workload
+                        .add_synthetic_camera(name, SyntheticConfig::default())
+                    {
+                        self.status_message = Some(format!(
+                            "Failed to add synthetic camera: {}",
+                            err
+                        ));
+                    }
+                }
+
+                if ui.button("Add Image Sequence").clicked() {
+                    self.image_sequence_modal.open();
+                }
+
+                if ui.button("Open Recording").clicked() {
+                    self.playback_modal.open();
+                }
+
+                if ui.button("Multi-Camera Calibration").clicked() {
+                    self.calibration_modal.open();
+                }
+
+                if ui.button("Markers").clicked() {
+                    self.marker_cloud_window.open();
+                }
+
+                if ui.button("Generate Board").clicked() {
+                    self.board_generator_modal.open();
+                }
+
                 if ui.button("Save Config").clicked() {
                     self.status_message = match save_state_to_disk(&self.state)
                     {
-                        Ok(()) => Some("Config saved.".to_owned()),
+                        Ok(()) => {
+                            self.config_load_failed = false;
+                            Some("Config saved.".to_owned())
+                        }
                         Err(err) => {
                             Some(format!("Failed to save config: {}", err))
                         }
@@ -66,7 +304,19 @@ impl eframe::App for App {
             });
         });
 
+        if let Some(status_message) = &self.status_message {
+            egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+                ui.label(status_message);
+            });
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(message) =
+                show_unavailable_cameras(ui, &mut self.state.workload)
+            {
+                self.status_message = Some(message);
+            }
+
             let tab = Tabs::new(self.state.workload.opencv_cams.len() as i32)
                 .show(ui, |ui, state| {
                     if let Some(opencv_cam) = self
@@ -75,30 +325,49 @@ impl eframe::App for App {
                         .opencv_cams
                         .get(state.index() as usize)
                     {
-                        ui.add(
-                            egui::Label::new(
-                                opencv_cam
-                                    .opencv_camera
-                                    .camera_stream_config
-                                    .name
-                                    .clone(),
-                            )
-                            .selectable(false),
+                        let (text, color) = stream_state_text(
+                            &opencv_cam.opencv_camera.stream_status.state(),
                         );
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Label::new(
+                                    egui::RichText::new("●").color(color),
+                                )
+                                .selectable(false),
+                            )
+                            .on_hover_text(text);
+                            ui.add(
+                                egui::Label::new(
+                                    opencv_cam
+                                        .opencv_camera
+                                        .camera_stream_config
+                                        .name
+                                        .clone(),
+                                )
+                                .selectable(false),
+                            );
+                        });
                     }
                 });
 
             if let Some(selected_tab) = tab.selected() {
+                let recording = self.state.workload.is_recording();
                 if let Some(selected_opencv_cam) = self
                     .state
                     .workload
                     .opencv_cams
                     .get_mut(selected_tab as usize)
                 {
-                    let mut mat = selected_opencv_cam.get_latest_frame();
+                    let mut mat = selected_opencv_cam.get_latest_frame().image;
 
                     if mat.empty() {
-                        ui.label("No frame available yet");
+                        let (text, _) = stream_state_text(
+                            &selected_opencv_cam
+                                .opencv_camera
+                                .stream_status
+                                .state(),
+                        );
+                        ui.label(format!("No frame available yet ({text})"));
                         return;
                     }
 
@@ -113,6 +382,13 @@ impl eframe::App for App {
                     )
                     .expect("Failed to draw detected markers");
 
+                    draw_detected_blobs(
+                        &mut mat,
+                        &selected_opencv_cam.get_latest_blobs(),
+                        Scalar::new(255.0, 0.0, 0.0, 0.0),
+                    )
+                    .expect("Failed to draw detected blobs");
+
                     let img = mat_to_color_image(mat)
                         .expect("Failed to convert mat to color image");
 
@@ -120,6 +396,26 @@ impl eframe::App for App {
                         ui,
                         &img,
                         selected_opencv_cam.on_calibration,
+                        &mut selected_opencv_cam.charuco_board_draft,
+                        &selected_opencv_cam
+                            .opencv_camera
+                            .marker_detector_config,
+                        recording,
+                        selected_opencv_cam
+                            .opencv_camera
+                            .playback
+                            .as_ref()
+                            .map(|p| p.status()),
+                        selected_opencv_cam
+                            .opencv_camera
+                            .mmap_status
+                            .as_ref()
+                            .map(|s| s.status()),
+                        &selected_opencv_cam
+                            .opencv_camera
+                            .stream_status
+                            .state(),
+                        selected_opencv_cam.opencv_camera.detector_stats(),
                     );
 
                     if selected_opencv_cam.on_calibration {
@@ -133,8 +429,7 @@ impl eframe::App for App {
                             VideoViewerEffect::OnClose => {
                                 self.state
                                     .workload
-                                    .opencv_cams
-                                    .remove(selected_tab as usize);
+                                    .remove_camera(selected_tab as usize);
                             }
                             VideoViewerEffect::OnStartCalibration => {
                                 selected_opencv_cam.on_calibration = true;
@@ -148,6 +443,63 @@ impl eframe::App for App {
                                 let _ = selected_opencv_cam
                                     .calibrate_with_captured_frames();
                             }
+                            VideoViewerEffect::OnMarkerDetectorChanged(
+                                config,
+                            ) => {
+                                selected_opencv_cam
+                                    .opencv_camera
+                                    .set_marker_detector_config(config);
+                            }
+                            VideoViewerEffect::OnStartRecording => {
+                                self.status_message = match self
+                                    .state
+                                    .workload
+                                    .start_recording(SESSIONS_DIR)
+                                {
+                                    Ok(dir) => Some(format!(
+                                        "Recording to {}.",
+                                        dir.display()
+                                    )),
+                                    Err(err) => Some(format!(
+                                        "Failed to start recording: {}",
+                                        err
+                                    )),
+                                };
+                            }
+                            VideoViewerEffect::OnStopRecording => {
+                                self.status_message = match self
+                                    .state
+                                    .workload
+                                    .stop_recording()
+                                {
+                                    Ok(Some(summary)) => Some(format!(
+                                        "Recorded {:?} frames to {}.",
+                                        summary.frame_counts,
+                                        summary.dir.display()
+                                    )),
+                                    Ok(None) => None,
+                                    Err(err) => Some(format!(
+                                        "Failed to stop recording: {}",
+                                        err
+                                    )),
+                                };
+                            }
+                            VideoViewerEffect::OnPlayback(command) => {
+                                self.state.workload.control_playback(command);
+                            }
+                            VideoViewerEffect::OnApplyCharucoBoard => {
+                                self.status_message = match selected_opencv_cam
+                                    .apply_charuco_board_draft()
+                                {
+                                    Ok(()) => Some(
+                                        "ChArUco board updated.".to_owned(),
+                                    ),
+                                    Err(err) => Some(format!(
+                                        "Failed to update ChArUco board: {}",
+                                        err
+                                    )),
+                                };
+                            }
                         }
                     };
                 }
@@ -159,6 +511,10 @@ impl eframe::App for App {
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.config_load_failed {
+            eprintln!("Config was not saved because it failed to load.");
+            return;
+        }
         if let Err(err) = save_state_to_disk(&self.state) {
             self.status_message =
                 Some(format!("Failed to save config on exit: {}", err));
@@ -168,21 +524,97 @@ impl eframe::App for App {
 
 impl App {
     fn new() -> Result<Self> {
-        let state = if let Ok(state) = load_state_from_disk() {
-            state
-        } else {
-            AppState::default()
+        let (state, load_error) = match load_state_from_disk() {
+            Ok(state) => (state, None),
+            Err(err) => {
+                eprintln!("Failed to load config: {err:#}");
+                (AppState::default(), Some(err))
+            }
+        };
+
+        let status_message = match &load_error {
+            Some(err) => Some(format!(
+                "Failed to load config: {err:#}. It will not be overwritten \
+                 on exit unless saved."
+            )),
+            // 同じカメラを2回追加した設定に気付けるようにする
+            None => duplicate_device_message(&state.workload),
         };
 
         Ok(Self {
             state,
             video_modal: VideoCaptureModal::new(),
-            status_message: None,
+            board_generator_modal: BoardGeneratorModal::new(),
+            calibration_modal: CalibrationModal::new(),
+            marker_cloud_window: MarkerCloudWindow::new(),
+            playback_modal: PlaybackModal::new(),
+            image_sequence_modal: ImageSequenceModal::new(),
+            status_message,
+            config_load_failed: load_error.is_some(),
         })
     }
 }
 
+fn duplicate_device_message(workload: &WorkLoad) -> Option<String> {
+    let warnings = workload.duplicate_device_warnings();
+    for warning in &warnings {
+        eprintln!("{warning}");
+    }
+    (!warnings.is_empty()).then(|| warnings.join(" "))
+}
+
+// 開けなかったカメラを並べ、開き直すか設定から外せるようにする
+fn show_unavailable_cameras(
+    ui: &mut egui::Ui,
+    workload: &mut WorkLoad,
+) -> Option<String> {
+    let mut message = None;
+    let mut remove = None;
+    for (i, cam) in workload.unavailable_cams.iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("●").color(egui::Color32::RED));
+            ui.label(format!("{} is unavailable: {}", cam.name(), cam.error));
+            if ui.button("Remove").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        workload.remove_unavailable_camera(i);
+    }
+    if !workload.unavailable_cams.is_empty()
+        && ui.button("Retry Unavailable Cameras").clicked()
+    {
+        let opened = workload.retry_unavailable_cameras();
+        message = Some(format!(
+            "Opened {} cameras, {} still unavailable.",
+            opened,
+            workload.unavailable_cams.len()
+        ));
+    }
+    message
+}
+
+// 観測のなかったカメラは"-"と表示する
+fn per_camera_rms_text(workload: &WorkLoad, rms: &[Option<f64>]) -> String {
+    let cameras: Vec<String> = workload
+        .opencv_cams
+        .iter()
+        .zip(rms)
+        .map(|(cam, rms)| {
+            let name = &cam.opencv_camera.camera_stream_config.name;
+            match rms {
+                Some(rms) => format!("{name}: {rms:.3} px"),
+                None => format!("{name}: -"),
+            }
+        })
+        .collect();
+    format!("Per camera: {}.", cameras.join(", "))
+}
+
 const CONFIG_PATH: &str = "mocap_for_one_config.json";
+// 収録したセッションを置くディレクトリ
+const SESSIONS_DIR: &str = "sessions";
 
 fn load_state_from_disk() -> Result<AppState> {
     match File::open(CONFIG_PATH) {
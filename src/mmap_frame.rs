@@ -0,0 +1,579 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{Ordering, fence};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+use memmap2::MmapMut;
+
+// mmapでやり取りするフレームのバイナリ形式。数値はすべてリトルエンディアン
+//
+//  0  magic         [u8; 4]  "MFO1"
+//  4  version       u32
+//  8  lock          u64      書き込み中は奇数 (seqlock)
+// 16  sequence      u64      フレームごとに1増える
+// 24  timestamp_ns  u64      書き込み側のUNIX時刻 [ns]
+// 32  width         u32
+// 36  height        u32
+// 40  pixel_format  u32      PixelFormat
+// 44  (reserved)    u32
+// 48  payload_len   u64
+// 56  (reserved)    u64
+// 64  payload
+pub const MMAP_FRAME_MAGIC: [u8; 4] = *b"MFO1";
+pub const MMAP_FRAME_VERSION: u32 = 1;
+pub const MMAP_FRAME_HEADER_LEN: usize = 64;
+
+const LOCK_OFFSET: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    // PNG/JPEGなどimdecodeで読める形式
+    Encoded,
+    Rgb8,
+    Bgr8,
+    Rgba8,
+    Bgra8,
+}
+
+impl PixelFormat {
+    fn to_u32(self) -> u32 {
+        match self {
+            Self::Encoded => 0,
+            Self::Rgb8 => 1,
+            Self::Bgr8 => 2,
+            Self::Rgba8 => 3,
+            Self::Bgra8 => 4,
+        }
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Encoded,
+            1 => Self::Rgb8,
+            2 => Self::Bgr8,
+            3 => Self::Rgba8,
+            4 => Self::Bgra8,
+            _ => return None,
+        })
+    }
+
+    /// Bytes per pixel of raw formats, `None` for encoded payloads.
+    pub fn channels(self) -> Option<usize> {
+        match self {
+            Self::Encoded => None,
+            Self::Rgb8 | Self::Bgr8 => Some(3),
+            Self::Rgba8 | Self::Bgra8 => Some(4),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MmapFrameHeader {
+    pub version: u32,
+    pub sequence: u64,
+    pub timestamp_ns: u64,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+    pub payload_len: u64,
+}
+
+impl MmapFrameHeader {
+    /// Whether `bytes` starts with the frame protocol magic. Files without
+    /// it are treated as a bare encoded image by older producers.
+    pub fn is_framed(bytes: &[u8]) -> bool {
+        bytes.len() >= MMAP_FRAME_HEADER_LEN && bytes[..4] == MMAP_FRAME_MAGIC
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if !Self::is_framed(bytes) {
+            return Err(anyhow!("missing frame header"));
+        }
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+        };
+
+        let version = u32_at(4);
+        if version != MMAP_FRAME_VERSION {
+            return Err(anyhow!("unsupported frame version {version}"));
+        }
+        let pixel_format = PixelFormat::from_u32(u32_at(40))
+            .ok_or_else(|| anyhow!("unknown pixel format {}", u32_at(40)))?;
+
+        let header = Self {
+            version,
+            sequence: u64_at(16),
+            timestamp_ns: u64_at(24),
+            width: u32_at(32),
+            height: u32_at(36),
+            pixel_format,
+            payload_len: u64_at(48),
+        };
+
+        if let Some(channels) = pixel_format.channels() {
+            let expected =
+                header.width as u64 * header.height as u64 * channels as u64;
+            if header.payload_len != expected {
+                return Err(anyhow!(
+                    "payload is {} bytes but {}x{} {:?} needs {}",
+                    header.payload_len,
+                    header.width,
+                    header.height,
+                    pixel_format,
+                    expected
+                ));
+            }
+        }
+        Ok(header)
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[..4].copy_from_slice(&MMAP_FRAME_MAGIC);
+        bytes[4..8].copy_from_slice(&self.version.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.timestamp_ns.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.width.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.height.to_le_bytes());
+        bytes[40..44]
+            .copy_from_slice(&self.pixel_format.to_u32().to_le_bytes());
+        bytes[48..56].copy_from_slice(&self.payload_len.to_le_bytes());
+    }
+}
+
+pub enum FrameSnapshot {
+    // 前回から新しいフレームが書かれていない
+    Unchanged,
+    // 書き込み中か、読んでいる間に書き換えられた
+    Busy,
+    Frame(MmapFrameHeader, Vec<u8>),
+}
+
+// 任意のスライスを受け取るので、長さを確かめてからコピーして読む
+fn load_lock(bytes: &[u8]) -> Result<u64> {
+    let lock = bytes.get(LOCK_OFFSET..LOCK_OFFSET + 8).ok_or_else(|| {
+        anyhow!("{} bytes is shorter than the frame header", bytes.len())
+    })?;
+    Ok(u64::from_le_bytes(lock.try_into()?))
+}
+
+/// Copies the frame out of `bytes` if its sequence differs from
+/// `last_sequence`, using the seqlock to reject torn reads.
+pub fn snapshot_frame(
+    bytes: &[u8],
+    last_sequence: Option<u64>,
+) -> Result<FrameSnapshot> {
+    let before = load_lock(bytes)?;
+    if before % 2 == 1 {
+        return Ok(FrameSnapshot::Busy);
+    }
+    fence(Ordering::Acquire);
+
+    let header = MmapFrameHeader::parse(bytes)?;
+    // 0はまだ1枚も書かれていない状態
+    if header.sequence == 0 || last_sequence == Some(header.sequence) {
+        return Ok(FrameSnapshot::Unchanged);
+    }
+    let available = (bytes.len() - MMAP_FRAME_HEADER_LEN) as u64;
+    if header.payload_len > available {
+        return Err(anyhow!(
+            "payload of {} bytes exceeds the {} mapped bytes",
+            header.payload_len,
+            available
+        ));
+    }
+    let start = MMAP_FRAME_HEADER_LEN;
+    let payload = bytes[start..start + header.payload_len as usize].to_vec();
+
+    fence(Ordering::Acquire);
+    if load_lock(bytes)? != before {
+        return Ok(FrameSnapshot::Busy);
+    }
+    Ok(FrameSnapshot::Frame(header, payload))
+}
+
+/// Like [`snapshot_frame`], but reads `file` with positioned reads
+/// instead of through a mapping. A producer truncating the file makes
+/// the read fail instead of raising SIGBUS.
+pub fn snapshot_frame_file(
+    file: &File,
+    last_sequence: Option<u64>,
+) -> Result<FrameSnapshot> {
+    let mut bytes = [0; MMAP_FRAME_HEADER_LEN];
+    read_exact_at(file, &mut bytes, 0)?;
+    let before = load_lock(&bytes)?;
+    if before % 2 == 1 {
+        return Ok(FrameSnapshot::Busy);
+    }
+
+    let header = MmapFrameHeader::parse(&bytes)?;
+    if header.sequence == 0 || last_sequence == Some(header.sequence) {
+        return Ok(FrameSnapshot::Unchanged);
+    }
+    // 壊れたヘッダで巨大なバッファを確保しないよう先に長さを確かめる
+    let available =
+        file.metadata()?.len().saturating_sub(MMAP_FRAME_HEADER_LEN as u64);
+    if header.payload_len > available {
+        return Err(anyhow!(
+            "payload of {} bytes exceeds the {} bytes in the file",
+            header.payload_len,
+            available
+        ));
+    }
+    let mut payload = vec![0; header.payload_len as usize];
+    read_exact_at(file, &mut payload, MMAP_FRAME_HEADER_LEN as u64)?;
+
+    read_exact_at(file, &mut bytes[..LOCK_OFFSET + 8], 0)?;
+    if load_lock(&bytes)? != before {
+        return Ok(FrameSnapshot::Busy);
+    }
+    Ok(FrameSnapshot::Frame(header, payload))
+}
+
+/// Reads exactly `buf.len()` bytes at `offset` without touching the file
+/// cursor. A file shorter than that gives `UnexpectedEof`.
+pub(crate) fn read_exact_at(
+    file: &File,
+    buf: &mut [u8],
+    offset: u64,
+) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.seek_read(&mut buf[filled..], offset + filled as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reference producer of the mmap frame protocol.
+pub struct MmapFrameWriter {
+    file: File,
+    mmap: MmapMut,
+    lock: u64,
+    sequence: u64,
+}
+
+impl MmapFrameWriter {
+    /// Creates (or truncates) `path` with room for `capacity` payload
+    /// bytes. The file grows when a larger frame is written.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| {
+                format!("failed to create '{}'", path.display())
+            })?;
+        file.set_len((MMAP_FRAME_HEADER_LEN + capacity) as u64)?;
+        // Safety: このファイルを書き換えるのはこのwriterだけ
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        MmapFrameHeader {
+            version: MMAP_FRAME_VERSION,
+            sequence: 0,
+            timestamp_ns: 0,
+            width: 0,
+            height: 0,
+            pixel_format: PixelFormat::Encoded,
+            payload_len: 0,
+        }
+        .write(&mut mmap);
+
+        Ok(Self {
+            file,
+            mmap,
+            lock: 0,
+            sequence: 0,
+        })
+    }
+
+    /// Publishes one frame and returns its sequence number.
+    pub fn write_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel_format: PixelFormat,
+        payload: &[u8],
+    ) -> Result<u64> {
+        if let Some(channels) = pixel_format.channels()
+            && payload.len() != width as usize * height as usize * channels
+        {
+            return Err(anyhow!(
+                "{}x{} {:?} payload must be {} bytes, got {}",
+                width,
+                height,
+                pixel_format,
+                width as usize * height as usize * channels,
+                payload.len()
+            ));
+        }
+
+        let needed = MMAP_FRAME_HEADER_LEN + payload.len();
+        if needed > self.mmap.len() {
+            self.mmap.flush()?;
+            self.file.set_len(needed as u64)?;
+            // Safety: createと同じ
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+
+        self.lock += 1;
+        self.store_lock();
+        fence(Ordering::Release);
+
+        self.sequence += 1;
+        let timestamp_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        MmapFrameHeader {
+            version: MMAP_FRAME_VERSION,
+            sequence: self.sequence,
+            timestamp_ns,
+            width,
+            height,
+            pixel_format,
+            payload_len: payload.len() as u64,
+        }
+        .write(&mut self.mmap);
+        self.mmap[MMAP_FRAME_HEADER_LEN..needed].copy_from_slice(payload);
+
+        fence(Ordering::Release);
+        self.lock += 1;
+        self.store_lock();
+
+        Ok(self.sequence)
+    }
+
+    fn store_lock(&mut self) {
+        let ptr = self.mmap[LOCK_OFFSET..].as_mut_ptr() as *mut u64;
+        // Safety: マップはページ境界から始まり、createでヘッダ分以上の長さを
+        // 確保しているので、オフセット8のu64は範囲内で8バイト境界にある
+        unsafe { ptr.write_volatile(self.lock.to_le()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use memmap2::Mmap;
+
+    use super::*;
+
+    // テストごとに別のファイルを使い、終わったら消す
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "mocap_for_one_{}_{name}.mmap",
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn map(path: &Path) -> Mmap {
+        let file = File::open(path).unwrap();
+        unsafe { Mmap::map(&file).unwrap() }
+    }
+
+    fn map_mut(path: &Path) -> MmapMut {
+        let file =
+            OpenOptions::new().read(true).write(true).open(path).unwrap();
+        unsafe { MmapMut::map_mut(&file).unwrap() }
+    }
+
+    fn expect_frame(
+        bytes: &[u8],
+        last: Option<u64>,
+    ) -> (MmapFrameHeader, Vec<u8>) {
+        match snapshot_frame(bytes, last).unwrap() {
+            FrameSnapshot::Frame(header, payload) => (header, payload),
+            FrameSnapshot::Unchanged => panic!("unexpected Unchanged"),
+            FrameSnapshot::Busy => panic!("unexpected Busy"),
+        }
+    }
+
+    #[test]
+    fn round_trips_payloads() {
+        let file = TempFile::new("round_trip");
+        let mut writer = MmapFrameWriter::create(&file.0, 16).unwrap();
+
+        let rgb: Vec<u8> = (0..2 * 3 * 3).collect();
+        // 容量より大きいのでファイルが伸びる
+        let bgra: Vec<u8> = (0..4 * 3 * 4).map(|v| 255 - v).collect();
+        let encoded = b"\x89PNG not really".to_vec();
+        let frames = [
+            (2, 3, PixelFormat::Rgb8, rgb),
+            (4, 3, PixelFormat::Bgra8, bgra),
+            (0, 0, PixelFormat::Encoded, encoded),
+        ];
+
+        let mut last = None;
+        for (width, height, pixel_format, payload) in frames {
+            let sequence = writer
+                .write_frame(width, height, pixel_format, &payload)
+                .unwrap();
+            let (header, read) = expect_frame(&map(&file.0), last);
+            let from_file =
+                match snapshot_frame_file(&File::open(&file.0).unwrap(), last)
+                    .unwrap()
+                {
+                    FrameSnapshot::Frame(header, payload) => (header, payload),
+                    _ => panic!("expected a frame"),
+                };
+            assert_eq!(from_file, (header, read.clone()));
+            assert_eq!(header.sequence, sequence);
+            assert_eq!(header.version, MMAP_FRAME_VERSION);
+            assert_eq!(
+                (header.width, header.height, header.pixel_format),
+                (width, height, pixel_format)
+            );
+            assert_eq!(header.payload_len, payload.len() as u64);
+            assert_eq!(read, payload);
+            last = Some(sequence);
+        }
+    }
+
+    #[test]
+    fn reports_unchanged_and_busy() {
+        let file = TempFile::new("unchanged_busy");
+        let mut writer = MmapFrameWriter::create(&file.0, 12).unwrap();
+
+        // まだ1枚も書かれていない
+        assert!(matches!(
+            snapshot_frame(&map(&file.0), None).unwrap(),
+            FrameSnapshot::Unchanged
+        ));
+
+        let sequence =
+            writer.write_frame(2, 2, PixelFormat::Rgb8, &[7; 12]).unwrap();
+        assert!(matches!(
+            snapshot_frame(&map(&file.0), Some(sequence)).unwrap(),
+            FrameSnapshot::Unchanged
+        ));
+
+        assert!(matches!(
+            snapshot_frame_file(&File::open(&file.0).unwrap(), Some(sequence))
+                .unwrap(),
+            FrameSnapshot::Unchanged
+        ));
+
+        // 書き込みの途中で止まった状態を作る
+        let mut bytes = map_mut(&file.0);
+        bytes[LOCK_OFFSET..LOCK_OFFSET + 8]
+            .copy_from_slice(&3u64.to_le_bytes());
+        assert!(matches!(
+            snapshot_frame(&bytes, None).unwrap(),
+            FrameSnapshot::Busy
+        ));
+        assert!(matches!(
+            snapshot_frame_file(&File::open(&file.0).unwrap(), None).unwrap(),
+            FrameSnapshot::Busy
+        ));
+    }
+
+    #[test]
+    fn truncated_file_fails_without_touching_pages() {
+        let file = TempFile::new("truncated");
+        let mut writer = MmapFrameWriter::create(&file.0, 12).unwrap();
+        writer.write_frame(2, 2, PixelFormat::Rgb8, &[1; 12]).unwrap();
+
+        let reader = File::open(&file.0).unwrap();
+        // ペイロードの途中、ヘッダの途中の順に縮める
+        for len in [MMAP_FRAME_HEADER_LEN as u64 + 4, 20] {
+            OpenOptions::new()
+                .write(true)
+                .open(&file.0)
+                .unwrap()
+                .set_len(len)
+                .unwrap();
+            assert!(snapshot_frame_file(&reader, None).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_inconsistent_headers() {
+        let file = TempFile::new("invalid");
+        let mut writer = MmapFrameWriter::create(&file.0, 12).unwrap();
+        assert!(writer.write_frame(2, 2, PixelFormat::Rgb8, &[0; 11]).is_err());
+        writer.write_frame(2, 2, PixelFormat::Rgb8, &[0; 12]).unwrap();
+
+        let mut bytes = map_mut(&file.0);
+        let header = MmapFrameHeader::parse(&bytes).unwrap();
+
+        // 画素数と合わないpayload_len
+        MmapFrameHeader {
+            payload_len: 13,
+            ..header
+        }
+        .write(&mut bytes);
+        assert!(snapshot_frame(&bytes, None).is_err());
+
+        // マップより大きいpayload
+        MmapFrameHeader {
+            width: 100,
+            height: 100,
+            payload_len: 100 * 100 * 3,
+            ..header
+        }
+        .write(&mut bytes);
+        assert!(snapshot_frame(&bytes, None).is_err());
+
+        // 未知のバージョン
+        MmapFrameHeader {
+            version: MMAP_FRAME_VERSION + 1,
+            ..header
+        }
+        .write(&mut bytes);
+        assert!(snapshot_frame(&bytes, None).is_err());
+
+        // magicの無いファイル
+        bytes[..4].copy_from_slice(b"\x89PNG");
+        assert!(snapshot_frame(&bytes, None).is_err());
+    }
+
+    #[test]
+    fn reads_short_and_unaligned_slices_safely() {
+        let file = TempFile::new("unaligned");
+        let mut writer = MmapFrameWriter::create(&file.0, 12).unwrap();
+        writer.write_frame(2, 2, PixelFormat::Rgb8, &[5; 12]).unwrap();
+        let bytes = map(&file.0);
+
+        // 8バイト境界からずらしたコピーでも読める
+        let mut shifted = vec![0; bytes.len() + 1];
+        shifted[1..].copy_from_slice(&bytes);
+        let (header, payload) = expect_frame(&shifted[1..], None);
+        assert_eq!(header.payload_len, 12);
+        assert_eq!(payload, vec![5; 12]);
+
+        // ロックより短いスライスはエラーになる
+        for len in [0, LOCK_OFFSET + 4, MMAP_FRAME_HEADER_LEN - 1] {
+            assert!(snapshot_frame(&bytes[..len], None).is_err());
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use std::fs::File;
-use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use opencv::aruco::{calibrate_camera_charuco, calibrate_camera_charuco_def};
@@ -8,7 +11,6 @@ use opencv::prelude::MatTraitConst;
 use opencv::{
     aruco::interpolate_corners_charuco_def,
     core::{Point2f, Point3f, Scalar, Size, Vector},
-    imgcodecs,
     objdetect::{
         CharucoBoard, CharucoDetector, Dictionary, draw_detected_markers,
         draw_detected_markers_def, get_predefined_dictionary,
@@ -22,7 +24,11 @@ use opencv::{
 use opencv::core::Mat;
 use serde::{Deserialize, Serialize};
 
-use crate::{CameraStream, CameraStreamConfig, VideoSourceConfig};
+use crate::{
+    Blob, BlobDetectorConfig, CameraStream, CameraStreamConfig,
+    CharucoBoardConfig, Frame, FrameMeta, MmapStatusHandle, PlaybackHandle,
+    StreamStatusHandle, VideoSourceConfig,
+};
 
 #[derive(Clone, Debug)]
 pub struct CameraParameter {
@@ -31,22 +37,75 @@ pub struct CameraParameter {
 }
 
 // カメラパラメータ、歪みパラメータのMatはserdeでシリアライズできないので、
-// Vec<u8>に変換して保存する
+// Vec<f64>に変換して保存する
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "StoredCameraParameterNum")]
 pub struct CameraParameterNum {
-    pub camera_matrix: Vec<u8>,
-    pub dist_coeffs: Vec<u8>,
+    pub camera_matrix: Vec<f64>,
+    pub dist_coeffs: Vec<f64>,
+}
+
+// 以前はMatのバイト列(Vec<u8>)をそのまま保存していた。JSONではどちらも
+// 数値の配列になるので、camera_matrixの要素数(9か72)で形式を見分ける
+#[derive(Deserialize)]
+struct StoredCameraParameterNum {
+    camera_matrix: Vec<f64>,
+    dist_coeffs: Vec<f64>,
+}
+
+impl TryFrom<StoredCameraParameterNum> for CameraParameterNum {
+    type Error = String;
+
+    fn try_from(stored: StoredCameraParameterNum) -> Result<Self, String> {
+        const OLD_CAMERA_MATRIX_BYTES: usize = 9 * size_of::<f64>();
+
+        match stored.camera_matrix.len() {
+            9 => Ok(Self {
+                camera_matrix: stored.camera_matrix,
+                dist_coeffs: stored.dist_coeffs,
+            }),
+            OLD_CAMERA_MATRIX_BYTES => Ok(Self {
+                camera_matrix: f64s_from_old_bytes(&stored.camera_matrix)?,
+                dist_coeffs: f64s_from_old_bytes(&stored.dist_coeffs)?,
+            }),
+            len => Err(format!("camera_matrix must have 9 values, got {len}")),
+        }
+    }
+}
+
+fn f64s_from_old_bytes(values: &[f64]) -> Result<Vec<f64>, String> {
+    let bytes = values
+        .iter()
+        .map(|&v| {
+            if v.fract() == 0.0 && (0.0..=255.0).contains(&v) {
+                Ok(v as u8)
+            } else {
+                Err(format!("old parameter format: {v} is not a byte"))
+            }
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    if bytes.len() % size_of::<f64>() != 0 {
+        return Err(format!(
+            "old parameter format: {} bytes is not a multiple of 8",
+            bytes.len()
+        ));
+    }
+
+    // 旧形式はMatのバッファをそのまま書き出していたのでネイティブエンディアン
+    Ok(bytes
+        .chunks_exact(size_of::<f64>())
+        .map(|chunk| f64::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect())
 }
 
 impl TryInto<CameraParameterNum> for &CameraParameter {
     type Error = opencv::Error;
 
     fn try_into(self) -> opencv::Result<CameraParameterNum> {
-        let camera_matrix =
-            self.camera_matrix.data_bytes()?.iter().map(|f| *f).collect();
+        let camera_matrix = self.camera_matrix.data_typed::<f64>()?.to_vec();
 
-        let dist_coeffs =
-            self.dist_coeffs.data_bytes()?.iter().map(|f| *f).collect();
+        let dist_coeffs = self.dist_coeffs.data_typed::<f64>()?.to_vec();
 
         Ok(CameraParameterNum {
             camera_matrix,
@@ -63,9 +122,12 @@ impl TryInto<CameraParameter> for &CameraParameterNum {
             Mat::new_rows_cols_with_data(3, 3, self.camera_matrix.as_slice())?
                 .try_clone()?;
 
-        let dist_coeffs =
-            Mat::new_rows_cols_with_data(1, 5, self.dist_coeffs.as_slice())?
-                .try_clone()?;
+        let dist_coeffs = Mat::new_rows_cols_with_data(
+            1,
+            self.dist_coeffs.len() as i32,
+            self.dist_coeffs.as_slice(),
+        )?
+        .try_clone()?;
 
         Ok(CameraParameter {
             camera_matrix,
@@ -74,35 +136,144 @@ impl TryInto<CameraParameter> for &CameraParameterNum {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct CharucoMarker {
     pub marker_corners: Vector<Vector<Point2f>>,
     pub marker_ids: Vector<i32>,
     pub charuco_corners: Mat,
     pub charuco_ids: Mat,
+    // 検出に使ったフレーム
+    pub meta: FrameMeta,
+}
+
+/// A frame together with the detections computed on it, published by the
+/// detector thread as one unit so consumers never mix up frames.
+#[derive(Clone, Debug, Default)]
+pub struct DetectedFrame {
+    pub frame: Frame,
+    pub charuco_marker: CharucoMarker,
+    pub blobs: Vec<Blob>,
+}
+
+// ワーカースレッドで毎フレーム実行する検出器
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum MarkerDetectorConfig {
+    #[default]
+    Charuco,
+    Blob(BlobDetectorConfig),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct OpenCvCameraConfig {
     pub camera_stream_config: CameraStreamConfig,
-    // pub charuco_board: CharucoBoard,
+    #[serde(default)]
+    pub charuco_board: CharucoBoardConfig,
+    #[serde(default)]
+    pub marker_detector: MarkerDetectorConfig,
+}
+
+// 新しいフレームを待つ間にstopを確認する間隔
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+// 平均の検出時間の平滑化係数
+const STATS_SMOOTHING: f64 = 0.1;
+
+/// Timing of the detector thread of one camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DetectorStats {
+    pub frames_processed: u64,
+    // 検出が追いつかず処理しないまま次のフレームに置き換わった数
+    pub frames_skipped: u64,
+    // 検出に失敗したフレームの数
+    pub frames_failed: u64,
+    // 直近のフレームの検出時間 [ms]
+    pub detection_ms: f64,
+    // 検出時間の指数移動平均 [ms]
+    pub mean_detection_ms: f64,
+    // キャプチャから検出結果を出すまで [ms]
+    pub latency_ms: f64,
+}
+
+impl DetectorStats {
+    fn add(
+        &mut self,
+        frame: &FrameMeta,
+        previous: Option<&FrameMeta>,
+        detection: Duration,
+        failed: bool,
+    ) {
+        let detection_ms = detection.as_secs_f64() * 1000.0;
+        self.mean_detection_ms = if self.frames_processed == 0 {
+            detection_ms
+        } else {
+            self.mean_detection_ms
+                + (detection_ms - self.mean_detection_ms) * STATS_SMOOTHING
+        };
+        self.frames_processed += 1;
+        self.frames_skipped += previous.map_or(0, |p| frame.dropped_since(p));
+        self.frames_failed += u64::from(failed);
+        self.detection_ms = detection_ms;
+        self.latency_ms = frame.age().as_secs_f64() * 1000.0;
+    }
+}
+
+// 検出スレッド。OpenCvCameraの複製がすべてdropされるかcloseで止まる
+#[derive(Debug)]
+struct DetectorThread {
+    stop: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl DetectorThread {
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.lock().unwrap().take()
+            && handle.join().is_err()
+        {
+            eprintln!("Detector thread panicked");
+        }
+    }
+}
+
+impl Drop for DetectorThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct OpenCvCamera {
     // pub stream: CameraStream,
     pub charuco_board: CharucoBoard,
+    pub charuco_board_config: CharucoBoardConfig,
+    pub marker_detector_config: MarkerDetectorConfig,
     // charuco_detector: CharucoDetector,
-    r: tokio::sync::watch::Receiver<Mat>,
+    r: tokio::sync::watch::Receiver<Frame>,
+    // 検出を経ていない、ソースから読んだままのフレーム
+    r_source: tokio::sync::watch::Receiver<Frame>,
     r_charuco_markers: tokio::sync::watch::Receiver<CharucoMarker>,
+    r_blobs: tokio::sync::watch::Receiver<Vec<Blob>>,
+    r_detected_frame: tokio::sync::watch::Receiver<DetectedFrame>,
+    s_charuco_board_config: tokio::sync::watch::Sender<CharucoBoardConfig>,
+    s_marker_detector_config: tokio::sync::watch::Sender<MarkerDetectorConfig>,
     pub camera_stream_config: CameraStreamConfig,
+    // 再生ソースの場合のみ
+    pub playback: Option<PlaybackHandle>,
+    // Unityカメラ(MMAP)の場合のみ
+    pub mmap_status: Option<MmapStatusHandle>,
+    pub stream_status: StreamStatusHandle,
+    detector: Arc<DetectorThread>,
+    stats: Arc<Mutex<DetectorStats>>,
 }
 
 impl TryFrom<OpenCvCameraConfig> for OpenCvCamera {
     type Error = anyhow::Error;
 
     fn try_from(config: OpenCvCameraConfig) -> Result<Self, Self::Error> {
-        Ok(OpenCvCamera::new(config.camera_stream_config.try_into()?))
+        OpenCvCamera::new(
+            config.camera_stream_config.try_into()?,
+            config.charuco_board,
+            config.marker_detector,
+        )
     }
 }
 
@@ -110,117 +281,283 @@ impl From<&OpenCvCamera> for OpenCvCameraConfig {
     fn from(camera: &OpenCvCamera) -> Self {
         Self {
             camera_stream_config: camera.camera_stream_config.clone(),
+            charuco_board: camera.charuco_board_config.clone(),
+            marker_detector: camera.marker_detector_config.clone(),
         }
     }
 }
 
 impl OpenCvCamera {
-    pub fn new(stream: CameraStream) -> Self {
+    pub fn new(
+        stream: CameraStream,
+        charuco_board_config: CharucoBoardConfig,
+        marker_detector_config: MarkerDetectorConfig,
+    ) -> Result<Self> {
         let camera_stream_config = (&stream).into();
+        let playback = stream.playback.clone();
+        let mmap_status = stream.mmap_status.clone();
+        let stream_status = stream.status.clone();
 
-        let charuco_board = CharucoBoard::new_def(
-            Size::new(3, 3),
-            0.3,
-            0.15,
-            &get_predefined_dictionary(
-                opencv::objdetect::PredefinedDictionaryType::DICT_6X6_250,
-            )
-            .unwrap(),
-        )
-        .unwrap();
-
-        // println!("here");
-
-        // https://docs.opencv.org/4.x/df/d4a/tutorial_charuco_detection.html
-        let mut img = Mat::default();
-        charuco_board
-            .generate_image_def(
-                Size::new(
-                    (charuco_board.get_chessboard_size().unwrap().width as f32
-                        * charuco_board.get_square_length().unwrap() as f32
-                        * 1000.0
-                        * 13.8)
-                        .floor() as i32,
-                    (charuco_board.get_chessboard_size().unwrap().height as f32
-                        * charuco_board.get_square_length().unwrap() as f32
-                        * 1000.0
-                        * 13.8)
-                        .floor() as i32,
-                ),
-                &mut img,
-            )
-            .unwrap();
-
-        let charuco_board_clone = charuco_board.clone();
-        let charuco_detector = CharucoDetector::new_def(&charuco_board_clone)
-            .expect("Failed to create charuco detector");
-
-        let (s, r) = tokio::sync::watch::channel(Mat::default());
+        let charuco_board = charuco_board_config.build()?;
+
+        let (s, r) = tokio::sync::watch::channel(Frame::default());
         let (s_charuco_markers, r_charuco_markers) =
-            tokio::sync::watch::channel(CharucoMarker {
-                marker_corners: Vector::new(),
-                marker_ids: Vector::new(),
-                charuco_corners: Mat::default(),
-                charuco_ids: Mat::default(),
-            });
-
-        thread::spawn(move || {
-            let charuco_detector =
-                CharucoDetector::new_def(&charuco_board_clone)
-                    .expect("Failed to create charuco detector");
-
-            loop {
-                Self::update(
-                    &stream,
+            tokio::sync::watch::channel(CharucoMarker::default());
+        let (s_blobs, r_blobs) = tokio::sync::watch::channel(Vec::new());
+        let (s_detected_frame, r_detected_frame) =
+            tokio::sync::watch::channel(DetectedFrame::default());
+        let (s_charuco_board_config, mut r_charuco_board_config) =
+            tokio::sync::watch::channel(charuco_board_config.clone());
+        let (s_marker_detector_config, mut r_marker_detector_config) =
+            tokio::sync::watch::channel(marker_detector_config.clone());
+
+        let mut charuco_board_clone = charuco_board.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(Mutex::new(DetectorStats::default()));
+
+        // 失敗をスレッド内のpanicにせず呼び出し元に返せるよう先に作る
+        let mut charuco_detector =
+            CharucoDetector::new_def(&charuco_board_clone)?;
+        // watchのchangedを待つためだけのランタイム
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()?;
+
+        let r_source = stream.subscribe();
+        let stop_clone = stop.clone();
+        let stats_clone = stats.clone();
+        let handle = thread::spawn(move || {
+            let mut r_frame = stream.subscribe();
+            let mut previous: Option<FrameMeta> = None;
+            // 同じ失敗を毎フレーム表示しないよう、変わったときだけ出す
+            let mut last_error: Option<String> = None;
+
+            while !stop_clone.load(Ordering::Relaxed) {
+                // 新しいフレームが来るまで眠る。stopを見るために時々起きる
+                match runtime.block_on(async {
+                    tokio::time::timeout(STOP_CHECK_INTERVAL, r_frame.changed())
+                        .await
+                }) {
+                    Ok(Ok(())) => {}
+                    Err(_) => continue,
+                    // キャプチャスレッドが終了した
+                    Ok(Err(_)) => break,
+                }
+                let frame = r_frame.borrow_and_update().clone();
+
+                // ボード設定が変更されたら検出器を作り直す
+                if r_charuco_board_config.has_changed().unwrap_or(false) {
+                    let config =
+                        r_charuco_board_config.borrow_and_update().clone();
+                    match config.build().and_then(|board| {
+                        let detector = CharucoDetector::new_def(&board)?;
+                        Ok((board, detector))
+                    }) {
+                        Ok((board, detector)) => {
+                            charuco_board_clone = board;
+                            charuco_detector = detector;
+                        }
+                        Err(err) => {
+                            eprintln!(
+                                "Failed to rebuild charuco detector: {err}"
+                            );
+                        }
+                    }
+                }
+
+                let marker_detector =
+                    r_marker_detector_config.borrow_and_update().clone();
+
+                let meta = frame.meta;
+                let (detection, error) = Self::update(
+                    frame,
+                    &marker_detector,
                     &charuco_detector,
                     &charuco_board_clone,
                     &s,
                     &s_charuco_markers,
+                    &s_blobs,
+                    &s_detected_frame,
                 );
-
-                // thread::sleep(Duration::from_millis(10));
+                stats_clone.lock().unwrap().add(
+                    &meta,
+                    previous.as_ref(),
+                    detection,
+                    error.is_some(),
+                );
+                previous = Some(meta);
+                if error != last_error {
+                    if let Some(err) = &error {
+                        eprintln!("{err}");
+                    }
+                    last_error = error;
+                }
             }
+            // streamのdropでキャプチャスレッドもjoinされ、デバイスが閉じる
+            drop(stream);
         });
 
-        Self {
+        Ok(Self {
             // stream,
             charuco_board: charuco_board,
+            charuco_board_config,
+            marker_detector_config,
             r,
+            r_source,
             r_charuco_markers,
+            r_blobs,
+            r_detected_frame,
+            s_charuco_board_config,
+            s_marker_detector_config,
             camera_stream_config,
-        }
+            playback,
+            mmap_status,
+            stream_status,
+            detector: Arc::new(DetectorThread {
+                stop,
+                handle: Mutex::new(Some(handle)),
+            }),
+            stats,
+        })
+    }
+
+    /// Stops the detector and capture threads and releases the source, for
+    /// this camera and all its clones. Returns once the device can be
+    /// opened again.
+    pub fn close(&self) {
+        self.detector.stop();
+    }
+
+    /// Replaces the board used for detection and calibration.
+    /// The detector thread picks the new board up on its next iteration.
+    pub fn set_charuco_board_config(
+        &mut self,
+        config: CharucoBoardConfig,
+    ) -> Result<()> {
+        self.charuco_board = config.build()?;
+        self.charuco_board_config = config.clone();
+        self.s_charuco_board_config.send_replace(config);
+        Ok(())
+    }
+
+    /// Switches the detector stage run on every frame.
+    pub fn set_marker_detector_config(&mut self, config: MarkerDetectorConfig) {
+        self.marker_detector_config = config.clone();
+        self.s_marker_detector_config.send_replace(config);
+    }
+
+    pub fn detector_stats(&self) -> DetectorStats {
+        *self.stats.lock().unwrap()
     }
 
-    pub fn get_latest_frame(&self) -> Mat {
+    pub fn get_latest_frame(&self) -> Frame {
         self.r.borrow().clone()
     }
 
+    /// The latest frame together with the detections computed on it.
+    pub fn get_latest_detected_frame(&self) -> DetectedFrame {
+        self.r_detected_frame.borrow().clone()
+    }
+
     pub fn get_latest_charuco_markers(&self) -> CharucoMarker {
         self.r_charuco_markers.borrow().clone()
     }
 
+    pub fn get_latest_blobs(&self) -> Vec<Blob> {
+        self.r_blobs.borrow().clone()
+    }
+
+    /// Receiver of every frame read from the source, including frames the
+    /// detector skips.
+    pub fn subscribe_source_frames(
+        &self,
+    ) -> tokio::sync::watch::Receiver<Frame> {
+        self.r_source.clone()
+    }
+
+    /// Receiver of every frame and its detections. Each frame is published
+    /// once, but frames arriving while the detector is busy are skipped.
+    pub fn subscribe_detected_frames(
+        &self,
+    ) -> tokio::sync::watch::Receiver<DetectedFrame> {
+        self.r_detected_frame.clone()
+    }
+
+    // 1フレーム分の検出を行って結果を送る。検出にかかった時間と失敗の理由を返す
+    #[allow(clippy::too_many_arguments)]
     fn update(
-        stream: &CameraStream,
+        frame: Frame,
+        marker_detector: &MarkerDetectorConfig,
         charuco_detector: &CharucoDetector,
         charuco_board: &CharucoBoard,
-        s: &tokio::sync::watch::Sender<Mat>,
+        s: &tokio::sync::watch::Sender<Frame>,
         s_charuco_markers: &tokio::sync::watch::Sender<CharucoMarker>,
-    ) {
-        let mut frame = stream.get_latest_image();
-
-        let (marker_corners, marker_ids, charuco_corners, charuco_ids) =
-            Self::detect_charuco(&frame, charuco_detector, charuco_board)
-                .expect("Failed to detect charuco");
-
-        s.send(frame).expect("Failed to send frame");
-        s_charuco_markers
-            .send(CharucoMarker {
-                marker_corners,
-                marker_ids,
-                charuco_corners,
-                charuco_ids,
-            })
-            .expect("Failed to send charuco markers");
+        s_blobs: &tokio::sync::watch::Sender<Vec<Blob>>,
+        s_detected_frame: &tokio::sync::watch::Sender<DetectedFrame>,
+    ) -> (Duration, Option<String>) {
+        let started = Instant::now();
+        let mut error = None;
+
+        // 選ばれていない検出器の結果は空にしておく
+        let (charuco_marker, blobs) = match marker_detector {
+            MarkerDetectorConfig::Charuco => {
+                let charuco_marker = match Self::detect_charuco(
+                    &frame.image,
+                    charuco_detector,
+                    charuco_board,
+                ) {
+                    Ok((
+                        marker_corners,
+                        marker_ids,
+                        charuco_corners,
+                        charuco_ids,
+                    )) => CharucoMarker {
+                        marker_corners,
+                        marker_ids,
+                        charuco_corners,
+                        charuco_ids,
+                        meta: frame.meta,
+                    },
+                    // 1フレームの失敗で検出スレッドを止めない
+                    Err(err) => {
+                        error =
+                            Some(format!("Failed to detect charuco: {err}"));
+                        CharucoMarker {
+                            meta: frame.meta,
+                            ..Default::default()
+                        }
+                    }
+                };
+                (charuco_marker, Vec::new())
+            }
+            MarkerDetectorConfig::Blob(config) => {
+                let blobs = config.detect(&frame.image).unwrap_or_else(|err| {
+                    error = Some(format!("Failed to detect blobs: {err}"));
+                    Vec::new()
+                });
+                let charuco_marker = CharucoMarker {
+                    meta: frame.meta,
+                    ..Default::default()
+                };
+                (charuco_marker, blobs)
+            }
+        };
+
+        let detection = started.elapsed();
+
+        let detected_frame = DetectedFrame {
+            frame: frame.clone(),
+            charuco_marker: charuco_marker.clone(),
+            blobs: blobs.clone(),
+        };
+
+        // OpenCvCameraがdropされて受け手が居なくなっても止まらないよう
+        // send_replaceを使う。終了はstopで行う
+        s.send_replace(frame);
+        s_charuco_markers.send_replace(charuco_marker);
+        s_blobs.send_replace(blobs);
+        s_detected_frame.send_replace(detected_frame);
+        (detection, error)
     }
 
     pub fn detect_charuco(
@@ -283,8 +620,3 @@ impl OpenCvCamera {
         }
     }
 }
-
-fn save_mat_as_image(mat: Mat) {
-    imgcodecs::imwrite_def("charuco.png", &mat).unwrap();
-    println!("Saved charuco.png");
-}
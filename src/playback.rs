@@ -0,0 +1,297 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use opencv::core::Mat;
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{self, VideoCapture, VideoCaptureTrait};
+
+use crate::{SESSION_MANIFEST, Session, SourceFrame, VideoSourceError};
+
+// 動画ファイルにfpsが入っていない場合の既定値
+const FALLBACK_FPS: f64 = 30.0;
+// 待機中もコマンドに反応できるよう、1回のreadで眠る上限
+const MAX_WAIT: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackCommand {
+    Play,
+    Pause,
+    SetLooping(bool),
+    // 再生位置 [s] に移動する
+    Seek(f64),
+    // 一時停止してフレーム単位で進める・戻す
+    Step(i64),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlaybackStatus {
+    pub playing: bool,
+    pub looping: bool,
+    // 次に読むフレーム番号
+    pub position: usize,
+    pub frame_count: usize,
+    // 最後に出したフレームの時刻 [s]
+    pub time: f64,
+    pub duration: f64,
+}
+
+#[derive(Debug, Default)]
+struct PlaybackShared {
+    commands: Vec<PlaybackCommand>,
+    status: PlaybackStatus,
+}
+
+/// Controls a [`PlaybackSource`] from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct PlaybackHandle(Arc<Mutex<PlaybackShared>>);
+
+impl PlaybackHandle {
+    /// Queues a command; the source applies it on its next read.
+    pub fn send(&self, command: PlaybackCommand) {
+        self.0.lock().unwrap().commands.push(command);
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        self.0.lock().unwrap().status
+    }
+}
+
+// 一時停止中は止まる再生時刻
+struct Clock {
+    origin: Instant,
+    at: f64,
+    running: bool,
+}
+
+impl Clock {
+    fn now(&self) -> f64 {
+        if self.running {
+            self.at + self.origin.elapsed().as_secs_f64()
+        } else {
+            self.at
+        }
+    }
+
+    fn set(&mut self, at: f64, running: bool) {
+        self.origin = Instant::now();
+        self.at = at;
+        self.running = running;
+    }
+}
+
+/// Replays a recorded session camera or a plain video file, pacing frames
+/// by their recorded timestamps.
+pub struct PlaybackSource {
+    capture: VideoCapture,
+    // 各フレームの再生時刻 [s]
+    timestamps: Vec<f64>,
+    position: usize,
+    looping: bool,
+    clock: Clock,
+    handle: PlaybackHandle,
+}
+
+impl PlaybackSource {
+    /// Opens `path`, which is either a session directory (or its
+    /// `session.json`), of which `camera` is replayed, or a video file.
+    pub fn open(
+        path: impl AsRef<Path>,
+        camera: usize,
+        looping: bool,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let session_dir = if path.is_dir() {
+            Some(path)
+        } else if path.file_name().is_some_and(|n| n == SESSION_MANIFEST) {
+            path.parent()
+        } else {
+            None
+        };
+
+        let (video_path, timestamps) = match session_dir {
+            Some(dir) => {
+                let session = Session::open(dir)?;
+                let timestamps = session
+                    .frame_records(camera)?
+                    .iter()
+                    .map(|r| r.timestamp)
+                    .collect();
+                (session.video_path(camera)?, Some(timestamps))
+            }
+            None => (path.to_path_buf(), None),
+        };
+
+        let capture = VideoCapture::from_file(
+            &video_path.to_string_lossy(),
+            videoio::CAP_ANY,
+        )?;
+        if !capture.is_opened()? {
+            return Err(anyhow!(
+                "failed to open video '{}'",
+                video_path.display()
+            ));
+        }
+
+        // 素の動画ファイルはfpsから等間隔の時刻を割り当てる
+        let timestamps = match timestamps {
+            Some(timestamps) => timestamps,
+            None => {
+                let fps = capture.get(videoio::CAP_PROP_FPS)?;
+                let fps = if fps > 0.0 { fps } else { FALLBACK_FPS };
+                let count = capture.get(videoio::CAP_PROP_FRAME_COUNT)?;
+                (0..count.max(0.0) as usize).map(|i| i as f64 / fps).collect()
+            }
+        };
+        if timestamps.is_empty() {
+            return Err(anyhow!("'{}' has no frames", path.display()));
+        }
+
+        let source = Self {
+            capture,
+            timestamps,
+            position: 0,
+            looping,
+            clock: Clock {
+                origin: Instant::now(),
+                at: 0.0,
+                running: true,
+            },
+            handle: PlaybackHandle::default(),
+        };
+        source.publish_status(0.0);
+        Ok(source)
+    }
+
+    pub fn handle(&self) -> PlaybackHandle {
+        self.handle.clone()
+    }
+
+    /// Reads the next frame once it is due. The frame carries its recorded
+    /// time, which is used instead of the read time to synchronize cameras.
+    pub fn read(&mut self) -> Result<SourceFrame, VideoSourceError> {
+        let commands =
+            std::mem::take(&mut self.handle.0.lock().unwrap().commands);
+        // シークとステップは再生状態にかかわらず即座に1フレーム出す
+        let mut emit_now = false;
+        for command in commands {
+            match command {
+                PlaybackCommand::Play => {
+                    if self.position >= self.timestamps.len() {
+                        self.seek_frame(0)?;
+                    }
+                    let now = self.clock.now();
+                    self.clock.set(now, true);
+                }
+                PlaybackCommand::Pause => {
+                    let now = self.clock.now();
+                    self.clock.set(now, false);
+                }
+                PlaybackCommand::SetLooping(looping) => self.looping = looping,
+                PlaybackCommand::Seek(time) => {
+                    let frame = self.timestamps.partition_point(|t| *t < time);
+                    self.seek_frame(frame)?;
+                    emit_now = true;
+                }
+                PlaybackCommand::Step(delta) => {
+                    // positionは次のフレームなので、今出ているのは1つ前
+                    let current = self.position as i64 - 1;
+                    let frame = (current + delta).max(0) as usize;
+                    self.seek_frame(frame)?;
+                    self.clock.running = false;
+                    emit_now = true;
+                }
+            }
+        }
+
+        if self.position >= self.timestamps.len() {
+            if !self.looping {
+                let now = self.clock.now();
+                self.clock.set(now, false);
+                self.publish_status(now);
+                thread::sleep(MAX_WAIT);
+                return Err(VideoSourceError::NotReady);
+            }
+            self.seek_frame(0)?;
+        }
+
+        if !emit_now {
+            if !self.clock.running {
+                thread::sleep(MAX_WAIT);
+                return Err(VideoSourceError::NotReady);
+            }
+            let wait = self.timestamps[self.position] - self.clock.now();
+            if wait > 0.0 {
+                thread::sleep(Duration::from_secs_f64(wait).min(MAX_WAIT));
+                return Err(VideoSourceError::NotReady);
+            }
+            // デコードが間に合わない場合は時刻を過ぎたフレームを読み飛ばす
+            while self.position + 1 < self.timestamps.len()
+                && self.timestamps[self.position + 1] <= self.clock.now()
+            {
+                if !self.capture.grab()? {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+
+        let mut frame = Mat::default();
+        let success = VideoCaptureTrait::read(&mut self.capture, &mut frame)?;
+        if !success || frame.empty() {
+            // 1枚もデコードできない動画は読み直しても変わらない
+            if self.position == 0 {
+                return Err(VideoSourceError::Read(
+                    "failed to decode the first frame".to_owned(),
+                ));
+            }
+            // フレーム数の申告が実際より多い動画がある
+            self.timestamps.truncate(self.position);
+            thread::sleep(MAX_WAIT);
+            return Err(VideoSourceError::NotReady);
+        }
+        let time = self.timestamps[self.position];
+        self.position += 1;
+        if emit_now {
+            self.clock.set(time, self.clock.running);
+        }
+        self.publish_status(time);
+
+        let mut rgb_frame = Mat::default();
+        imgproc::cvt_color(
+            &frame,
+            &mut rgb_frame,
+            imgproc::COLOR_BGR2RGB,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
+
+        Ok(SourceFrame {
+            image: rgb_frame,
+            ground_truth: None,
+            recorded_at: Duration::try_from_secs_f64(time).ok(),
+        })
+    }
+
+    fn seek_frame(&mut self, frame: usize) -> opencv::Result<()> {
+        let frame = frame.min(self.timestamps.len() - 1);
+        self.capture.set(videoio::CAP_PROP_POS_FRAMES, frame as f64)?;
+        self.position = frame;
+        self.clock.set(self.timestamps[frame], self.clock.running);
+        Ok(())
+    }
+
+    fn publish_status(&self, time: f64) {
+        self.handle.0.lock().unwrap().status = PlaybackStatus {
+            playing: self.clock.running,
+            looping: self.looping,
+            position: self.position,
+            frame_count: self.timestamps.len(),
+            time,
+            duration: self.timestamps.last().copied().unwrap_or(0.0),
+        };
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+use opencv::core::{
+    Mat, MatTraitConst, MatTraitConstManual, Point2f, Size, VectorToVec,
+};
+use opencv::imgproc;
+use opencv::videoio::{VideoWriter, VideoWriterTrait, VideoWriterTraitConst};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    Blob, CharucoMarker, DetectedFrame, Frame, VideoSourceConfig, WorkLoad,
+    WorkLoadConfig,
+};
+
+pub const SESSION_MANIFEST: &str = "session.json";
+// 2からは検出結果が検出したフレームの分だけになり、frameで動画と対応を取る
+pub const SESSION_VERSION: u32 = 2;
+
+// 動画ファイルに書き込むfps。実際の撮影時刻はフレームインデックスに残す
+const NOMINAL_FPS: f64 = 30.0;
+// 新しいフレームを待つ間にstopを確認する間隔
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+// 検出結果を待つフレームの数の上限。これより遅れた検出は書かない
+const MAX_PENDING_DETECTIONS: usize = 256;
+// 同じ名前のセッションがあるときに付ける番号の上限
+const MAX_SESSION_DIR_ATTEMPTS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionCamera {
+    pub name: String,
+    pub video_source_config: VideoSourceConfig,
+    // 以下はセッションディレクトリからの相対パス
+    pub video: String,
+    pub frames: String,
+    pub detections: String,
+}
+
+/// Contents of `session.json`, which describes everything else in a
+/// session directory.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionManifest {
+    pub version: u32,
+    pub created_at: String,
+    pub nominal_fps: f64,
+    // cameras[i]はworkload.opencv_cams[i]に対応する
+    pub cameras: Vec<SessionCamera>,
+    // 収録開始時点のカメラ設定とキャリブレーション
+    pub workload: WorkLoadConfig,
+}
+
+/// One line of a camera's frame index.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FrameRecord {
+    // 動画ファイル内のフレーム番号
+    pub frame: usize,
+    // キャプチャスレッドの通し番号。飛んでいればその間のフレームは落ちている
+    pub sequence: u64,
+    // 収録開始からの経過時間 [s]
+    pub timestamp: f64,
+    // UNIX時刻 [s]
+    pub wall_clock: f64,
+}
+
+/// One line of a camera's detection log. `frame` is the video frame the
+/// detections were computed on.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FrameDetections {
+    pub frame: usize,
+    pub charuco_ids: Vec<i32>,
+    pub charuco_corners: Vec<[f32; 2]>,
+    pub marker_ids: Vec<i32>,
+    pub marker_corners: Vec<Vec<[f32; 2]>>,
+    pub blobs: Vec<Blob>,
+}
+
+impl FrameDetections {
+    fn new(
+        frame: usize,
+        charuco_marker: &CharucoMarker,
+        blobs: &[Blob],
+    ) -> opencv::Result<Self> {
+        let point = |p: &Point2f| [p.x, p.y];
+        let (charuco_ids, charuco_corners) =
+            if charuco_marker.charuco_ids.empty() {
+                (Vec::new(), Vec::new())
+            } else {
+                (
+                    charuco_marker.charuco_ids.data_typed::<i32>()?.to_vec(),
+                    charuco_marker
+                        .charuco_corners
+                        .data_typed::<Point2f>()?
+                        .iter()
+                        .map(point)
+                        .collect(),
+                )
+            };
+
+        Ok(Self {
+            frame,
+            charuco_ids,
+            charuco_corners,
+            marker_ids: charuco_marker.marker_ids.to_vec(),
+            marker_corners: charuco_marker
+                .marker_corners
+                .iter()
+                .map(|corners| corners.iter().map(|p| point(&p)).collect())
+                .collect(),
+            blobs: blobs.to_vec(),
+        })
+    }
+}
+
+/// Number of frames written per camera, in manifest order.
+#[derive(Clone, Debug)]
+pub struct RecordingSummary {
+    pub dir: PathBuf,
+    pub frame_counts: Vec<usize>,
+}
+
+/// Records every camera of a workload into a new session directory:
+///
+/// - `session.json`: the [`SessionManifest`]
+/// - `cam<i>.avi`: MJPG video
+/// - `cam<i>.frames.jsonl`: one [`FrameRecord`] per video frame
+/// - `cam<i>.detections.jsonl`: one [`FrameDetections`] per video frame
+///   the detector processed
+///
+/// Each camera is written by its own thread until [`Self::stop`] is called
+/// or the recorder is dropped.
+pub struct SessionRecorder {
+    dir: PathBuf,
+    stop: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<Result<usize>>>,
+}
+
+impl SessionRecorder {
+    /// Creates `root/session_<local time>` and starts recording. A
+    /// suffix is added when that directory already exists.
+    pub fn start(root: impl AsRef<Path>, workload: &WorkLoad) -> Result<Self> {
+        if workload.opencv_cams.is_empty() {
+            return Err(anyhow!("no camera to record"));
+        }
+
+        let now = chrono::Local::now();
+        let dir = create_session_dir(
+            root.as_ref(),
+            &format!("session_{}", now.format("%Y%m%d_%H%M%S_%3f")),
+        )?;
+
+        let cameras: Vec<SessionCamera> = workload
+            .opencv_cams
+            .iter()
+            .enumerate()
+            .map(|(i, cam)| {
+                let stream = &cam.opencv_camera.camera_stream_config;
+                SessionCamera {
+                    name: stream.name.clone(),
+                    video_source_config: stream.video_source_config.clone(),
+                    video: format!("cam{i}.avi"),
+                    frames: format!("cam{i}.frames.jsonl"),
+                    detections: format!("cam{i}.detections.jsonl"),
+                }
+            })
+            .collect();
+
+        let manifest = SessionManifest {
+            version: SESSION_VERSION,
+            created_at: now.to_rfc3339(),
+            nominal_fps: NOMINAL_FPS,
+            cameras: cameras.clone(),
+            workload: workload.into(),
+        };
+        let file = File::create(dir.join(SESSION_MANIFEST))
+            .context("failed to create session manifest")?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &manifest)
+            .context("failed to write session manifest")?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
+        let workers = cameras
+            .into_iter()
+            .zip(&workload.opencv_cams)
+            .map(|(camera, cam)| {
+                let r_frame = cam.opencv_camera.subscribe_source_frames();
+                let r_detected = cam.opencv_camera.subscribe_detected_frames();
+                let dir = dir.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    record_camera(
+                        &dir, &camera, started, r_frame, r_detected, &stop,
+                    )
+                })
+            })
+            .collect();
+
+        Ok(Self { dir, stop, workers })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stops every writer thread and finalizes the files.
+    pub fn stop(mut self) -> Result<RecordingSummary> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<RecordingSummary> {
+        self.stop.store(true, Ordering::Relaxed);
+        // 1台が失敗しても残りのスレッドは必ずjoinする
+        let results: Vec<Result<usize>> = self
+            .workers
+            .drain(..)
+            .map(|worker| {
+                worker
+                    .join()
+                    .map_err(|_| anyhow!("session writer thread panicked"))?
+            })
+            .collect();
+        Ok(RecordingSummary {
+            dir: self.dir.clone(),
+            frame_counts: results.into_iter().collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl Drop for SessionRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Failed to finish recording: {err}");
+        }
+    }
+}
+
+// 既存のセッションに書き込まないよう、新しく作れた名前を使う
+fn create_session_dir(root: &Path, name: &str) -> Result<PathBuf> {
+    fs::create_dir_all(root).with_context(|| {
+        format!("failed to create sessions directory '{}'", root.display())
+    })?;
+    for attempt in 1..=MAX_SESSION_DIR_ATTEMPTS {
+        let dir = match attempt {
+            1 => root.join(name),
+            n => root.join(format!("{name}_{n}")),
+        };
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(anyhow::Error::new(err).context(format!(
+                    "failed to create session directory '{}'",
+                    dir.display()
+                )));
+            }
+        }
+    }
+    Err(anyhow!(
+        "session directory '{}' already exists",
+        root.join(name).display()
+    ))
+}
+
+// 1台分の書き込みループ。ソースのフレームをすべて動画に書き、
+// 検出結果は検出スレッドが処理したフレームの分だけ書く。
+// 書き込んだフレーム数を返す
+fn record_camera(
+    dir: &Path,
+    camera: &SessionCamera,
+    started: Instant,
+    mut r_frame: watch::Receiver<Frame>,
+    mut r_detected: watch::Receiver<DetectedFrame>,
+    stop: &AtomicBool,
+) -> Result<usize> {
+    let mut frames = BufWriter::new(File::create(dir.join(&camera.frames))?);
+    let mut detections =
+        BufWriter::new(File::create(dir.join(&camera.detections))?);
+    let mut writer: Option<(VideoWriter, Size)> = None;
+    // 検出結果を待っているフレームの(sequence, 動画内のフレーム番号)
+    let mut pending: VecDeque<(u64, usize)> = VecDeque::new();
+    let mut last_sequence = None;
+    let mut detector_running = true;
+    let mut count = 0;
+
+    // watchのchangedを待つためだけのランタイム
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("failed to build session writer runtime")?;
+
+    while !stop.load(Ordering::Relaxed) {
+        // どちらかに新しいフレームが来るまで眠る。stopを見るために時々起きる。
+        // changedは届いた値を既読にするので、下で読めるよう未読に戻す
+        let waited = runtime.block_on(async {
+            tokio::time::timeout(STOP_CHECK_INTERVAL, async {
+                tokio::select! {
+                    changed = r_frame.changed() => {
+                        r_frame.mark_changed();
+                        changed.is_ok()
+                    }
+                    changed = r_detected.changed(), if detector_running => {
+                        r_detected.mark_changed();
+                        detector_running = changed.is_ok();
+                        true
+                    }
+                }
+            })
+            .await
+        });
+        match waited {
+            Ok(true) => {}
+            Err(_) => continue,
+            // キャプチャスレッドが終了した
+            Ok(false) => break,
+        }
+
+        if r_frame.has_changed().unwrap_or(false) {
+            let frame = r_frame.borrow_and_update().clone();
+            let meta = frame.meta;
+            if !frame.is_empty() && last_sequence != Some(meta.sequence) {
+                last_sequence = Some(meta.sequence);
+                if write_video_frame(dir, camera, &mut writer, &frame)? {
+                    let record = FrameRecord {
+                        frame: count,
+                        sequence: meta.sequence,
+                        timestamp: meta
+                            .captured_at
+                            .saturating_duration_since(started)
+                            .as_secs_f64(),
+                        wall_clock: meta
+                            .wall_clock
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0.0, |d| d.as_secs_f64()),
+                    };
+                    write_line(&mut frames, &record)?;
+                    pending.push_back((meta.sequence, count));
+                    if pending.len() > MAX_PENDING_DETECTIONS {
+                        pending.pop_front();
+                    }
+                    count += 1;
+                }
+            }
+        }
+
+        if detector_running && r_detected.has_changed().unwrap_or(false) {
+            let (sequence, line) = {
+                let detected = r_detected.borrow_and_update();
+                let sequence = detected.frame.meta.sequence;
+                // 検出はキャプチャより遅れるので、先に書いたフレームに対応付ける
+                let line = pending
+                    .iter()
+                    .find(|(s, _)| *s == sequence)
+                    .map(|&(_, frame)| {
+                        FrameDetections::new(
+                            frame,
+                            &detected.charuco_marker,
+                            &detected.blobs,
+                        )
+                    })
+                    .transpose()?;
+                (sequence, line)
+            };
+            if let Some(line) = line {
+                write_line(&mut detections, &line)?;
+            }
+            pending.retain(|(s, _)| *s > sequence);
+        }
+    }
+
+    frames.flush()?;
+    detections.flush()?;
+    if let Some((mut video, _)) = writer {
+        video.release()?;
+    }
+    Ok(count)
+}
+
+// 動画に1フレーム書き込む。解像度が変わって書けなかったときはfalseを返す
+fn write_video_frame(
+    dir: &Path,
+    camera: &SessionCamera,
+    writer: &mut Option<(VideoWriter, Size)>,
+    frame: &Frame,
+) -> Result<bool> {
+    let size = frame.image.size()?;
+    // 解像度は最初のフレームが来るまでわからないので遅延して開く
+    if writer.is_none() {
+        let path = dir.join(&camera.video);
+        let video = VideoWriter::new(
+            &path.to_string_lossy(),
+            VideoWriter::fourcc('M', 'J', 'P', 'G')?,
+            NOMINAL_FPS,
+            size,
+            true,
+        )?;
+        if !video.is_opened()? {
+            return Err(anyhow!(
+                "failed to open video writer '{}'",
+                path.display()
+            ));
+        }
+        *writer = Some((video, size));
+    }
+    let (video, video_size) = writer.as_mut().expect("opened above");
+    // VideoWriterは途中で解像度を変えられない
+    if size != *video_size {
+        eprintln!(
+            "Skipping frame {} of '{}': resolution changed",
+            frame.meta.sequence, camera.name
+        );
+        return Ok(false);
+    }
+
+    // フレームはRGBで流れているのでBGRに戻して書き込む
+    let mut bgr = Mat::default();
+    imgproc::cvt_color(
+        &frame.image,
+        &mut bgr,
+        imgproc::COLOR_RGB2BGR,
+        0,
+        opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+    )?;
+    video.write(&bgr)?;
+    Ok(true)
+}
+
+fn write_line<T: Serialize>(writer: &mut impl Write, value: &T) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn read_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open '{}'", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?).with_context(|| {
+                format!("invalid line {} in '{}'", i + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+/// A recorded session directory opened for reading.
+pub struct Session {
+    pub dir: PathBuf,
+    pub manifest: SessionManifest,
+}
+
+impl Session {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let file =
+            File::open(dir.join(SESSION_MANIFEST)).with_context(|| {
+                format!("'{}' is not a session directory", dir.display())
+            })?;
+        let manifest: SessionManifest =
+            serde_json::from_reader(BufReader::new(file))
+                .context("failed to parse session manifest")?;
+        if manifest.version > SESSION_VERSION {
+            return Err(anyhow!(
+                "session version {} is newer than supported version {}",
+                manifest.version,
+                SESSION_VERSION
+            ));
+        }
+        Ok(Self { dir, manifest })
+    }
+
+    pub fn camera(&self, camera: usize) -> Result<&SessionCamera> {
+        self.manifest
+            .cameras
+            .get(camera)
+            .ok_or_else(|| anyhow!("session has no camera {camera}"))
+    }
+
+    pub fn video_path(&self, camera: usize) -> Result<PathBuf> {
+        Ok(self.dir.join(&self.camera(camera)?.video))
+    }
+
+    pub fn frame_records(&self, camera: usize) -> Result<Vec<FrameRecord>> {
+        read_lines(&self.dir.join(&self.camera(camera)?.frames))
+    }
+
+    pub fn detections(&self, camera: usize) -> Result<Vec<FrameDetections>> {
+        read_lines(&self.dir.join(&self.camera(camera)?.detections))
+    }
+}
@@ -0,0 +1,435 @@
+use std::f64::consts::PI;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use opencv::core::{CV_32FC1, Mat, Scalar, Size};
+use opencv::imgproc;
+use opencv::prelude::{BoardTraitConst, MatTraitConst, MatTraitManual};
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::{Mat3, Pose, mat3_mul_vec, rodrigues_to_matrix};
+use crate::{
+    ArucoDictionary, CharucoBoardConfig, Intrinsics, SourceFrame,
+    VideoSourceError,
+};
+
+// ボード画像の1マスあたりの画素数
+const TEXTURE_PIXELS_PER_SQUARE: i32 = 100;
+// ボードの周りの白い余白(画素)
+const TEXTURE_MARGIN: i32 = TEXTURE_PIXELS_PER_SQUARE / 2;
+const BACKGROUND: f64 = 128.0;
+// 待機中に眠る上限
+const MAX_WAIT: Duration = Duration::from_millis(20);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SyntheticTrajectory {
+    // カメラの正面distance [m] でボードを傾けながら揺らす
+    Wobble {
+        distance: f64,
+        tilt_deg: f64,
+        period: f64,
+    },
+    // board->cameraの姿勢を毎フレーム順番に使う
+    Poses(Vec<Pose>),
+}
+
+/// A virtual pinhole camera looking at a generated ChArUco board.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyntheticConfig {
+    pub width: i32,
+    pub height: i32,
+    pub intrinsics: Intrinsics,
+    pub board: CharucoBoardConfig,
+    pub trajectory: SyntheticTrajectory,
+    pub fps: f64,
+    // 輝度に加えるガウスノイズの標準偏差
+    pub noise: f64,
+    pub seed: u64,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            intrinsics: Intrinsics {
+                fx: 600.0,
+                fy: 600.0,
+                cx: 320.0,
+                cy: 240.0,
+                dist: [-0.1, 0.05, 0.0, 0.0, 0.0],
+            },
+            board: CharucoBoardConfig {
+                squares_x: 7,
+                squares_y: 5,
+                square_length: 0.04,
+                marker_length: 0.03,
+                dictionary: ArucoDictionary::DICT_6X6_250,
+                legacy_pattern: false,
+            },
+            trajectory: SyntheticTrajectory::Wobble {
+                distance: 0.6,
+                tilt_deg: 30.0,
+                period: 10.0,
+            },
+            fps: 30.0,
+            noise: 2.0,
+            seed: 0,
+        }
+    }
+}
+
+/// One rendered frame and the pose it was rendered at.
+pub struct SyntheticFrame {
+    pub index: u64,
+    // 先頭フレームからの時刻 [s]
+    pub timestamp: f64,
+    // RGB
+    pub image: Mat,
+    // board->camera。solvePnPのrvec/tvecと同じ向き
+    pub board_pose: Pose,
+}
+
+/// Renders frames of a [`SyntheticConfig`]. Rendering is a pure function
+/// of the frame index, so the same config always yields the same frames.
+pub struct SyntheticRenderer {
+    pub config: SyntheticConfig,
+    // グレースケールのボード画像
+    texture: Mat,
+    // 各出力画素の歪み除去済み正規化座標。姿勢によらないので一度だけ計算する
+    rays: Vec<[f64; 2]>,
+}
+
+impl SyntheticRenderer {
+    pub fn new(config: SyntheticConfig) -> Result<Self> {
+        if config.width <= 0 || config.height <= 0 || config.fps <= 0.0 {
+            return Err(anyhow!("image size and fps must be positive"));
+        }
+        if let SyntheticTrajectory::Poses(poses) = &config.trajectory
+            && poses.is_empty()
+        {
+            return Err(anyhow!("trajectory has no poses"));
+        }
+
+        let board = config.board.build()?;
+        let size = Size::new(
+            config.board.squares_x * TEXTURE_PIXELS_PER_SQUARE
+                + 2 * TEXTURE_MARGIN,
+            config.board.squares_y * TEXTURE_PIXELS_PER_SQUARE
+                + 2 * TEXTURE_MARGIN,
+        );
+        let mut texture = Mat::default();
+        board.generate_image(size, &mut texture, TEXTURE_MARGIN, 1)?;
+
+        let rays = (0..config.height)
+            .flat_map(|v| (0..config.width).map(move |u| (u, v)))
+            .map(|(u, v)| config.intrinsics.undistort(&[u as f64, v as f64]))
+            .collect();
+
+        Ok(Self {
+            config,
+            texture,
+            rays,
+        })
+    }
+
+    /// Ground-truth board pose of frame `index`.
+    pub fn pose_at(&self, index: u64) -> Pose {
+        match &self.config.trajectory {
+            SyntheticTrajectory::Wobble {
+                distance,
+                tilt_deg,
+                period,
+            } => {
+                let phase = 2.0 * PI * index as f64 / self.config.fps / period;
+                let tilt = tilt_deg.to_radians();
+                // x, y軸まわりの傾きの周期をずらして色々な向きを通る
+                let rotation = [
+                    tilt * phase.sin(),
+                    tilt * (1.3 * phase).cos(),
+                    0.2 * (0.7 * phase).sin(),
+                ];
+                let r = rodrigues_to_matrix(&rotation);
+
+                // ボード中心が光軸上distanceの位置に来るようにする
+                let board = &self.config.board;
+                let center = [
+                    board.squares_x as f64 * board.square_length as f64 / 2.0,
+                    board.squares_y as f64 * board.square_length as f64 / 2.0,
+                    0.0,
+                ];
+                let rc = mat3_mul_vec(&r, &center);
+                let offset = distance * 0.1;
+                Pose {
+                    rotation,
+                    translation: [
+                        offset * (0.5 * phase).sin() - rc[0],
+                        offset * (0.9 * phase).cos() - rc[1],
+                        distance - rc[2],
+                    ],
+                }
+            }
+            SyntheticTrajectory::Poses(poses) => {
+                poses[(index % poses.len() as u64) as usize]
+            }
+        }
+    }
+
+    pub fn render(&self, index: u64) -> Result<SyntheticFrame> {
+        let config = &self.config;
+        let board_pose = self.pose_at(index);
+
+        // ボード平面 z=0 上の点 (X, Y) は H = [r1 r2 t] で正規化座標に写る
+        let r = board_pose.rotation_matrix();
+        let t = board_pose.translation;
+        let h = [
+            [r[0][0], r[0][1], t[0]],
+            [r[1][0], r[1][1], t[1]],
+            [r[2][0], r[2][1], t[2]],
+        ];
+        let h_inv = mat3_inverse(&h)
+            .ok_or_else(|| anyhow!("board is seen edge-on at frame {index}"))?;
+
+        // ボード座標 [m] からテクスチャの画素座標へ。OpenCVのボードは
+        // 左上原点でy軸が下向き。画素中心は整数座標なので0.5ずらす
+        let pixels_per_meter = TEXTURE_PIXELS_PER_SQUARE as f64
+            / config.board.square_length as f64;
+        let to_texture = |m: f64| {
+            (TEXTURE_MARGIN as f64 + m * pixels_per_meter - 0.5) as f32
+        };
+
+        let mut map_x = Mat::new_rows_cols_with_default(
+            config.height,
+            config.width,
+            CV_32FC1,
+            Scalar::all(-1.0),
+        )?;
+        let mut map_y = map_x.try_clone()?;
+        {
+            let xs = map_x.data_typed_mut::<f32>()?;
+            let ys = map_y.data_typed_mut::<f32>()?;
+            for (i, [x, y]) in self.rays.iter().enumerate() {
+                let q = mat3_mul_vec(&h_inv, &[*x, *y, 1.0]);
+                // 負ならボードはカメラの後ろ
+                if q[2] <= 1e-12 {
+                    continue;
+                }
+                xs[i] = to_texture(q[0] / q[2]);
+                ys[i] = to_texture(q[1] / q[2]);
+            }
+        }
+
+        let mut gray = Mat::default();
+        imgproc::remap(
+            &self.texture,
+            &mut gray,
+            &map_x,
+            &map_y,
+            imgproc::INTER_LINEAR,
+            opencv::core::BORDER_CONSTANT,
+            Scalar::all(BACKGROUND),
+        )?;
+
+        if config.noise > 0.0 {
+            let mut rng = SplitMix64(
+                config.seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+            );
+            for p in gray.data_bytes_mut()? {
+                let value = *p as f64 + config.noise * rng.next_gaussian();
+                *p = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+
+        let mut image = Mat::default();
+        imgproc::cvt_color(
+            &gray,
+            &mut image,
+            imgproc::COLOR_GRAY2RGB,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
+
+        Ok(SyntheticFrame {
+            index,
+            timestamp: index as f64 / config.fps,
+            image,
+            board_pose,
+        })
+    }
+}
+
+/// Streams a [`SyntheticRenderer`] in real time at the configured fps.
+pub struct SyntheticSource {
+    renderer: SyntheticRenderer,
+    index: u64,
+    started: Instant,
+}
+
+impl SyntheticSource {
+    pub fn new(config: SyntheticConfig) -> Result<Self> {
+        Ok(Self {
+            renderer: SyntheticRenderer::new(config)?,
+            index: 0,
+            started: Instant::now(),
+        })
+    }
+
+    /// Returns the next frame together with the board pose it was
+    /// rendered at.
+    pub fn read(&mut self) -> Result<SourceFrame, VideoSourceError> {
+        let due = Duration::from_secs_f64(
+            self.index as f64 / self.renderer.config.fps,
+        );
+        let elapsed = self.started.elapsed();
+        if elapsed < due {
+            thread::sleep((due - elapsed).min(MAX_WAIT));
+            return Err(VideoSourceError::NotReady);
+        }
+
+        let frame = self
+            .renderer
+            .render(self.index)
+            .map_err(|err| VideoSourceError::Read(err.to_string()))?;
+        self.index += 1;
+        Ok(SourceFrame {
+            image: frame.image,
+            ground_truth: Some(frame.board_pose),
+            recorded_at: None,
+        })
+    }
+}
+
+fn mat3_inverse(m: &Mat3) -> Option<Mat3> {
+    let cof = |r0: usize, r1: usize, c0: usize, c1: usize| {
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let adj = [
+        [cof(1, 2, 1, 2), -cof(0, 2, 1, 2), cof(0, 1, 1, 2)],
+        [-cof(1, 2, 0, 2), cof(0, 2, 0, 2), -cof(0, 1, 0, 2)],
+        [cof(1, 2, 0, 1), -cof(0, 2, 0, 1), cof(0, 1, 0, 1)],
+    ];
+    let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    Some(adj.map(|row| row.map(|a| a / det)))
+}
+
+// 再現性のためにフレーム番号から種を決める小さな乱数生成器
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Box-Muller
+    fn next_gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opencv::aruco::calibrate_camera_charuco_def;
+    use opencv::core::{Ptr, Vector};
+    use opencv::objdetect::CharucoDetector;
+
+    use super::*;
+    use crate::{CameraParameter, OpenCvCamera};
+
+    // ボード中心がカメラの前distance [m] 付近に来るよう、向きを変えた姿勢を並べる
+    fn calibration_poses(board: &CharucoBoardConfig) -> Vec<Pose> {
+        let center = [
+            board.squares_x as f64 * board.square_length as f64 / 2.0,
+            board.squares_y as f64 * board.square_length as f64 / 2.0,
+            0.0,
+        ];
+        (0..20)
+            .map(|i| {
+                let a = 2.0 * PI * i as f64 / 20.0;
+                let rotation =
+                    [0.45 * a.sin(), 0.45 * a.cos(), 0.3 * (2.0 * a).sin()];
+                let rc = mat3_mul_vec(&rodrigues_to_matrix(&rotation), &center);
+                Pose {
+                    rotation,
+                    translation: [
+                        0.06 * (3.0 * a).cos() - rc[0],
+                        0.04 * (3.0 * a).sin() - rc[1],
+                        0.6 + 0.08 * a.sin() - rc[2],
+                    ],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calibration_recovers_intrinsics() {
+        let mut config = SyntheticConfig::default();
+        let poses = calibration_poses(&config.board);
+        config.trajectory = SyntheticTrajectory::Poses(poses.clone());
+        let renderer = SyntheticRenderer::new(config.clone()).unwrap();
+
+        let board = config.board.build().unwrap();
+        let detector = CharucoDetector::new_def(&board).unwrap();
+        let mut all_corners = Vector::<Mat>::new();
+        let mut all_ids = Vector::<Mat>::new();
+        for (index, pose) in poses.iter().enumerate() {
+            let frame = renderer.render(index as u64).unwrap();
+            assert_eq!(frame.board_pose, *pose);
+
+            let (_, _, corners, ids) =
+                OpenCvCamera::detect_charuco(&frame.image, &detector, &board)
+                    .unwrap();
+            if ids.rows() >= 6 {
+                all_corners.push(corners);
+                all_ids.push(ids);
+            }
+        }
+        assert!(
+            all_ids.len() >= 15,
+            "board found in {} frames",
+            all_ids.len()
+        );
+
+        let mut camera_matrix = Mat::default();
+        let mut dist_coeffs = Mat::default();
+        calibrate_camera_charuco_def(
+            &all_corners,
+            &all_ids,
+            &Ptr::new(board),
+            Size::new(config.width, config.height),
+            &mut camera_matrix,
+            &mut dist_coeffs,
+        )
+        .unwrap();
+        let estimated = Intrinsics::try_from(&CameraParameter {
+            camera_matrix,
+            dist_coeffs,
+        })
+        .unwrap();
+
+        let truth = config.intrinsics;
+        assert!(
+            (estimated.fx - truth.fx).abs() < 0.01 * truth.fx,
+            "{estimated:?}"
+        );
+        assert!(
+            (estimated.fy - truth.fy).abs() < 0.01 * truth.fy,
+            "{estimated:?}"
+        );
+        assert!((estimated.cx - truth.cx).abs() < 5.0, "{estimated:?}");
+        assert!((estimated.cy - truth.cy).abs() < 5.0, "{estimated:?}");
+    }
+}
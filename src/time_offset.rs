@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow};
+use opencv::core::{Mat, Scalar};
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{self, VideoCapture};
+use serde::{Deserialize, Serialize};
+
+use crate::{FrameDetections, FrameRecord, Session};
+
+// これより間隔の空いたサンプルの間は補間しない [s]
+const MAX_SAMPLE_GAP: f64 = 0.2;
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default,
+)]
+pub enum OffsetSignal {
+    // ChArUcoコーナー(無ければブロブの重心)の画像上の速さ
+    #[default]
+    BoardMotion,
+    // フレーム全体の平均輝度。点滅するLEDを全カメラに見せる
+    Brightness,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TimeOffsetOptions {
+    pub signal: OffsetSignal,
+    // 探索するずれの範囲 [s]
+    pub max_offset: f64,
+    // 相関を取る時間刻み [s]
+    pub step: f64,
+    // 相関に使う重なりの最小サンプル数
+    pub min_overlap: usize,
+}
+
+impl Default for TimeOffsetOptions {
+    fn default() -> Self {
+        Self {
+            signal: OffsetSignal::BoardMotion,
+            max_offset: 0.5,
+            step: 0.002,
+            min_overlap: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeOffsetEstimate {
+    // カメラの時刻から引くと基準カメラの時刻になる [s]
+    pub offset: f64,
+    // 正規化相互相関のピーク値 (-1..=1)
+    pub correlation: f64,
+}
+
+/// Time series of `(time [s], value)` samples in increasing time order.
+pub type Signal = Vec<(f64, f64)>;
+
+/// Estimates how much later than `reference` the events in `signal` are
+/// timestamped, by maximizing the normalized cross-correlation of the two
+/// signals. Returns `None` when they never overlap enough.
+pub fn estimate_offset(
+    reference: &[(f64, f64)],
+    signal: &[(f64, f64)],
+    options: &TimeOffsetOptions,
+) -> Option<TimeOffsetEstimate> {
+    let (first, last) = (reference.first()?.0, reference.last()?.0);
+    let step = options.step;
+    let max_lag = (options.max_offset / step).ceil() as i64;
+    let len = ((last - first) / step).floor() as i64 + 1;
+
+    let at = |i: i64| first + i as f64 * step;
+    let reference: Vec<Option<f64>> =
+        (0..len).map(|i| resample(reference, at(i))).collect();
+    // 基準の範囲の外側もずらした分だけ用意する
+    let signal: Vec<Option<f64>> =
+        (-max_lag..len + max_lag).map(|i| resample(signal, at(i))).collect();
+
+    let correlations: Vec<Option<f64>> = (-max_lag..=max_lag)
+        .map(|lag| {
+            let pairs = reference.iter().enumerate().filter_map(|(i, r)| {
+                Some(((*r)?, signal[(i as i64 + lag + max_lag) as usize]?))
+            });
+            correlation(pairs, options.min_overlap)
+        })
+        .collect();
+
+    let (best, peak) = correlations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| Some((i, (*c)?)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    // 両隣の相関に放物線を当てはめて刻みより細かく求める
+    let mut lag = best as f64 - max_lag as f64;
+    if let (Some(Some(left)), Some(Some(right))) = (
+        best.checked_sub(1).map(|i| correlations[i]),
+        correlations.get(best + 1).copied(),
+    ) {
+        let curvature = left - 2.0 * peak + right;
+        if curvature < 0.0 {
+            lag += 0.5 * (left - right) / curvature;
+        }
+    }
+
+    Some(TimeOffsetEstimate {
+        offset: lag * step,
+        correlation: peak,
+    })
+}
+
+// 時刻tの値を線形補間する。前後のサンプルが離れすぎていればNone
+fn resample(signal: &[(f64, f64)], t: f64) -> Option<f64> {
+    let after = signal.partition_point(|(time, _)| *time < t);
+    let (t1, v1) = *signal.get(after)?;
+    if t1 == t {
+        return Some(v1);
+    }
+    let (t0, v0) = *signal.get(after.checked_sub(1)?)?;
+    if t1 - t0 > MAX_SAMPLE_GAP {
+        return None;
+    }
+    Some(v0 + (v1 - v0) * (t - t0) / (t1 - t0))
+}
+
+fn correlation(
+    pairs: impl Iterator<Item = (f64, f64)>,
+    min_overlap: usize,
+) -> Option<f64> {
+    let pairs: Vec<_> = pairs.collect();
+    if pairs.len() < min_overlap.max(2) {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+    for (a, b) in &pairs {
+        ab += (a - mean_a) * (b - mean_b);
+        aa += (a - mean_a).powi(2);
+        bb += (b - mean_b).powi(2);
+    }
+    if aa <= 0.0 || bb <= 0.0 {
+        return None;
+    }
+    Some(ab / (aa * bb).sqrt())
+}
+
+/// Image-space speed [px/s] of the board (or of the blob centroid when no
+/// board is seen) between consecutive detected frames.
+pub fn motion_signal(
+    records: &[FrameRecord],
+    detections: &[FrameDetections],
+) -> Signal {
+    // 検出スレッドが飛ばしたフレームには検出結果が無いので、frameで時刻を引く
+    let timestamps: BTreeMap<usize, f64> =
+        records.iter().map(|r| (r.frame, r.timestamp)).collect();
+    let mut signal = Vec::new();
+    for detections in detections.windows(2) {
+        let (Some(t0), Some(t1)) = (
+            timestamps.get(&detections[0].frame),
+            timestamps.get(&detections[1].frame),
+        ) else {
+            continue;
+        };
+        let dt = t1 - t0;
+        if dt <= 0.0 {
+            continue;
+        }
+        let Some(distance) = displacement(&detections[0], &detections[1])
+        else {
+            continue;
+        };
+        // 区間の中央の時刻の速さとする
+        let t = (t0 + t1) / 2.0;
+        signal.push((t, distance / dt));
+    }
+    signal
+}
+
+// 両方のフレームで見えているコーナーの平均移動量。
+// カメラごとに見えるコーナーが違っても比べられるよう重心は使わない
+fn displacement(a: &FrameDetections, b: &FrameDetections) -> Option<f64> {
+    let corners = |d: &FrameDetections| -> BTreeMap<i32, [f32; 2]> {
+        d.charuco_ids
+            .iter()
+            .copied()
+            .zip(d.charuco_corners.iter().copied())
+            .collect()
+    };
+    let (ca, cb) = (corners(a), corners(b));
+    let moves: Vec<f64> = ca
+        .iter()
+        .filter_map(|(id, p)| {
+            let q = cb.get(id)?;
+            Some(((q[0] - p[0]) as f64).hypot((q[1] - p[1]) as f64))
+        })
+        .collect();
+    if !moves.is_empty() {
+        return Some(moves.iter().sum::<f64>() / moves.len() as f64);
+    }
+
+    let centroid = |d: &FrameDetections| -> Option<[f64; 2]> {
+        if d.blobs.is_empty() {
+            return None;
+        }
+        let n = d.blobs.len() as f64;
+        Some([
+            d.blobs.iter().map(|b| b.center[0]).sum::<f64>() / n,
+            d.blobs.iter().map(|b| b.center[1]).sum::<f64>() / n,
+        ])
+    };
+    let (p, q) = (centroid(a)?, centroid(b)?);
+    Some((q[0] - p[0]).hypot(q[1] - p[1]))
+}
+
+/// Mean gray level of every frame of a recorded video.
+pub fn brightness_signal(
+    video_path: &str,
+    records: &[FrameRecord],
+) -> Result<Signal> {
+    let mut capture = VideoCapture::from_file(video_path, videoio::CAP_ANY)?;
+    if !capture.is_opened()? {
+        return Err(anyhow!("failed to open video '{video_path}'"));
+    }
+
+    let mut signal = Vec::with_capacity(records.len());
+    let mut frame = Mat::default();
+    let mut gray = Mat::default();
+    for record in records {
+        if !capture.read(&mut frame)? || frame.empty() {
+            break;
+        }
+        imgproc::cvt_color(
+            &frame,
+            &mut gray,
+            imgproc::COLOR_BGR2GRAY,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
+        let mean: Scalar = opencv::core::mean_def(&gray)?;
+        signal.push((record.timestamp, mean[0]));
+    }
+    Ok(signal)
+}
+
+/// Estimates the offset of every camera of a recorded session relative to
+/// `reference`. Cameras whose signal never correlates get `None`.
+pub fn estimate_session_offsets(
+    session: &Session,
+    reference: usize,
+    options: &TimeOffsetOptions,
+) -> Result<Vec<Option<TimeOffsetEstimate>>> {
+    let cameras = session.manifest.cameras.len();
+    if reference >= cameras {
+        return Err(anyhow!("reference camera {reference} not in session"));
+    }
+
+    let signals = (0..cameras)
+        .map(|camera| {
+            let records = session.frame_records(camera)?;
+            match options.signal {
+                OffsetSignal::BoardMotion => {
+                    let detections = session.detections(camera)?;
+                    Ok(motion_signal(&records, &detections))
+                }
+                OffsetSignal::Brightness => brightness_signal(
+                    &session.video_path(camera)?.to_string_lossy(),
+                    &records,
+                ),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(signals
+        .iter()
+        .enumerate()
+        .map(|(camera, signal)| {
+            if camera == reference {
+                return Some(TimeOffsetEstimate {
+                    offset: 0.0,
+                    correlation: 1.0,
+                });
+            }
+            estimate_offset(&signals[reference], signal, options)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 周期の揃わない正弦波の和。どのずれでも自分自身とは似ない
+    fn wave(t: f64) -> f64 {
+        (2.1 * t).sin() + 0.7 * (5.3 * t + 1.0).sin() + 0.4 * (11.7 * t).sin()
+    }
+
+    // 30fps前後で少し揺らいだ時刻に、delayだけ遅れた時刻印で記録する
+    fn sampled(delay: f64, phase: f64) -> Signal {
+        (0..300)
+            .map(|i| {
+                let t =
+                    i as f64 / 30.0 + 0.004 * (i as f64 * 1.7 + phase).sin();
+                (t + delay, wave(t))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recovers_known_shifts() {
+        let reference = sampled(0.0, 0.0);
+        for delay in [0.123, -0.05, 0.0] {
+            let estimate = estimate_offset(
+                &reference,
+                &sampled(delay, 0.5),
+                &TimeOffsetOptions::default(),
+            )
+            .unwrap();
+            assert!(
+                (estimate.offset - delay).abs() < 0.002,
+                "{delay}: {estimate:?}"
+            );
+            assert!(estimate.correlation > 0.99);
+        }
+    }
+
+    #[test]
+    fn disjoint_signals_have_no_estimate() {
+        let reference = sampled(0.0, 0.0);
+        // 探索範囲より大きくずれていれば重ならない
+        let late = sampled(20.0, 0.0);
+        assert!(
+            estimate_offset(&reference, &late, &TimeOffsetOptions::default())
+                .is_none()
+        );
+        assert!(
+            estimate_offset(&[], &reference, &TimeOffsetOptions::default())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn motion_signal_pairs_detections_by_frame() {
+        let records: Vec<FrameRecord> = (0..5)
+            .map(|frame| FrameRecord {
+                frame,
+                sequence: frame as u64 + 1,
+                timestamp: frame as f64 * 0.1,
+                wall_clock: 0.0,
+            })
+            .collect();
+        // 1フレームに2 px動くボード。検出スレッドはフレーム1と4を飛ばした
+        let detections: Vec<FrameDetections> = [0, 2, 3]
+            .into_iter()
+            .map(|frame| FrameDetections {
+                frame,
+                charuco_ids: vec![0, 1],
+                charuco_corners: vec![
+                    [2.0 * frame as f32, 0.0],
+                    [2.0 * frame as f32, 10.0],
+                ],
+                ..Default::default()
+            })
+            .collect();
+
+        let signal = motion_signal(&records, &detections);
+        assert_eq!(signal.len(), 2);
+        for ((t, speed), expected_t) in signal.iter().zip([0.1, 0.25]) {
+            assert!((t - expected_t).abs() < 1e-9);
+            assert!((speed - 20.0).abs() < 1e-6);
+        }
+    }
+}
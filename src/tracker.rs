@@ -0,0 +1,432 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::{Vec3, vec3_norm, vec3_sub};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AssignmentMethod {
+    NearestNeighbor,
+    Hungarian,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TrackerConfig {
+    pub assignment: AssignmentMethod,
+    // 予測位置からこの距離 [m] 以内の点だけを同じトラックに割り当てる
+    pub max_distance: f64,
+    // 何フレーム連続で見失ったらトラックを消すか
+    pub max_gap: usize,
+    // 加速度の分散 [(m/s^2)^2]
+    pub process_noise: f64,
+    // 三角測量した位置の分散 [m^2]
+    pub measurement_noise: f64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            assignment: AssignmentMethod::Hungarian,
+            max_distance: 0.05,
+            max_gap: 10,
+            process_noise: 50.0,
+            measurement_noise: 1e-6,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    pub id: u64,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    // 観測で更新された回数
+    pub hits: usize,
+    // 連続で観測が得られなかったフレーム数
+    pub missed: usize,
+    // 位置・速度の共分散。各軸独立で等方的なので2x2を全軸で共有する
+    covariance: [[f64; 2]; 2],
+}
+
+impl Track {
+    fn new(id: u64, position: Vec3, config: &TrackerConfig) -> Self {
+        Self {
+            id,
+            position,
+            velocity: [0.0; 3],
+            hits: 1,
+            missed: 0,
+            // 初速は未知なので大きめの分散から始める
+            covariance: [[config.measurement_noise, 0.0], [0.0, 1.0]],
+        }
+    }
+
+    fn predict(&mut self, dt: f64, q: f64) {
+        for k in 0..3 {
+            self.position[k] += self.velocity[k] * dt;
+        }
+
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let (dt2, dt3, dt4) = (dt * dt, dt * dt * dt, dt * dt * dt * dt);
+        self.covariance = [
+            [
+                p00 + dt * (p10 + p01) + dt2 * p11 + q * dt4 / 4.0,
+                p01 + dt * p11 + q * dt3 / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt3 / 2.0, p11 + q * dt2],
+        ];
+    }
+
+    fn correct(&mut self, z: &Vec3, r: f64) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let s = p00 + r;
+        let (k0, k1) = (p00 / s, p10 / s);
+        for (k, z) in z.iter().enumerate() {
+            let innovation = z - self.position[k];
+            self.position[k] += k0 * innovation;
+            self.velocity[k] += k1 * innovation;
+        }
+        self.covariance = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrackEvent {
+    Born { id: u64, position: Vec3 },
+    Died { id: u64, last_position: Vec3 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackerUpdate {
+    // 入力点と同じ順。割り当てられたトラックのid
+    pub assignments: Vec<Option<u64>>,
+    pub events: Vec<TrackEvent>,
+}
+
+/// Keeps stable ids for 3D points across frames with a constant velocity
+/// Kalman filter per track.
+pub struct Tracker {
+    pub config: TrackerConfig,
+    tracks: Vec<Track>,
+    next_id: u64,
+    last_timestamp: Option<f64>,
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig) -> Self {
+        Self {
+            config,
+            tracks: Vec::new(),
+            next_id: 0,
+            last_timestamp: None,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Forgets every track. No death events are emitted.
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+        self.last_timestamp = None;
+    }
+
+    /// Advances all tracks to `timestamp` (seconds) and assigns `points`
+    /// to them. Unassigned points start new tracks; tracks unseen for more
+    /// than `max_gap` frames are dropped.
+    pub fn update(&mut self, timestamp: f64, points: &[Vec3]) -> TrackerUpdate {
+        let dt =
+            self.last_timestamp.map_or(0.0, |last| (timestamp - last).max(0.0));
+        self.last_timestamp = Some(timestamp);
+
+        for track in &mut self.tracks {
+            track.predict(dt, self.config.process_noise);
+        }
+
+        let cost: Vec<Vec<f64>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                points
+                    .iter()
+                    .map(|p| vec3_norm(&vec3_sub(p, &track.position)))
+                    .collect()
+            })
+            .collect();
+        let matches = match self.config.assignment {
+            AssignmentMethod::NearestNeighbor => {
+                assign_nearest(&cost, self.config.max_distance)
+            }
+            AssignmentMethod::Hungarian => {
+                assign_hungarian(&cost, self.config.max_distance)
+            }
+        };
+
+        let mut update = TrackerUpdate {
+            assignments: vec![None; points.len()],
+            events: Vec::new(),
+        };
+
+        for (track, point) in self.tracks.iter_mut().zip(&matches) {
+            match point {
+                Some(j) => {
+                    track.correct(&points[*j], self.config.measurement_noise);
+                    track.hits += 1;
+                    track.missed = 0;
+                    update.assignments[*j] = Some(track.id);
+                }
+                None => track.missed += 1,
+            }
+        }
+
+        let max_gap = self.config.max_gap;
+        self.tracks.retain(|track| {
+            let alive = track.missed <= max_gap;
+            if !alive {
+                update.events.push(TrackEvent::Died {
+                    id: track.id,
+                    last_position: track.position,
+                });
+            }
+            alive
+        });
+
+        for (j, point) in points.iter().enumerate() {
+            if update.assignments[j].is_some() {
+                continue;
+            }
+            let id = self.next_id;
+            self.next_id += 1;
+            self.tracks.push(Track::new(id, *point, &self.config));
+            update.assignments[j] = Some(id);
+            update.events.push(TrackEvent::Born {
+                id,
+                position: *point,
+            });
+        }
+
+        update
+    }
+}
+
+// 距離の小さい組から貪欲に割り当てる
+fn assign_nearest(cost: &[Vec<f64>], gate: f64) -> Vec<Option<usize>> {
+    let mut pairs: Vec<(usize, usize, f64)> = cost
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| {
+            row.iter().enumerate().map(move |(j, c)| (i, j, *c))
+        })
+        .filter(|(_, _, c)| *c <= gate)
+        .collect();
+    pairs.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let cols = cost.first().map_or(0, Vec::len);
+    let mut rows = vec![None; cost.len()];
+    let mut used = vec![false; cols];
+    for (i, j, _) in pairs {
+        if rows[i].is_none() && !used[j] {
+            rows[i] = Some(j);
+            used[j] = true;
+        }
+    }
+    rows
+}
+
+// ゲート外の組はgateより大きいコストにしておき、解いた後に取り除く
+fn assign_hungarian(cost: &[Vec<f64>], gate: f64) -> Vec<Option<usize>> {
+    let rows = cost.len();
+    let cols = cost.first().map_or(0, Vec::len);
+    let n = rows.max(cols);
+    if n == 0 {
+        return vec![None; rows];
+    }
+
+    let outside = gate * 2.0 + 1.0;
+    let padded: Vec<Vec<f64>> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| match cost.get(i).and_then(|row| row.get(j)) {
+                    Some(c) if *c <= gate => *c,
+                    Some(_) => outside,
+                    None => gate,
+                })
+                .collect()
+        })
+        .collect();
+
+    hungarian(&padded)
+        .into_iter()
+        .take(rows)
+        .enumerate()
+        .map(|(i, j)| (j < cols && cost[i][j] <= gate).then_some(j))
+        .collect()
+}
+
+/// Minimum cost perfect matching on a square matrix (Kuhn-Munkres with
+/// potentials, O(n^3)). Returns the column assigned to each row.
+fn hungarian(cost: &[Vec<f64>]) -> Vec<usize> {
+    let n = cost.len();
+    // 1始まりの添字で、0番目を番兵として使う
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut row_of = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+
+    for i in 1..=n {
+        row_of[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let c = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                if c < min_v[j] {
+                    min_v[j] = c;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut col_of = vec![0; n];
+    for j in 1..=n {
+        if row_of[j] > 0 {
+            col_of[row_of[j] - 1] = j - 1;
+        }
+    }
+    col_of
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hungarian_finds_minimum_cost() {
+        // 貪欲に0から取ると4+0+2=6になるが、最小は1+2+2=5
+        let cost = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(hungarian(&cost), vec![1, 0, 2]);
+        assert_eq!(
+            assign_nearest(&cost, 10.0),
+            vec![Some(0), Some(1), Some(2)]
+        );
+    }
+
+    #[test]
+    fn assignment_respects_gate_and_shape() {
+        // トラック2本に点3つ。ゲート外の組は割り当てない
+        let cost = vec![vec![0.01, 0.5, 0.03], vec![0.02, 0.6, 0.9]];
+        assert_eq!(assign_hungarian(&cost, 0.05), vec![Some(2), Some(0)]);
+        // 点が無いトラックはNoneになる
+        let cost = vec![vec![0.01], vec![0.02]];
+        assert_eq!(assign_hungarian(&cost, 0.05), vec![Some(0), None]);
+        assert_eq!(assign_hungarian(&[], 0.05), Vec::<Option<usize>>::new());
+    }
+
+    #[test]
+    fn tracks_are_born_and_die_after_max_gap() {
+        let mut tracker = Tracker::new(TrackerConfig {
+            max_gap: 2,
+            ..Default::default()
+        });
+        let point = [0.1, 0.2, 0.3];
+
+        let update = tracker.update(0.0, &[point]);
+        assert_eq!(update.assignments, vec![Some(0)]);
+        assert_eq!(
+            update.events,
+            vec![TrackEvent::Born {
+                id: 0,
+                position: point
+            }]
+        );
+
+        // max_gapフレームまでは見失っても残る
+        for k in 1..=2 {
+            let update = tracker.update(k as f64 / 30.0, &[]);
+            assert!(update.events.is_empty());
+            assert_eq!(tracker.tracks()[0].missed, k);
+        }
+        let update = tracker.update(3.0 / 30.0, &[]);
+        assert_eq!(
+            update.events,
+            vec![TrackEvent::Died {
+                id: 0,
+                last_position: point
+            }]
+        );
+        assert!(tracker.tracks().is_empty());
+
+        // 消えた後に同じ場所に現れた点は新しいidになる
+        let update = tracker.update(4.0 / 30.0, &[point]);
+        assert_eq!(update.assignments, vec![Some(1)]);
+    }
+
+    #[test]
+    fn crossing_targets_keep_their_ids() {
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        let dt = 1.0 / 30.0;
+        // y方向に0.3 m/sですれ違う2点。同じフレームで重なることはない
+        let a = |k: usize| [0.0, -0.045 + 0.01 * k as f64, 1.0];
+        let b = |k: usize| [0.0, 0.045 - 0.01 * k as f64, 1.0];
+
+        let first = tracker.update(0.0, &[a(0), b(0)]);
+        let (id_a, id_b) = (first.assignments[0], first.assignments[1]);
+        assert_ne!(id_a, id_b);
+
+        for k in 1..10 {
+            // 入力の順番には頼らない
+            let update = if k % 2 == 0 {
+                let u = tracker.update(k as f64 * dt, &[a(k), b(k)]);
+                (u.assignments[0], u.assignments[1], u.events)
+            } else {
+                let u = tracker.update(k as f64 * dt, &[b(k), a(k)]);
+                (u.assignments[1], u.assignments[0], u.events)
+            };
+            assert_eq!((update.0, update.1), (id_a, id_b), "frame {k}");
+            assert!(update.2.is_empty(), "frame {k}");
+        }
+        for track in tracker.tracks() {
+            let expected = if Some(track.id) == id_a { 0.3 } else { -0.3 };
+            assert!((track.velocity[1] - expected).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use crate::calibration::geometry::{solve_cholesky, symmetric_eigen};
+use crate::calibration::{Intrinsics, Pose, Vec3};
+
+/// A camera with both intrinsics and extrinsics known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibratedCamera {
+    pub intrinsics: Intrinsics,
+    // world -> camera
+    pub pose: Pose,
+}
+
+impl CalibratedCamera {
+    pub fn project(&self, p: &Vec3) -> Option<[f64; 2]> {
+        self.intrinsics.project(&self.pose.transform_point(p))
+    }
+
+    pub fn depth(&self, p: &Vec3) -> f64 {
+        self.pose.transform_point(p)[2]
+    }
+
+    /// Reprojection error in pixels, `None` if the point is behind the
+    /// camera.
+    pub fn reprojection_error(&self, p: &Vec3, uv: &[f64; 2]) -> Option<f64> {
+        let projected = self.project(p)?;
+        Some((projected[0] - uv[0]).hypot(projected[1] - uv[1]))
+    }
+}
+
+/// One camera's 2D observation of a feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointObservation {
+    pub camera: usize,
+    pub point: [f64; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangulationOptions {
+    // DLTの解を再投影誤差最小化で改善する
+    pub refine: bool,
+    // RANSACでインライアとみなす再投影誤差 [px]
+    pub inlier_threshold: f64,
+    pub min_views: usize,
+}
+
+impl Default for TriangulationOptions {
+    fn default() -> Self {
+        Self {
+            refine: true,
+            inlier_threshold: 2.0,
+            min_views: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriangulatedPoint {
+    pub position: Vec3,
+    // observationsと同じ順。カメラの後ろに来た観測はNone
+    pub residuals: Vec<Option<f64>>,
+    pub inliers: Vec<bool>,
+    pub rms: f64,
+    // インライア率と再投影誤差から求めた0..=1の信頼度
+    pub confidence: f64,
+}
+
+impl TriangulatedPoint {
+    pub fn inlier_count(&self) -> usize {
+        self.inliers.iter().filter(|i| **i).count()
+    }
+}
+
+/// Triangulates one feature seen by several cameras.
+///
+/// Every pair of cameras is tried as a minimal hypothesis (an exhaustive
+/// RANSAC, since a rig only has a handful of cameras) and the one with the
+/// most views within `inlier_threshold` wins. The point is then
+/// re-estimated with DLT from all inliers and optionally refined by
+/// minimizing the reprojection error. Returns `None` when fewer than
+/// `min_views` observations agree.
+pub fn triangulate(
+    cameras: &[CalibratedCamera],
+    observations: &[PointObservation],
+    options: &TriangulationOptions,
+) -> Option<TriangulatedPoint> {
+    let observations: Vec<(&CalibratedCamera, [f64; 2])> = observations
+        .iter()
+        .map(|o| Some((cameras.get(o.camera)?, o.point)))
+        .collect::<Option<_>>()?;
+    let min_views = options.min_views.max(2);
+    if observations.len() < min_views {
+        return None;
+    }
+
+    let inliers_of = |p: &Vec3| -> (Vec<bool>, f64) {
+        let mut sum = 0.0;
+        let inliers = observations
+            .iter()
+            .map(|(camera, uv)| match camera.reprojection_error(p, uv) {
+                Some(e) if e <= options.inlier_threshold => {
+                    sum += e * e;
+                    true
+                }
+                _ => false,
+            })
+            .collect();
+        (inliers, sum)
+    };
+
+    let mut best: Option<(Vec<bool>, usize, f64)> = None;
+    for a in 0..observations.len() {
+        for b in (a + 1)..observations.len() {
+            let Some(p) = triangulate_dlt(&[observations[a], observations[b]])
+            else {
+                continue;
+            };
+            let (inliers, sum) = inliers_of(&p);
+            let count = inliers.iter().filter(|i| **i).count();
+            let better =
+                best.as_ref().is_none_or(|(_, best_count, best_sum)| {
+                    count > *best_count
+                        || (count == *best_count && sum < *best_sum)
+                });
+            if better {
+                best = Some((inliers, count, sum));
+            }
+        }
+    }
+
+    let (inliers, count, _) = best?;
+    if count < min_views {
+        return None;
+    }
+
+    let inlier_observations: Vec<_> = observations
+        .iter()
+        .zip(&inliers)
+        .filter(|(_, inlier)| **inlier)
+        .map(|(o, _)| *o)
+        .collect();
+    let mut position = triangulate_dlt(&inlier_observations)?;
+    if options.refine {
+        position = refine_point(&inlier_observations, position);
+    }
+
+    let residuals: Vec<Option<f64>> = observations
+        .iter()
+        .map(|(camera, uv)| camera.reprojection_error(&position, uv))
+        .collect();
+    let inliers: Vec<bool> = residuals
+        .iter()
+        .map(|r| r.is_some_and(|r| r <= options.inlier_threshold))
+        .collect();
+    let inlier_residuals: Vec<f64> = residuals
+        .iter()
+        .zip(&inliers)
+        .filter_map(|(r, inlier)| r.filter(|_| *inlier))
+        .collect();
+    if inlier_residuals.len() < min_views {
+        return None;
+    }
+
+    let rms = (inlier_residuals.iter().map(|r| r * r).sum::<f64>()
+        / inlier_residuals.len() as f64)
+        .sqrt();
+    let inlier_ratio =
+        inlier_residuals.len() as f64 / observations.len() as f64;
+    let confidence =
+        inlier_ratio * (-0.5 * (rms / options.inlier_threshold).powi(2)).exp();
+
+    Some(TriangulatedPoint {
+        position,
+        residuals,
+        inliers,
+        rms,
+        confidence,
+    })
+}
+
+// 歪みを除去した正規化座標で x × (P X) = 0 を解く
+fn triangulate_dlt(
+    observations: &[(&CalibratedCamera, [f64; 2])],
+) -> Option<Vec3> {
+    let mut ata = [0.0; 16];
+    for (camera, uv) in observations {
+        let [x, y] = camera.intrinsics.undistort(uv);
+        let r = camera.pose.rotation_matrix();
+        let t = camera.pose.translation;
+        let row = |i: usize| [r[i][0], r[i][1], r[i][2], t[i]];
+        let (p1, p2, p3) = (row(0), row(1), row(2));
+        let equations: [[f64; 4]; 2] = [
+            std::array::from_fn(|k| x * p3[k] - p1[k]),
+            std::array::from_fn(|k| y * p3[k] - p2[k]),
+        ];
+
+        for equation in equations {
+            // 行ごとに正規化して条件数を抑える
+            let norm = equation.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm < 1e-12 {
+                continue;
+            }
+            for i in 0..4 {
+                for j in 0..4 {
+                    ata[i * 4 + j] += equation[i] * equation[j] / (norm * norm);
+                }
+            }
+        }
+    }
+
+    let (_, x) = symmetric_eigen(&ata, 4).into_iter().next()?;
+    if x[3].abs() < 1e-12 {
+        return None;
+    }
+    let p = [x[0] / x[3], x[1] / x[3], x[2] / x[3]];
+
+    observations.iter().all(|(camera, _)| camera.depth(&p) > 0.0).then_some(p)
+}
+
+// 再投影誤差を3自由度のLevenberg-Marquardtで最小化する
+fn refine_point(
+    observations: &[(&CalibratedCamera, [f64; 2])],
+    initial: Vec3,
+) -> Vec3 {
+    let residuals = |p: &Vec3| -> Option<Vec<f64>> {
+        let mut r = Vec::with_capacity(observations.len() * 2);
+        for (camera, uv) in observations {
+            let projected = camera.project(p)?;
+            r.push(projected[0] - uv[0]);
+            r.push(projected[1] - uv[1]);
+        }
+        Some(r)
+    };
+    let cost = |r: &[f64]| r.iter().map(|v| v * v).sum::<f64>();
+
+    let mut p = initial;
+    let Some(mut r) = residuals(&p) else {
+        return initial;
+    };
+    let mut current_cost = cost(&r);
+    let mut lambda = 1e-3;
+
+    for _ in 0..20 {
+        let scale = p.iter().fold(1.0_f64, |m, v| m.max(v.abs()));
+        let h = 1e-7 * scale;
+        let mut jacobian = vec![[0.0; 3]; r.len()];
+        for k in 0..3 {
+            let (mut plus, mut minus) = (p, p);
+            plus[k] += h;
+            minus[k] -= h;
+            let (Some(rp), Some(rm)) = (residuals(&plus), residuals(&minus))
+            else {
+                return p;
+            };
+            for (row, (a, b)) in jacobian.iter_mut().zip(rp.iter().zip(&rm)) {
+                row[k] = (a - b) / (2.0 * h);
+            }
+        }
+
+        let mut jtj = [[0.0; 3]; 3];
+        let mut jtr = [0.0; 3];
+        for (row, ri) in jacobian.iter().zip(&r) {
+            for a in 0..3 {
+                jtr[a] += row[a] * ri;
+                for b in 0..3 {
+                    jtj[a][b] += row[a] * row[b];
+                }
+            }
+        }
+
+        let mut improved = false;
+        while lambda < 1e10 {
+            let mut a: Vec<f64> = (0..9)
+                .map(|i| {
+                    let (row, col) = (i / 3, i % 3);
+                    let damping = if row == col {
+                        lambda * jtj[row][row] + 1e-12
+                    } else {
+                        0.0
+                    };
+                    jtj[row][col] + damping
+                })
+                .collect();
+            let mut delta: Vec<f64> = jtr.iter().map(|v| -v).collect();
+            if solve_cholesky(&mut a, &mut delta).is_none() {
+                lambda *= 10.0;
+                continue;
+            }
+
+            let candidate = [p[0] + delta[0], p[1] + delta[1], p[2] + delta[2]];
+            if let Some(candidate_r) = residuals(&candidate) {
+                let candidate_cost = cost(&candidate_r);
+                if candidate_cost < current_cost {
+                    let relative = (current_cost - candidate_cost)
+                        / current_cost.max(1e-300);
+                    p = candidate;
+                    r = candidate_r;
+                    current_cost = candidate_cost;
+                    lambda = (lambda / 10.0).max(1e-12);
+                    improved = relative > 1e-12;
+                    break;
+                }
+            }
+            lambda *= 10.0;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 原点付近を囲むように並べたカメラ
+    fn cameras(count: usize) -> Vec<CalibratedCamera> {
+        let intrinsics = Intrinsics {
+            fx: 700.0,
+            fy: 710.0,
+            cx: 320.0,
+            cy: 240.0,
+            dist: [-0.08, 0.02, 0.001, -0.001, 0.0],
+        };
+        [-0.6, 0.3, -0.2, 0.7]
+            .iter()
+            .enumerate()
+            .take(count)
+            .map(|(i, &yaw)| CalibratedCamera {
+                intrinsics,
+                pose: Pose {
+                    rotation: [0.1 * i as f64 - 0.1, yaw, 0.02],
+                    translation: [0.05 * i as f64, -0.03, 2.0],
+                },
+            })
+            .collect()
+    }
+
+    const POINTS: [Vec3; 4] = [
+        [0.0, 0.0, 0.0],
+        [0.1, -0.2, 0.05],
+        [-0.3, 0.1, 0.2],
+        [0.25, 0.3, -0.15],
+    ];
+
+    fn observe(
+        cameras: &[CalibratedCamera],
+        p: &Vec3,
+    ) -> Vec<PointObservation> {
+        cameras
+            .iter()
+            .enumerate()
+            .map(|(camera, c)| PointObservation {
+                camera,
+                point: c.project(p).expect("point is in front of the camera"),
+            })
+            .collect()
+    }
+
+    fn distance(a: &Vec3, b: &Vec3) -> f64 {
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2))
+            .sqrt()
+    }
+
+    #[test]
+    fn recovers_points_from_exact_projections() {
+        for count in 2..=4 {
+            let cameras = cameras(count);
+            for refine in [false, true] {
+                let options = TriangulationOptions {
+                    refine,
+                    ..Default::default()
+                };
+                for p in &POINTS {
+                    let point =
+                        triangulate(&cameras, &observe(&cameras, p), &options)
+                            .expect("point should triangulate");
+                    assert!(
+                        distance(&point.position, p) < 1e-6,
+                        "{count} cameras, refine {refine}: {point:?}"
+                    );
+                    assert_eq!(point.inlier_count(), count);
+                    assert!(point.rms < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ransac_rejects_outlier_view() {
+        let cameras = cameras(4);
+        for p in &POINTS {
+            let mut observations = observe(&cameras, p);
+            observations[2].point[0] += 40.0;
+            observations[2].point[1] -= 25.0;
+
+            let point =
+                triangulate(&cameras, &observations, &Default::default())
+                    .expect("point should triangulate");
+            assert_eq!(point.inliers, vec![true, true, false, true]);
+            assert!(distance(&point.position, p) < 1e-6, "{point:?}");
+            assert!(point.confidence < 1.0);
+        }
+    }
+}
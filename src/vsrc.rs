@@ -1,45 +1,322 @@
 use anyhow::anyhow;
-use memmap2::Mmap;
 use opencv::core::Mat;
 use opencv::imgcodecs;
 use opencv::imgproc;
 use opencv::prelude::*;
 use opencv::videoio::{VideoCapture, VideoCaptureTrait};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::ErrorKind;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::calibration::Pose;
+use crate::mmap_frame::read_exact_at;
+use crate::{
+    CaptureDeviceId, FrameSnapshot, ImageSequence, MMAP_FRAME_HEADER_LEN,
+    MmapFrameHeader, PixelFormat, PlaybackHandle, PlaybackSource,
+    SyntheticConfig, SyntheticSource, snapshot_frame_file,
+};
+
+// ファイルが無い間に確認する間隔
+const MISSING_FILE_WAIT: Duration = Duration::from_millis(100);
+// 切断されたカメラを開き直す間隔。失敗するたびに倍にする
+const RECONNECT_MIN_WAIT: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_WAIT: Duration = Duration::from_secs(8);
+// これだけ続けてフレームが取れなければ切断とみなす
+const MAX_FAILED_READS: u32 = 5;
+
+/// An RGB image read from a [`VideoSource`].
+#[derive(Debug)]
+pub struct SourceFrame {
+    pub image: Mat,
+    // 合成ソースのみ。描画に使ったboard->cameraの姿勢
+    pub ground_truth: Option<Pose>,
+    // 再生ソースのみ。収録開始からの経過時間
+    pub recorded_at: Option<Duration>,
+}
+
+impl From<Mat> for SourceFrame {
+    fn from(image: Mat) -> Self {
+        Self {
+            image,
+            ground_truth: None,
+            recorded_at: None,
+        }
+    }
+}
+
+/// Why [`VideoSource::read`] returned no frame.
+#[derive(Debug)]
+pub enum VideoSourceError {
+    // 次のフレームがまだ無いだけ。すぐに読み直してよい
+    NotReady,
+    // デバイスやファイルが無くなった
+    Disconnected(String),
+    // フレームを読み出せたが画像にできなかった
+    Read(String),
+    OpenCv(opencv::Error),
+}
+
+impl fmt::Display for VideoSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoSourceError::NotReady => write!(f, "no new frame yet"),
+            VideoSourceError::Disconnected(reason) => {
+                write!(f, "disconnected: {reason}")
+            }
+            VideoSourceError::Read(reason) => {
+                write!(f, "failed to read frame: {reason}")
+            }
+            VideoSourceError::OpenCv(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for VideoSourceError {}
+
+impl From<opencv::Error> for VideoSourceError {
+    fn from(err: opencv::Error) -> Self {
+        VideoSourceError::OpenCv(err)
+    }
+}
+
+/// What an MMAP source is currently seeing of its file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MmapSourceState {
+    // ファイルはあるがまだフレームが来ていない
+    #[default]
+    Waiting,
+    Streaming,
+    // ファイルが作り直された。次のフレームが来るまでこの状態
+    Restarted,
+    // ファイルが消えている
+    Missing,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MmapStatus {
+    pub state: MmapSourceState,
+    // ファイルが作り直された回数
+    pub restarts: u32,
+}
+
+/// Reads the [`MmapStatus`] of an MMAP source from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct MmapStatusHandle(Arc<Mutex<MmapStatus>>);
+
+impl MmapStatusHandle {
+    pub fn status(&self) -> MmapStatus {
+        *self.0.lock().unwrap()
+    }
+
+    fn set_state(&self, state: MmapSourceState) {
+        self.0.lock().unwrap().state = state;
+    }
+
+    fn restarted(&self) {
+        let mut status = self.0.lock().unwrap();
+        status.state = MmapSourceState::Restarted;
+        status.restarts += 1;
+    }
+}
+
+// 開いたファイルを見分けるための情報
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileIdentity {
+    #[cfg(unix)]
+    dev: u64,
+    #[cfg(unix)]
+    ino: u64,
+}
+
+impl FileIdentity {
+    fn of(
+        #[cfg_attr(not(unix), allow(unused_variables))] metadata: &Metadata,
+    ) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            #[cfg(unix)]
+            dev: metadata.dev(),
+            #[cfg(unix)]
+            ino: metadata.ino(),
+        }
+    }
+}
+
+// 書き込み側のファイルはマップせずpreadで読む。マップしていると
+// 切り詰められた瞬間に触ったページでSIGBUSになるが、preadなら
+// 短く読めてエラーになるだけで済む
+struct OpenedFile {
+    file: File,
+    identity: FileIdentity,
+}
+
+impl OpenedFile {
+    fn open(path: &str) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        // パスではなく開いたファイル自体の情報を使い、
+        // 開いた後に差し替えられても混ざらないようにする
+        let identity = FileIdentity::of(&file.metadata()?);
+        Ok(Self { file, identity })
+    }
+}
 
 pub struct MMAPCam {
-    mmap: Mmap,
+    opened: Option<OpenedFile>,
     path: String,
+    // ヘッダ付き形式で最後に出したフレームの番号
+    last_sequence: Option<u64>,
+    status: MmapStatusHandle,
 }
 
 impl MMAPCam {
-    pub fn new<P: AsRef<Path> + Clone>(path: P) -> std::io::Result<Self> {
-        let file = File::open(path.clone())?;
-        // Safety: We assume the file is not concurrently modified in a way that causes UB,
-        // though for IPC with mmap, race conditions on content are expected but usually safe for read-only mapping of data.
-        // However, if the file is truncated while mapped, it can cause SIGBUS.
-        // The Python code uses it for reading images, presumably written by another process.
-        let mmap = unsafe { Mmap::map(&file)? };
+    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        let opened = OpenedFile::open(&path)?;
         Ok(Self {
-            mmap,
-            path: path.as_ref().to_string_lossy().into_owned(),
+            opened: Some(opened),
+            path,
+            last_sequence: None,
+            status: MmapStatusHandle::default(),
         })
     }
 
-    fn read(&mut self) -> Result<Mat, ()> {
-        // Create a Mat from the mmap slice (as a byte buffer)
-        // We use from_slice to create a 1D Mat of bytes
-        let bytes_mat = Mat::from_slice(&self.mmap).map_err(|_| ())?;
+    pub fn status_handle(&self) -> MmapStatusHandle {
+        self.status.clone()
+    }
+
+    // ファイルが差し替えられていれば開き直す。同じファイルの伸び縮みは
+    // preadがそのまま扱える
+    fn refresh(&mut self) -> std::io::Result<()> {
+        let current = match fs::metadata(&self.path) {
+            Ok(metadata) => FileIdentity::of(&metadata),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                // もう更新されないので閉じる
+                self.opened = None;
+                self.status.set_state(MmapSourceState::Missing);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        let restarted = match &self.opened {
+            Some(opened) if opened.identity == current => return Ok(()),
+            Some(_) => true,
+            // 消えていたファイルが作り直された
+            None => self.status.status().state == MmapSourceState::Missing,
+        };
+
+        self.opened = None;
+        self.opened = Some(OpenedFile::open(&self.path)?);
+        if restarted {
+            self.restart();
+        }
+        Ok(())
+    }
+
+    fn restart(&mut self) {
+        eprintln!("MMAP source {} restarted", self.path);
+        self.last_sequence = None;
+        self.status.restarted();
+    }
+
+    fn read(&mut self) -> Result<Mat, VideoSourceError> {
+        if let Err(err) = self.refresh() {
+            self.opened = None;
+            thread::sleep(MISSING_FILE_WAIT);
+            return Err(VideoSourceError::Read(format!(
+                "failed to open {}: {err}",
+                self.path
+            )));
+        }
+        let Some(opened) = &self.opened else {
+            thread::sleep(MISSING_FILE_WAIT);
+            return Err(VideoSourceError::Disconnected(format!(
+                "{} is missing",
+                self.path
+            )));
+        };
+
+        let mut header = [0; MMAP_FRAME_HEADER_LEN];
+        let framed = match read_exact_at(&opened.file, &mut header, 0) {
+            Ok(()) => MmapFrameHeader::is_framed(&header),
+            // ヘッダより短いのは旧形式の画像か空のファイル
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => false,
+            Err(err) => {
+                thread::sleep(MISSING_FILE_WAIT);
+                return Err(VideoSourceError::Read(format!(
+                    "failed to read {}: {err}",
+                    self.path
+                )));
+            }
+        };
+        if !framed {
+            let frame = Self::read_legacy(&opened.file);
+            if frame.is_ok() {
+                self.status.set_state(MmapSourceState::Streaming);
+            }
+            return frame;
+        }
+
+        match snapshot_frame_file(&opened.file, self.last_sequence) {
+            Ok(FrameSnapshot::Frame(header, payload)) => {
+                // 書き込み側が作り直されると番号が1からやり直しになる
+                if self.last_sequence.is_some_and(|last| header.sequence < last)
+                {
+                    self.restart();
+                }
+                self.last_sequence = Some(header.sequence);
+                let frame = decode_frame(&header, &payload)
+                    .map_err(|err| VideoSourceError::Read(err.to_string()))?;
+                self.status.set_state(MmapSourceState::Streaming);
+                Ok(frame)
+            }
+            // 新しいフレームが来るまで少し待つ
+            Ok(FrameSnapshot::Unchanged | FrameSnapshot::Busy) => {
+                thread::sleep(Duration::from_millis(1));
+                Err(VideoSourceError::NotReady)
+            }
+            Err(err) => {
+                thread::sleep(Duration::from_millis(1));
+                Err(VideoSourceError::Read(format!(
+                    "invalid frame in {}: {err}",
+                    self.path
+                )))
+            }
+        }
+    }
+
+    // ヘッダの無いエンコード済み画像1枚だけのファイル
+    fn read_legacy(file: &File) -> Result<Mat, VideoSourceError> {
+        let len = file.metadata().map_or(0, |m| m.len());
+        if len == 0 {
+            // 書き込み側が最初のフレームを書くのを待つ
+            thread::sleep(MISSING_FILE_WAIT);
+            return Err(VideoSourceError::NotReady);
+        }
+        // 読んでいる間に切り詰められるとUnexpectedEofになる
+        let mut bytes = vec![0; len as usize];
+        read_exact_at(file, &mut bytes, 0).map_err(|err| {
+            VideoSourceError::Read(format!("failed to read image: {err}"))
+        })?;
+
+        // Create a 1D Mat of bytes from the owned buffer
+        let bytes_mat = Mat::from_slice(&bytes)?;
 
         // Decode the image
         // IMREAD_COLOR loads as BGR
-        let frame = imgcodecs::imdecode(&bytes_mat, imgcodecs::IMREAD_COLOR)
-            .map_err(|_| ())?;
+        let frame = imgcodecs::imdecode(&bytes_mat, imgcodecs::IMREAD_COLOR)?;
 
         if frame.empty() {
-            return Err(());
+            return Err(VideoSourceError::Read(
+                "file is not a valid image".to_owned(),
+            ));
         }
 
         // Convert BGR to RGB to match Python's PIL .convert('RGB')
@@ -50,23 +327,286 @@ impl MMAPCam {
             imgproc::COLOR_BGR2RGB,
             0,
             opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
-        )
-        .map_err(|_| ())?;
+        )?;
+
+        Ok(rgb_frame)
+    }
+}
+
+fn decode_frame(
+    header: &MmapFrameHeader,
+    payload: &[u8],
+) -> anyhow::Result<Mat> {
+    let bytes = Mat::from_slice(payload)?;
+
+    let (frame, code) = match header.pixel_format.channels() {
+        // IMREAD_COLOR loads as BGR
+        None => (
+            imgcodecs::imdecode(&bytes, imgcodecs::IMREAD_COLOR)?,
+            Some(imgproc::COLOR_BGR2RGB),
+        ),
+        Some(channels) => {
+            let frame = bytes
+                .reshape(channels as i32, header.height as i32)?
+                .try_clone()?;
+            let code = match header.pixel_format {
+                PixelFormat::Bgr8 => Some(imgproc::COLOR_BGR2RGB),
+                PixelFormat::Rgba8 => Some(imgproc::COLOR_RGBA2RGB),
+                PixelFormat::Bgra8 => Some(imgproc::COLOR_BGRA2RGB),
+                _ => None,
+            };
+            (frame, code)
+        }
+    };
+    if frame.empty() {
+        return Err(anyhow!("empty frame"));
+    }
+
+    let Some(code) = code else {
+        return Ok(frame);
+    };
+    let mut rgb_frame = Mat::default();
+    imgproc::cvt_color(
+        &frame,
+        &mut rgb_frame,
+        code,
+        0,
+        opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+    )?;
+    Ok(rgb_frame)
+}
+
+/// A camera opened through OpenCV that reopens itself after being
+/// unplugged, finding the same physical device again by its identity.
+pub struct CaptureCam {
+    // 切断中はNone
+    capture: Option<VideoCapture>,
+    index: i32,
+    // 識別できないプラットフォームでは同じ番号を開き直す
+    device: Option<CaptureDeviceId>,
+    failed_reads: u32,
+    retry_at: Instant,
+    retry_wait: Duration,
+}
+
+impl CaptureCam {
+    /// Opens `device` wherever it is now, or `index` when no device is
+    /// given or devices cannot be identified on this platform. A device
+    /// that is not plugged in starts disconnected and is opened once it
+    /// appears.
+    pub fn open(
+        index: i32,
+        device: Option<CaptureDeviceId>,
+    ) -> anyhow::Result<Self> {
+        let Some(index) = Self::resolve_index(device.as_ref(), index) else {
+            // 抜かれたままでも設定を失わないよう、切断状態で始める
+            eprintln!(
+                "Camera {} is not connected",
+                device.as_ref().map_or("", |d| d.name.as_str())
+            );
+            return Ok(Self {
+                capture: None,
+                index,
+                device,
+                failed_reads: 0,
+                retry_at: Instant::now(),
+                retry_wait: RECONNECT_MIN_WAIT,
+            });
+        };
+        let capture = Self::open_capture(index)?;
+        Ok(Self {
+            capture: Some(capture),
+            index,
+            // 古い設定には識別情報が無いので、開いたデバイスから取り直す
+            device: CaptureDeviceId::of_index(index).or(device),
+            failed_reads: 0,
+            retry_at: Instant::now(),
+            retry_wait: RECONNECT_MIN_WAIT,
+        })
+    }
+
+    /// The config that reopens this device, with its identity resolved.
+    pub fn config(&self) -> VideoSourceConfig {
+        VideoSourceConfig::Capture {
+            index: self.index,
+            device: self.device.clone(),
+        }
+    }
+
+    fn resolve_index(
+        device: Option<&CaptureDeviceId>,
+        index: i32,
+    ) -> Option<i32> {
+        match device {
+            Some(device) if CaptureDeviceId::supported() => {
+                device.find_index(index)
+            }
+            _ => Some(index),
+        }
+    }
+
+    fn open_capture(index: i32) -> anyhow::Result<VideoCapture> {
+        let vcap = VideoCapture::new(index, opencv::videoio::CAP_ANY)?;
+        if !vcap.is_opened()? {
+            return Err(anyhow!("Failed to open camera at index {index}"));
+        }
+        Ok(vcap)
+    }
+
+    // デバイスを閉じて再接続を待つ
+    fn disconnect(&mut self, reason: &str) -> VideoSourceError {
+        if let Some(mut vcap) = self.capture.take()
+            && let Err(err) = vcap.release()
+        {
+            eprintln!("Failed to release video capture: {err}");
+        }
+        self.failed_reads = 0;
+        self.retry_wait = RECONNECT_MIN_WAIT;
+        self.retry_at = Instant::now() + self.retry_wait;
+        VideoSourceError::Disconnected(reason.to_owned())
+    }
+
+    fn reconnect(&mut self) -> Result<(), VideoSourceError> {
+        if Instant::now() < self.retry_at {
+            return Err(VideoSourceError::Disconnected(
+                "waiting to reconnect".to_owned(),
+            ));
+        }
+        // 抜き差しで番号が変わるので同じデバイスを探し直す
+        let Some(index) = Self::resolve_index(self.device.as_ref(), self.index)
+        else {
+            self.back_off();
+            return Err(VideoSourceError::Disconnected(
+                "camera not found".to_owned(),
+            ));
+        };
+        match Self::open_capture(index) {
+            Ok(vcap) => {
+                eprintln!("Camera reconnected at index {index}");
+                self.capture = Some(vcap);
+                self.index = index;
+                self.retry_wait = RECONNECT_MIN_WAIT;
+                Ok(())
+            }
+            Err(err) => {
+                self.back_off();
+                Err(VideoSourceError::Disconnected(err.to_string()))
+            }
+        }
+    }
+
+    fn back_off(&mut self) {
+        self.retry_wait = (self.retry_wait * 2).min(RECONNECT_MAX_WAIT);
+        self.retry_at = Instant::now() + self.retry_wait;
+    }
+
+    fn read(&mut self) -> Result<Mat, VideoSourceError> {
+        if self.capture.is_none() {
+            self.reconnect()?;
+        }
+        let Some(vcap) = &mut self.capture else {
+            return Err(VideoSourceError::NotReady);
+        };
+
+        let mut frame = Mat::default();
+        let success = match VideoCaptureTrait::read(vcap, &mut frame) {
+            Ok(success) => success,
+            Err(err) => return Err(self.disconnect(&err.to_string())),
+        };
+
+        // 抜かれたカメラはフレームを返さなくなる。
+        // 開いた直後に空のフレームを返すカメラもあるので少しは待つ
+        if !success || frame.empty() {
+            self.failed_reads += 1;
+            if self.failed_reads >= MAX_FAILED_READS {
+                return Err(self.disconnect("camera returned no frame"));
+            }
+            return Err(VideoSourceError::NotReady);
+        }
+        self.failed_reads = 0;
+
+        let mut rgb_frame = Mat::default();
+        imgproc::cvt_color(
+            &frame,
+            &mut rgb_frame,
+            imgproc::COLOR_BGR2RGB,
+            0,
+            opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
+        )?;
 
         Ok(rgb_frame)
     }
 }
 
-/// Enum holding either MMAPCam or VideoCapture
+impl Drop for CaptureCam {
+    fn drop(&mut self) {
+        if let Some(vcap) = &mut self.capture
+            && let Err(err) = vcap.release()
+        {
+            eprintln!("Failed to release video capture: {err}");
+        }
+    }
+}
+
+/// Enum holding the live and offline frame sources
 pub enum VideoSource {
     MMAP(MMAPCam),
-    Capture(VideoCapture),
+    Capture(CaptureCam),
+    Playback(PlaybackSource),
+    ImageSequence(ImageSequence),
+    Synthetic(SyntheticSource),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum VideoSourceConfig {
-    MMAP { path: String },
-    Capture { index: i32 },
+    MMAP {
+        path: String,
+    },
+    // indexは前回開いたときの番号。deviceがあればそちらで探し直す
+    Capture {
+        index: i32,
+        #[serde(default)]
+        device: Option<CaptureDeviceId>,
+    },
+    // pathはセッションディレクトリか動画ファイル。cameraはセッション内の番号
+    Playback {
+        path: String,
+        #[serde(default)]
+        camera: usize,
+        #[serde(default)]
+        looping: bool,
+    },
+    // patternは*と?が使えるファイル名のパターン
+    ImageSequence {
+        dir: String,
+        pattern: String,
+        fps: f64,
+    },
+    Synthetic(SyntheticConfig),
+}
+
+impl VideoSourceConfig {
+    /// Whether both configs capture from the same physical camera.
+    pub fn same_capture_device(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                VideoSourceConfig::Capture {
+                    index: a,
+                    device: device_a,
+                },
+                VideoSourceConfig::Capture {
+                    index: b,
+                    device: device_b,
+                },
+            ) => match (device_a, device_b) {
+                (Some(device_a), Some(device_b)) => {
+                    device_a.same_device(device_b).unwrap_or(a == b)
+                }
+                _ => a == b,
+            },
+            _ => false,
+        }
+    }
 }
 
 impl TryFrom<VideoSourceConfig> for VideoSource {
@@ -78,55 +618,70 @@ impl TryFrom<VideoSourceConfig> for VideoSource {
                 let mmap_cam = MMAPCam::new(path)?;
                 Ok(VideoSource::MMAP(mmap_cam))
             }
-            VideoSourceConfig::Capture { index } => {
-                let mut vcap =
-                    VideoCapture::new(index, opencv::videoio::CAP_ANY)?;
-                if !vcap.is_opened()? {
-                    return Err(anyhow!(
-                        "Failed to open camera at index {index}"
-                    ));
-                }
-                Ok(VideoSource::Capture(vcap))
+            VideoSourceConfig::Capture { index, device } => {
+                Ok(VideoSource::Capture(CaptureCam::open(index, device)?))
+            }
+            VideoSourceConfig::Playback {
+                path,
+                camera,
+                looping,
+            } => Ok(VideoSource::Playback(PlaybackSource::open(
+                path, camera, looping,
+            )?)),
+            VideoSourceConfig::ImageSequence { dir, pattern, fps } => {
+                Ok(VideoSource::ImageSequence(ImageSequence::new(
+                    dir, &pattern, fps,
+                )?))
+            }
+            VideoSourceConfig::Synthetic(config) => {
+                Ok(VideoSource::Synthetic(SyntheticSource::new(config)?))
             }
         }
     }
 }
 
 impl VideoSource {
-    pub fn read(&mut self) -> Result<Mat, ()> {
+    /// Transport controls, for playback sources only.
+    pub fn playback_handle(&self) -> Option<PlaybackHandle> {
         match self {
-            VideoSource::MMAP(mmap) => mmap.read(),
-            VideoSource::Capture(vcap) => {
-                let mut frame = Mat::default();
-                let success = VideoCaptureTrait::read(vcap, &mut frame)
-                    .map_err(|_| ())?;
-
-                if !success || frame.empty() {
-                    return Err(());
-                }
+            VideoSource::Playback(playback) => Some(playback.handle()),
+            _ => None,
+        }
+    }
 
-                let mut rgb_frame = Mat::default();
-                imgproc::cvt_color(
-                    &frame,
-                    &mut rgb_frame,
-                    imgproc::COLOR_BGR2RGB,
-                    0,
-                    opencv::core::AlgorithmHint::ALGO_HINT_DEFAULT,
-                )
-                .map_err(|_| ())?;
+    /// File status, for MMAP sources only.
+    pub fn mmap_status_handle(&self) -> Option<MmapStatusHandle> {
+        match self {
+            VideoSource::MMAP(mmap) => Some(mmap.status_handle()),
+            _ => None,
+        }
+    }
 
-                Ok(rgb_frame)
-            }
+    /// The config with the device identity resolved at open time, for
+    /// capture sources only.
+    pub fn capture_config(&self) -> Option<VideoSourceConfig> {
+        match self {
+            VideoSource::Capture(capture) => Some(capture.config()),
+            _ => None,
         }
     }
-}
 
-impl Drop for VideoSource {
-    fn drop(&mut self) {
+    /// Live sources deliver frames continuously, so a long pause means
+    /// the source stalled rather than that it is paused or slow.
+    pub fn is_live(&self) -> bool {
+        matches!(self, VideoSource::MMAP(_) | VideoSource::Capture(_))
+    }
+
+    pub fn read(&mut self) -> Result<SourceFrame, VideoSourceError> {
         match self {
-            VideoSource::MMAP(_) => {}
-            VideoSource::Capture(vcap) => {
-                vcap.release().expect("Failed to release video capture");
+            VideoSource::MMAP(mmap) => mmap.read().map(SourceFrame::from),
+            VideoSource::Playback(playback) => playback.read(),
+            VideoSource::ImageSequence(sequence) => {
+                sequence.read().map(SourceFrame::from)
+            }
+            VideoSource::Synthetic(synthetic) => synthetic.read(),
+            VideoSource::Capture(capture) => {
+                capture.read().map(SourceFrame::from)
             }
         }
     }
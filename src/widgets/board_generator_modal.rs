@@ -0,0 +1,139 @@
+use eframe::egui::{self, Context, Id, Modal};
+use mocap_for_one::{BoardPrintConfig, PaperSize};
+
+use crate::widgets::CharucoBoardEditor;
+
+pub struct BoardGeneratorModal {
+    pub open: bool,
+    pub config: BoardPrintConfig,
+    pub output_stem: String,
+}
+
+pub enum BoardGeneratorModalEffect {
+    OnGenerate(BoardPrintConfig, String),
+}
+
+impl BoardGeneratorModal {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            config: BoardPrintConfig::default(),
+            output_stem: "charuco_board".to_owned(),
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    pub fn show(&mut self, ctx: &Context) -> Option<BoardGeneratorModalEffect> {
+        if !self.open {
+            return None;
+        }
+
+        let mut ret = None;
+
+        Modal::new(Id::new("Generate Board Modal")).show(ctx, |ui| {
+            ui.heading("Generate ChArUco Board");
+
+            CharucoBoardEditor::new("board_generator_charuco_board")
+                .show(ui, &mut self.config.board);
+
+            ui.separator();
+
+            egui::Grid::new("board_generator_print").num_columns(2).show(
+                ui,
+                |ui| {
+                    ui.label("Paper");
+                    egui::ComboBox::from_id_salt("board_generator_paper")
+                        .selected_text(self.config.paper.name())
+                        .show_ui(ui, |ui| {
+                            for paper in PaperSize::PRESETS {
+                                ui.selectable_value(
+                                    &mut self.config.paper,
+                                    paper,
+                                    paper.name(),
+                                );
+                            }
+                            let is_custom = matches!(
+                                self.config.paper,
+                                PaperSize::Custom { .. }
+                            );
+                            if ui
+                                .selectable_label(is_custom, "Custom")
+                                .clicked()
+                                && !is_custom
+                            {
+                                let (width_mm, height_mm) =
+                                    self.config.paper.dimensions_mm();
+                                self.config.paper = PaperSize::Custom {
+                                    width_mm,
+                                    height_mm,
+                                };
+                            }
+                        });
+                    ui.end_row();
+
+                    if let PaperSize::Custom {
+                        width_mm,
+                        height_mm,
+                    } = &mut self.config.paper
+                    {
+                        ui.label("Paper width [mm]");
+                        ui.add(
+                            egui::DragValue::new(width_mm).range(50.0..=2000.0),
+                        );
+                        ui.end_row();
+
+                        ui.label("Paper height [mm]");
+                        ui.add(
+                            egui::DragValue::new(height_mm)
+                                .range(50.0..=2000.0),
+                        );
+                        ui.end_row();
+                    }
+
+                    ui.label("DPI");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.dpi)
+                            .range(72.0..=1200.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Output (without extension)");
+                    ui.text_edit_singleline(&mut self.output_stem);
+                    ui.end_row();
+                },
+            );
+
+            ui.separator();
+
+            egui::Sides::new().show(
+                ui,
+                |_left_ui| {},
+                |ui| {
+                    if ui.button("Cancel").clicked() {
+                        self.open = false;
+                    }
+
+                    if ui
+                        .add_enabled(
+                            self.config.board.validate().is_ok()
+                                && !self.output_stem.trim().is_empty(),
+                            egui::Button::new("Generate"),
+                        )
+                        .clicked()
+                    {
+                        ret = Some(BoardGeneratorModalEffect::OnGenerate(
+                            self.config.clone(),
+                            self.output_stem.trim().to_owned(),
+                        ));
+                        self.open = false;
+                    }
+                },
+            );
+        });
+
+        ret
+    }
+}
@@ -1,16 +1,388 @@
 use eframe::egui;
+use egui_file::FileDialog;
+use mocap_for_one::{
+    BundleAdjustmentOptions, OffsetSignal, RobustLoss, SyncConfig, SyncMode,
+    TimeOffsetOptions, TriangulationOptions, WorkLoad, vec3_norm, vec3_sub,
+};
+use std::path::PathBuf;
 
-pub struct CalibrationModal {}
+pub struct CalibrationModal {
+    pub open: bool,
+    pub reference: usize,
+    pub bundle_options: BundleAdjustmentOptions,
+    pub offset_options: TimeOffsetOptions,
+    // 時刻オフセットを推定するセッションの選択
+    pub dialog: Option<FileDialog>,
+}
 
-pub enum CalibrationModalEffect {}
+pub enum CalibrationModalEffect {
+    OnCaptureAll,
+    OnClearCaptures,
+    OnSolveExtrinsics(usize),
+    OnRefineCalibration(usize, BundleAdjustmentOptions),
+    OnSyncConfigChanged(SyncConfig),
+    // セッションディレクトリ, 基準カメラ
+    OnEstimateTimeOffsets(PathBuf, usize, TimeOffsetOptions),
+}
 
 impl CalibrationModal {
-    pub fn open(&mut self) {}
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            reference: 0,
+            bundle_options: BundleAdjustmentOptions::default(),
+            offset_options: TimeOffsetOptions::default(),
+            dialog: None,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
 
     pub fn show(
         &mut self,
         ctx: &egui::Context,
+        workload: &WorkLoad,
     ) -> Option<CalibrationModalEffect> {
-        None
+        let mut ret = None;
+        let cams = &workload.opencv_cams;
+
+        if let Some(dialog) = &mut self.dialog {
+            dialog.show(ctx);
+            if dialog.selected()
+                && let Some(path) = dialog.path()
+            {
+                ret = Some(CalibrationModalEffect::OnEstimateTimeOffsets(
+                    path.to_path_buf(),
+                    self.reference,
+                    self.offset_options,
+                ));
+                self.dialog = None;
+            }
+        }
+
+        let reference = &mut self.reference;
+        let bundle_options = &mut self.bundle_options;
+        let offset_options = &mut self.offset_options;
+        let mut open_dialog = false;
+
+        if *reference >= cams.len() {
+            *reference = 0;
+        }
+
+        egui::Window::new("Multi-Camera Calibration")
+            .open(&mut self.open)
+            .show(ctx, |ui| {
+                if cams.is_empty() {
+                    ui.label("No camera opened");
+                    return;
+                }
+
+                egui::ComboBox::from_label("Reference camera")
+                    .selected_text(
+                        cams[*reference]
+                            .opencv_camera
+                            .camera_stream_config
+                            .name
+                            .clone(),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (i, cam) in cams.iter().enumerate() {
+                            ui.selectable_value(
+                                reference,
+                                i,
+                                format!(
+                                    "{}: {}",
+                                    i,
+                                    cam.opencv_camera.camera_stream_config.name
+                                ),
+                            );
+                        }
+                    });
+
+                if let Some(config) = show_sync(ui, workload) {
+                    ret = Some(CalibrationModalEffect::OnSyncConfigChanged(
+                        config,
+                    ));
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Time offsets");
+                    egui::ComboBox::from_id_salt("calibration_modal_signal")
+                        .selected_text(format!("{:?}", offset_options.signal))
+                        .show_ui(ui, |ui| {
+                            for signal in [
+                                OffsetSignal::BoardMotion,
+                                OffsetSignal::Brightness,
+                            ] {
+                                ui.selectable_value(
+                                    &mut offset_options.signal,
+                                    signal,
+                                    format!("{:?}", signal),
+                                );
+                            }
+                        });
+                    let estimating = workload.is_estimating_time_offsets();
+                    if ui
+                        .add_enabled(
+                            !estimating,
+                            egui::Button::new("Estimate from Session"),
+                        )
+                        .clicked()
+                    {
+                        open_dialog = true;
+                    }
+                    if estimating {
+                        ui.spinner();
+                    }
+                });
+
+                ui.separator();
+
+                ui.label(format!(
+                    "Synchronized captures: {}",
+                    workload.synchronized_captures.len()
+                ));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Capture All").clicked() {
+                        ret = Some(CalibrationModalEffect::OnCaptureAll);
+                    }
+                    if ui.button("Clear").clicked() {
+                        ret = Some(CalibrationModalEffect::OnClearCaptures);
+                    }
+                    if ui
+                        .add_enabled(
+                            cams.len() >= 2
+                                && !workload.synchronized_captures.is_empty(),
+                            egui::Button::new("Solve Extrinsics"),
+                        )
+                        .clicked()
+                    {
+                        ret = Some(CalibrationModalEffect::OnSolveExtrinsics(
+                            *reference,
+                        ));
+                    }
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(
+                        &mut bundle_options.fix_intrinsics,
+                        "Fix intrinsics",
+                    );
+
+                    let (name, scale) = match bundle_options.loss {
+                        RobustLoss::Squared => ("Squared", 1.0),
+                        RobustLoss::Huber(s) => ("Huber", s),
+                        RobustLoss::Cauchy(s) => ("Cauchy", s),
+                    };
+                    egui::ComboBox::from_id_salt("calibration_modal_loss")
+                        .selected_text(name)
+                        .show_ui(ui, |ui| {
+                            for (label, loss) in [
+                                ("Squared", RobustLoss::Squared),
+                                ("Huber", RobustLoss::Huber(scale)),
+                                ("Cauchy", RobustLoss::Cauchy(scale)),
+                            ] {
+                                if ui
+                                    .selectable_label(label == name, label)
+                                    .clicked()
+                                {
+                                    bundle_options.loss = loss;
+                                }
+                            }
+                        });
+                    if let RobustLoss::Huber(scale)
+                    | RobustLoss::Cauchy(scale) = &mut bundle_options.loss
+                    {
+                        ui.add(
+                            egui::DragValue::new(scale)
+                                .range(0.1..=50.0)
+                                .speed(0.1)
+                                .suffix(" px"),
+                        );
+                    }
+
+                    if ui
+                        .add_enabled(
+                            cams[*reference].extrinsic.is_some(),
+                            egui::Button::new("Bundle Adjust"),
+                        )
+                        .clicked()
+                    {
+                        ret =
+                            Some(CalibrationModalEffect::OnRefineCalibration(
+                                *reference,
+                                *bundle_options,
+                            ));
+                    }
+                });
+
+                ui.separator();
+
+                live_triangulation_label(ui, workload);
+
+                ui.separator();
+
+                egui::Grid::new("calibration_modal_cameras")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Camera");
+                        ui.label("Intrinsics");
+                        ui.label("Board views");
+                        ui.label("Position [m] / rotation [deg]");
+                        ui.label("Offset [ms]");
+                        ui.end_row();
+
+                        for (i, cam) in cams.iter().enumerate() {
+                            let views = workload
+                                .synchronized_captures
+                                .iter()
+                                .filter(|c| {
+                                    c.frames.get(i).is_some_and(Option::is_some)
+                                })
+                                .count();
+
+                            ui.label(
+                                cam.opencv_camera
+                                    .camera_stream_config
+                                    .name
+                                    .as_str(),
+                            );
+                            ui.label(if cam.camera_parameter.is_some() {
+                                "calibrated"
+                            } else {
+                                "missing"
+                            });
+                            ui.label(format!("{}", views));
+                            ui.label(match &cam.extrinsic {
+                                Some(pose) => {
+                                    let c = pose.center();
+                                    format!(
+                                        "({:.3}, {:.3}, {:.3}) / {:.1}",
+                                        c[0],
+                                        c[1],
+                                        c[2],
+                                        vec3_norm(&pose.rotation).to_degrees()
+                                    )
+                                }
+                                None => "not solved".to_owned(),
+                            });
+                            ui.label(format!(
+                                "{:.1}",
+                                cam.time_offset * 1000.0
+                            ));
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if open_dialog {
+            let mut d = FileDialog::select_folder(None);
+            d.open();
+            self.dialog = Some(d);
+        }
+
+        ret
+    }
+}
+
+// 現在検出中のChArUcoコーナーを三角測量し、隣接コーナー間の距離を
+// ボードのマス目の長さと比べてキャリブレーションの精度を確認する
+fn live_triangulation_label(ui: &mut egui::Ui, workload: &WorkLoad) {
+    let points = match workload
+        .triangulate_charuco_corners(&TriangulationOptions::default())
+    {
+        Ok(points) => points,
+        Err(err) => {
+            ui.label(format!("Triangulation failed: {}", err));
+            return;
+        }
+    };
+    if points.is_empty() {
+        ui.label("Live triangulation: no corner seen by 2 calibrated cameras");
+        return;
+    }
+
+    let rms = (points.values().map(|p| p.rms * p.rms).sum::<f64>()
+        / points.len() as f64)
+        .sqrt();
+    ui.label(format!(
+        "Live triangulation: {} corners, reprojection RMS {:.2} px",
+        points.len(),
+        rms
+    ));
+
+    let Some(board) = workload
+        .opencv_cams
+        .first()
+        .map(|c| &c.opencv_camera.charuco_board_config)
+    else {
+        return;
+    };
+    // コーナーidは行ごとに(squares_x - 1)個並ぶ
+    let per_row = board.squares_x - 1;
+    let spacings: Vec<f64> = points
+        .iter()
+        .filter(|(id, _)| per_row > 0 && (*id + 1) % per_row != 0)
+        .filter_map(|(id, p)| {
+            let next = points.get(&(id + 1))?;
+            Some(vec3_norm(&vec3_sub(&next.position, &p.position)))
+        })
+        .collect();
+    if !spacings.is_empty() {
+        ui.label(format!(
+            "Adjacent corner spacing {:.1} mm (board {:.1} mm)",
+            spacings.iter().sum::<f64>() / spacings.len() as f64 * 1000.0,
+            board.square_length * 1000.0
+        ));
     }
 }
+
+// フレーム同期の設定と統計。設定が変わったら新しい設定を返す
+fn show_sync(ui: &mut egui::Ui, workload: &WorkLoad) -> Option<SyncConfig> {
+    let mut config = workload.sync_config();
+    ui.horizontal(|ui| {
+        ui.label("Frame sync");
+        egui::ComboBox::from_id_salt("calibration_modal_sync_mode")
+            .selected_text(format!("{:?}", config.mode))
+            .show_ui(ui, |ui| {
+                for mode in [SyncMode::LatestComplete, SyncMode::Interpolated] {
+                    ui.selectable_value(
+                        &mut config.mode,
+                        mode,
+                        format!("{:?}", mode),
+                    );
+                }
+            });
+        let mut tolerance_ms = config.tolerance * 1000.0;
+        if ui
+            .add(
+                egui::DragValue::new(&mut tolerance_ms)
+                    .range(1.0..=200.0)
+                    .speed(0.5)
+                    .prefix("tolerance ")
+                    .suffix(" ms"),
+            )
+            .changed()
+        {
+            config.tolerance = tolerance_ms / 1000.0;
+        }
+    });
+
+    let stats = workload.sync_stats();
+    ui.label(format!(
+        "Sets: {}, incomplete: {}, skew last {:.1} / mean {:.1} / max {:.1} ms",
+        stats.sets,
+        stats.incomplete,
+        stats.last_skew * 1000.0,
+        stats.mean_skew * 1000.0,
+        stats.max_skew * 1000.0
+    ));
+
+    (config != workload.sync_config()).then_some(config)
+}
@@ -0,0 +1,94 @@
+use eframe::egui;
+use mocap_for_one::{ArucoDictionary, CharucoBoardConfig};
+
+pub struct CharucoBoardEditor {
+    id: egui::Id,
+}
+
+impl CharucoBoardEditor {
+    pub fn new(id: impl std::hash::Hash) -> Self {
+        Self {
+            id: egui::Id::new(id),
+        }
+    }
+
+    /// Edits the board in place and returns true if any field changed.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        config: &mut CharucoBoardConfig,
+    ) -> bool {
+        let mut changed = false;
+
+        egui::Grid::new(self.id.with("grid")).num_columns(2).show(ui, |ui| {
+            ui.label("Squares X");
+            changed |= ui
+                .add(egui::DragValue::new(&mut config.squares_x).range(2..=64))
+                .changed();
+            ui.end_row();
+
+            ui.label("Squares Y");
+            changed |= ui
+                .add(egui::DragValue::new(&mut config.squares_y).range(2..=64))
+                .changed();
+            ui.end_row();
+
+            // UI上はmm単位で編集し、設定にはm単位で保存する
+            let mut square_mm = config.square_length * 1000.0;
+            ui.label("Square [mm]");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut square_mm)
+                        .range(1.0..=1000.0)
+                        .speed(0.1),
+                )
+                .changed()
+            {
+                config.square_length = square_mm / 1000.0;
+                changed = true;
+            }
+            ui.end_row();
+
+            let mut marker_mm = config.marker_length * 1000.0;
+            ui.label("Marker [mm]");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut marker_mm)
+                        .range(1.0..=1000.0)
+                        .speed(0.1),
+                )
+                .changed()
+            {
+                config.marker_length = marker_mm / 1000.0;
+                changed = true;
+            }
+            ui.end_row();
+
+            ui.label("Dictionary");
+            egui::ComboBox::from_id_salt(self.id.with("dictionary"))
+                .selected_text(config.dictionary.name())
+                .show_ui(ui, |ui| {
+                    for dictionary in ArucoDictionary::ALL {
+                        changed |= ui
+                            .selectable_value(
+                                &mut config.dictionary,
+                                dictionary,
+                                dictionary.name(),
+                            )
+                            .changed();
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Legacy pattern");
+            changed |= ui.checkbox(&mut config.legacy_pattern, "").changed();
+            ui.end_row();
+        });
+
+        if let Err(err) = config.validate() {
+            ui.colored_label(egui::Color32::RED, err.to_string());
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,128 @@
+use eframe::egui::{self, Context, Id, Modal};
+use egui_file::{FileDialog, State};
+use mocap_for_one::{CameraStream, VideoSourceConfig};
+use std::path::PathBuf;
+
+pub struct ImageSequenceModal {
+    pub open: bool,
+    pub dialog: Option<FileDialog>,
+    pub dir: String,
+    pub pattern: String,
+    pub fps: f64,
+}
+
+pub enum ImageSequenceModalEffect {
+    OnOpenCamera(CameraStream),
+    OnError(String),
+}
+
+impl ImageSequenceModal {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            dialog: None,
+            dir: String::new(),
+            pattern: "*".to_owned(),
+            // 静止画を1枚ずつ確認してキャプチャできる程度の速さ
+            fps: 1.0,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    pub fn show(&mut self, ctx: &Context) -> Option<ImageSequenceModalEffect> {
+        // Modalの裏ではダイアログを操作できないので、選び終わるまで閉じておく
+        if let Some(dialog) = &mut self.dialog {
+            dialog.show(ctx);
+            if dialog.state() != State::Open {
+                if dialog.selected()
+                    && let Some(path) = dialog.path()
+                {
+                    self.dir = path.to_string_lossy().into_owned();
+                }
+                self.dialog = None;
+                self.open = true;
+            }
+        }
+
+        if !self.open {
+            return None;
+        }
+
+        let mut ret = None;
+
+        Modal::new(Id::new("Open Image Sequence Modal")).show(ctx, |ui| {
+            ui.heading("Open Image Sequence");
+
+            egui::Grid::new("image_sequence_modal_grid").num_columns(2).show(
+                ui,
+                |ui| {
+                    ui.label("Folder");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut self.dir);
+                        if ui.button("Browse").clicked() {
+                            let initial = (!self.dir.is_empty())
+                                .then(|| PathBuf::from(&self.dir));
+                            let mut d = FileDialog::select_folder(initial);
+                            d.open();
+                            self.dialog = Some(d);
+                            self.open = false;
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Pattern");
+                    ui.text_edit_singleline(&mut self.pattern);
+                    ui.end_row();
+
+                    ui.label("FPS");
+                    ui.add(
+                        egui::DragValue::new(&mut self.fps)
+                            .range(0.1..=120.0)
+                            .speed(0.1),
+                    );
+                    ui.end_row();
+                },
+            );
+
+            ui.separator();
+
+            egui::Sides::new().show(
+                ui,
+                |_left_ui| {},
+                |ui| {
+                    if ui.button("Cancel").clicked() {
+                        self.open = false;
+                    }
+                    if ui.button("Open").clicked() {
+                        let config = VideoSourceConfig::ImageSequence {
+                            dir: self.dir.clone(),
+                            pattern: self.pattern.clone(),
+                            fps: self.fps,
+                        };
+                        ret = Some(
+                            match CameraStream::new(self.dir.clone(), config) {
+                                Ok(stream) => {
+                                    ImageSequenceModalEffect::OnOpenCamera(
+                                        stream,
+                                    )
+                                }
+                                Err(err) => {
+                                    ImageSequenceModalEffect::OnError(format!(
+                                        "Failed to open image sequence: {}",
+                                        err
+                                    ))
+                                }
+                            },
+                        );
+                        self.open = false;
+                    }
+                },
+            );
+        });
+
+        ret
+    }
+}
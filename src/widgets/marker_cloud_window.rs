@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+use eframe::egui;
+use mocap_for_one::{AssignmentMethod, TrackEvent, TrackedMarkers, WorkLoad};
+
+// イベントログに残す件数
+const MAX_EVENTS: usize = 20;
+
+pub struct MarkerCloudWindow {
+    pub open: bool,
+    events: VecDeque<String>,
+}
+
+impl MarkerCloudWindow {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    /// `tracked` is the result of `WorkLoad::update_tracking` for this
+    /// repaint. Its events are logged even while the window is closed.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        workload: &mut WorkLoad,
+        tracked: Option<&TrackedMarkers>,
+    ) {
+        if let Some(tracked) = tracked {
+            for event in &tracked.update.events {
+                self.events.push_front(match event {
+                    TrackEvent::Born { id, .. } => format!("#{} born", id),
+                    TrackEvent::Died { id, .. } => format!("#{} died", id),
+                });
+            }
+            self.events.truncate(MAX_EVENTS);
+        }
+        let events = &self.events;
+
+        egui::Window::new("Markers").open(&mut self.open).show(ctx, |ui| {
+            let options = &mut workload.marker_options;
+            ui.horizontal(|ui| {
+                ui.label("Max distance");
+                ui.add(
+                    egui::DragValue::new(&mut options.max_distance)
+                        .range(0.1..=50.0)
+                        .speed(0.1)
+                        .suffix(" px"),
+                );
+                options.triangulation.inlier_threshold = options.max_distance;
+            });
+
+            let tracker = &mut workload.marker_tracker.config;
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("marker_cloud_window_assignment")
+                    .selected_text(format!("{:?}", tracker.assignment))
+                    .show_ui(ui, |ui| {
+                        for method in [
+                            AssignmentMethod::Hungarian,
+                            AssignmentMethod::NearestNeighbor,
+                        ] {
+                            ui.selectable_value(
+                                &mut tracker.assignment,
+                                method,
+                                format!("{:?}", method),
+                            );
+                        }
+                    });
+
+                // UI上はmm単位
+                let mut gate_mm = tracker.max_distance * 1000.0;
+                ui.label("Gate");
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut gate_mm)
+                            .range(1.0..=1000.0)
+                            .suffix(" mm"),
+                    )
+                    .changed()
+                {
+                    tracker.max_distance = gate_mm / 1000.0;
+                }
+
+                ui.label("Max gap");
+                ui.add(
+                    egui::DragValue::new(&mut tracker.max_gap)
+                        .range(0..=600)
+                        .suffix(" frames"),
+                );
+            });
+
+            let calibrated = workload
+                .opencv_cams
+                .iter()
+                .filter(|c| c.calibrated_camera().is_some())
+                .count();
+            if calibrated < 2 {
+                ui.label("At least 2 calibrated cameras are required");
+                return;
+            }
+
+            let Some(TrackedMarkers { cloud, update, .. }) =
+                workload.tracked_markers()
+            else {
+                ui.label("Waiting for synchronized frames");
+                return;
+            };
+
+            ui.label(format!(
+                "{} markers, {} tracks",
+                cloud.markers.len(),
+                workload.marker_tracker.tracks().len()
+            ));
+
+            egui::Grid::new("marker_cloud_window_markers")
+                .num_columns(4)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Track");
+                    ui.label("Position [m]");
+                    ui.label("Views");
+                    ui.label("RMS [px]");
+                    ui.end_row();
+
+                    for (marker, id) in
+                        cloud.markers.iter().zip(&update.assignments)
+                    {
+                        let p = marker.position;
+                        ui.label(match id {
+                            Some(id) => format!("#{}", id),
+                            None => "-".to_owned(),
+                        });
+                        ui.label(format!(
+                            "({:.3}, {:.3}, {:.3})",
+                            p[0], p[1], p[2]
+                        ));
+                        ui.label(format!("{}", marker.views.len()));
+                        ui.label(format!("{:.2}", marker.rms));
+                        ui.end_row();
+                    }
+                });
+
+            ui.separator();
+            ui.collapsing("Track events", |ui| {
+                for event in events.iter() {
+                    ui.label(event);
+                }
+            });
+        });
+    }
+}
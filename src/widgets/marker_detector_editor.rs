@@ -0,0 +1,83 @@
+use eframe::egui;
+use mocap_for_one::{BlobDetectorConfig, MarkerDetectorConfig};
+
+pub struct MarkerDetectorEditor {
+    id: egui::Id,
+}
+
+impl MarkerDetectorEditor {
+    pub fn new(id: impl std::hash::Hash) -> Self {
+        Self {
+            id: egui::Id::new(id),
+        }
+    }
+
+    /// Edits the detector in place and returns true if anything changed.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        config: &mut MarkerDetectorConfig,
+    ) -> bool {
+        let mut changed = false;
+
+        egui::Grid::new(self.id.with("grid")).num_columns(2).show(ui, |ui| {
+            ui.label("Detector");
+            let is_blob = matches!(config, MarkerDetectorConfig::Blob(_));
+            egui::ComboBox::from_id_salt(self.id.with("kind"))
+                .selected_text(if is_blob { "Blob" } else { "ChArUco" })
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(!is_blob, "ChArUco").clicked()
+                        && is_blob
+                    {
+                        *config = MarkerDetectorConfig::Charuco;
+                        changed = true;
+                    }
+                    if ui.selectable_label(is_blob, "Blob").clicked()
+                        && !is_blob
+                    {
+                        *config = MarkerDetectorConfig::Blob(
+                            BlobDetectorConfig::default(),
+                        );
+                        changed = true;
+                    }
+                });
+            ui.end_row();
+
+            let MarkerDetectorConfig::Blob(blob) = config else {
+                return;
+            };
+
+            ui.label("Threshold");
+            changed |= ui
+                .add(egui::Slider::new(&mut blob.threshold, 0.0..=255.0))
+                .changed();
+            ui.end_row();
+
+            ui.label("Min area [px]");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut blob.min_area)
+                        .range(1.0..=blob.max_area),
+                )
+                .changed();
+            ui.end_row();
+
+            ui.label("Max area [px]");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut blob.max_area)
+                        .range(blob.min_area..=100000.0),
+                )
+                .changed();
+            ui.end_row();
+
+            ui.label("Min circularity");
+            changed |= ui
+                .add(egui::Slider::new(&mut blob.min_circularity, 0.0..=1.0))
+                .changed();
+            ui.end_row();
+        });
+
+        changed
+    }
+}
@@ -1,8 +1,20 @@
+pub mod board_generator_modal;
 pub mod calibration_modal;
+pub mod charuco_board_editor;
+pub mod image_sequence_modal;
+pub mod marker_cloud_window;
+pub mod marker_detector_editor;
+pub mod playback_modal;
 pub mod unity_camera_modal;
 pub mod video_capture_modal;
 pub mod video_viewer;
+pub use board_generator_modal::BoardGeneratorModal;
 pub use calibration_modal::CalibrationModal;
+pub use charuco_board_editor::CharucoBoardEditor;
+pub use image_sequence_modal::ImageSequenceModal;
+pub use marker_cloud_window::MarkerCloudWindow;
+pub use marker_detector_editor::MarkerDetectorEditor;
+pub use playback_modal::PlaybackModal;
 pub use unity_camera_modal::{UnityCameraModal, UnityCameraModalConfig};
 pub use video_capture_modal::VideoCaptureModal;
-pub use video_viewer::VideoViewer;
+pub use video_viewer::{VideoViewer, stream_state_text};
@@ -0,0 +1,61 @@
+use eframe::egui;
+use egui_file::FileDialog;
+use mocap_for_one::{CameraStream, SESSION_MANIFEST, VideoSourceConfig};
+use std::path::PathBuf;
+
+pub struct PlaybackModal {
+    pub dialog: Option<FileDialog>,
+    pub opened_path: Option<PathBuf>,
+}
+
+pub enum PlaybackModalEffect {
+    // session.jsonが選ばれたらセッション全体を開く
+    OnOpenSession(PathBuf),
+    OnOpenCamera(CameraStream),
+    OnError(String),
+}
+
+impl PlaybackModal {
+    pub fn new() -> Self {
+        Self {
+            dialog: None,
+            opened_path: None,
+        }
+    }
+
+    pub fn open(&mut self) {
+        let mut d = FileDialog::open_file(self.opened_path.clone());
+        d.open();
+        self.dialog = Some(d);
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<PlaybackModalEffect> {
+        let dialog = self.dialog.as_mut()?;
+        dialog.show(ctx);
+        if !dialog.selected() {
+            return None;
+        }
+        let path = dialog.path()?.to_path_buf();
+        self.dialog = None;
+        self.opened_path = Some(path.clone());
+
+        if path.file_name().is_some_and(|n| n == SESSION_MANIFEST) {
+            let dir = path.parent()?.to_path_buf();
+            return Some(PlaybackModalEffect::OnOpenSession(dir));
+        }
+
+        let name = path.to_string_lossy().into_owned();
+        let config = VideoSourceConfig::Playback {
+            path: name.clone(),
+            camera: 0,
+            looping: true,
+        };
+        Some(match CameraStream::new(name, config) {
+            Ok(stream) => PlaybackModalEffect::OnOpenCamera(stream),
+            Err(err) => PlaybackModalEffect::OnError(format!(
+                "Failed to open video: {}",
+                err
+            )),
+        })
+    }
+}
@@ -1,7 +1,9 @@
 use eframe::egui::{self, Context, Id, Modal};
 // use egui_dock::DockState;
 // use egui_elm::prelude::*;
-use mocap_for_one::{CameraStream, VideoSourceConfig, enumerate_cameras};
+use mocap_for_one::{
+    CameraEnumerator, CameraStream, VideoSourceConfig, list_cameras,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -9,6 +11,8 @@ pub struct VideoCaptureModal {
     pub open: bool,
     pub enum_cameras: Vec<(i32, String)>,
     pub selected: Option<(i32, String)>,
+    // enum_camerasを列挙した手段
+    pub enumerator: Option<CameraEnumerator>,
 }
 
 pub enum VideoCaptureModalEffect {
@@ -21,13 +25,14 @@ impl VideoCaptureModal {
             open: false,
             enum_cameras: vec![],
             selected: None,
+            enumerator: None,
         }
     }
 
     pub fn open(&mut self) {
-        if let Ok(list) = enumerate_cameras() {
-            self.enum_cameras = list;
-        }
+        let list = list_cameras();
+        self.enum_cameras = list.cameras;
+        self.enumerator = Some(list.enumerator);
         self.open = true;
     }
 
@@ -40,6 +45,12 @@ impl VideoCaptureModal {
 
         Modal::new(Id::new("Open Camera Modal")).show(ctx, |ui| {
             ui.heading("Select Camera Device");
+            if let Some(enumerator) = self.enumerator {
+                ui.label(format!("Listed by: {}", enumerator.label()));
+            }
+            if self.enum_cameras.is_empty() {
+                ui.label("No cameras found");
+            }
 
             egui::ComboBox::from_label("")
                 .selected_text(format!(
@@ -67,8 +78,11 @@ impl VideoCaptureModal {
                 |ui| {
                     if ui.button("Open").clicked() {
                         if let Some((index, name)) = &self.selected {
-                            let config =
-                                VideoSourceConfig::Capture { index: *index };
+                            // 識別情報は開いたときに埋まる
+                            let config = VideoSourceConfig::Capture {
+                                index: *index,
+                                device: None,
+                            };
 
                             match CameraStream::new(name.clone(), config) {
                                 Ok(tcam) => {
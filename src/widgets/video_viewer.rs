@@ -1,14 +1,25 @@
 use eframe::egui::{self, Color32, ColorImage, RichText};
-use mocap_for_one::{OpenCvCamera, mat_to_color_image};
+use mocap_for_one::{
+    CharucoBoardConfig, DetectorStats, MarkerDetectorConfig, MmapSourceState,
+    MmapStatus, OpenCvCamera, PlaybackCommand, PlaybackStatus, StreamState,
+    mat_to_color_image,
+};
 use opencv::{
     core::MatTraitConst, core::Scalar, objdetect::draw_detected_markers,
 };
 
+use crate::widgets::{CharucoBoardEditor, MarkerDetectorEditor};
+
 pub enum VideoViewerEffect {
     OnClose,
     OnStartCalibration,
     OnCaptureFrame,
     OnStopCalibration,
+    OnApplyCharucoBoard,
+    OnMarkerDetectorChanged(MarkerDetectorConfig),
+    OnStartRecording,
+    OnStopRecording,
+    OnPlayback(PlaybackCommand),
 }
 
 pub struct VideoViewer {}
@@ -18,11 +29,19 @@ impl VideoViewer {
         Self {}
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn show(
         &mut self,
         ui: &mut egui::Ui,
         color_img: &ColorImage,
         on_calibration: bool,
+        charuco_board_draft: &mut CharucoBoardConfig,
+        marker_detector: &MarkerDetectorConfig,
+        recording: bool,
+        playback: Option<PlaybackStatus>,
+        mmap: Option<MmapStatus>,
+        stream: &StreamState,
+        detector: DetectorStats,
     ) -> Option<VideoViewerEffect> {
         let mut ret = None;
 
@@ -79,6 +98,15 @@ impl VideoViewer {
                         ret = Some(VideoViewerEffect::OnClose);
                     }
 
+                    let (text, color) = stream_state_text(stream);
+                    ui.label(
+                        RichText::new(format!("Stream: {text}")).color(color),
+                    );
+                    if let Some(status) = mmap {
+                        Self::show_mmap_status(ui, &status);
+                    }
+                    Self::show_detector_stats(ui, &detector);
+
                     if ui
                         .button(
                             RichText::new("Start Calibration")
@@ -99,6 +127,42 @@ impl VideoViewer {
                         }
                     }
 
+                    ui.label("ChArUco Board:");
+                    CharucoBoardEditor::new("video_viewer_charuco_board")
+                        .show(ui, charuco_board_draft);
+                    if ui
+                        .add_enabled(
+                            charuco_board_draft.validate().is_ok(),
+                            egui::Button::new("Apply Board"),
+                        )
+                        .clicked()
+                    {
+                        ret = Some(VideoViewerEffect::OnApplyCharucoBoard);
+                    }
+
+                    ui.separator();
+
+                    if let Some(status) = playback {
+                        ui.label("Playback (all playback cameras):");
+                        if let Some(command) = Self::show_playback(ui, &status)
+                        {
+                            ret = Some(VideoViewerEffect::OnPlayback(command));
+                        }
+                        ui.separator();
+                    }
+
+                    ui.label("Marker Detection:");
+                    let mut detector = marker_detector.clone();
+                    if MarkerDetectorEditor::new("video_viewer_marker_detector")
+                        .show(ui, &mut detector)
+                    {
+                        ret = Some(VideoViewerEffect::OnMarkerDetectorChanged(
+                            detector,
+                        ));
+                    }
+
+                    ui.separator();
+
                     ui.label("Camera Settings:");
                     ui.add(
                         egui::Slider::new(&mut 50.0, 0.0..=100.0)
@@ -125,9 +189,20 @@ impl VideoViewer {
 
                     ui.separator();
 
-                    ui.label("Recording:");
-                    if ui.button("Start Recording").clicked() {
-                        // TODO: Implement recording
+                    // 収録は全カメラまとめて行う
+                    ui.label("Recording (all cameras):");
+                    if recording {
+                        if ui
+                            .button(
+                                RichText::new("Stop Recording")
+                                    .color(Color32::RED),
+                            )
+                            .clicked()
+                        {
+                            ret = Some(VideoViewerEffect::OnStopRecording);
+                        }
+                    } else if ui.button("Start Recording").clicked() {
+                        ret = Some(VideoViewerEffect::OnStartRecording);
                     }
                     if ui.button("Take Screenshot").clicked() {
                         // TODO: Implement screenshot
@@ -151,4 +226,84 @@ impl VideoViewer {
 
         ret
     }
+
+    fn show_detector_stats(ui: &mut egui::Ui, stats: &DetectorStats) {
+        ui.label(format!(
+            "Detection: {:.1} ms (avg {:.1} ms), latency {:.1} ms",
+            stats.detection_ms, stats.mean_detection_ms, stats.latency_ms
+        ));
+        ui.label(format!(
+            "Frames: {} processed, {} skipped, {} failed",
+            stats.frames_processed, stats.frames_skipped, stats.frames_failed
+        ));
+    }
+
+    fn show_mmap_status(ui: &mut egui::Ui, status: &MmapStatus) {
+        let (text, color) = match status.state {
+            MmapSourceState::Waiting => ("Waiting for frames", Color32::GRAY),
+            MmapSourceState::Streaming => ("Streaming", Color32::GREEN),
+            MmapSourceState::Restarted => ("Source restarted", Color32::YELLOW),
+            MmapSourceState::Missing => ("File missing", Color32::RED),
+        };
+        ui.label(RichText::new(format!("Unity: {text}")).color(color));
+        if status.restarts > 0 {
+            ui.label(format!("Restarted {} times", status.restarts));
+        }
+    }
+
+    fn show_playback(
+        ui: &mut egui::Ui,
+        status: &PlaybackStatus,
+    ) -> Option<PlaybackCommand> {
+        let mut ret = None;
+
+        ui.horizontal(|ui| {
+            if ui.button("< Step").clicked() {
+                ret = Some(PlaybackCommand::Step(-1));
+            }
+            if status.playing {
+                if ui.button("Pause").clicked() {
+                    ret = Some(PlaybackCommand::Pause);
+                }
+            } else if ui.button("Play").clicked() {
+                ret = Some(PlaybackCommand::Play);
+            }
+            if ui.button("Step >").clicked() {
+                ret = Some(PlaybackCommand::Step(1));
+            }
+
+            let mut looping = status.looping;
+            if ui.checkbox(&mut looping, "Loop").changed() {
+                ret = Some(PlaybackCommand::SetLooping(looping));
+            }
+        });
+
+        let mut time = status.time;
+        if ui
+            .add(
+                egui::Slider::new(&mut time, 0.0..=status.duration)
+                    .suffix(" s"),
+            )
+            .changed()
+        {
+            ret = Some(PlaybackCommand::Seek(time));
+        }
+        ui.label(format!(
+            "Frame {} / {}",
+            status.position, status.frame_count
+        ));
+
+        ret
+    }
+}
+
+/// Short description and color of a stream's health, for tabs and panels.
+pub fn stream_state_text(state: &StreamState) -> (String, Color32) {
+    match state {
+        StreamState::Connecting => ("Connecting".to_owned(), Color32::GRAY),
+        StreamState::Streaming => ("Streaming".to_owned(), Color32::GREEN),
+        StreamState::Stalled => ("Stalled".to_owned(), Color32::YELLOW),
+        StreamState::Disconnected => ("Disconnected".to_owned(), Color32::RED),
+        StreamState::Error(msg) => (format!("Error: {msg}"), Color32::RED),
+    }
 }
@@ -1,35 +1,114 @@
 use crate::{
-    CameraParameter, CameraParameterNum, CameraStream, CharucoMarker,
-    OpenCvCamera, OpenCvCameraConfig, VideoSourceConfig, camera_stream,
+    Blob, BoardObservation, BundleAdjustmentOptions, BundleAdjustmentReport,
+    CalibratedCamera, CameraParameter, CameraParameterNum, CameraStream,
+    CameraStreamConfig, CharucoBoardConfig, CharucoMarker,
+    CorrespondenceOptions, DetectedFrame, ExtrinsicCalibration, Frame,
+    FrameSynchronizer, Intrinsics, MarkerCloud, MarkerDetectorConfig,
+    OpenCvCamera, OpenCvCameraConfig, PlaybackCommand, PointObservation, Pose,
+    RecordingSummary, Session, SessionRecorder, SyncConfig, SyncStats,
+    SyncedFrameSet, SyntheticConfig, TimeOffsetEstimate, TimeOffsetOptions,
+    Tracker, TrackerConfig, TrackerUpdate, TriangulatedPoint,
+    TriangulationOptions, VideoSourceConfig, bundle_adjust, camera_stream,
+    estimate_session_offsets, initial_board_poses, match_markers,
+    solve_extrinsics, triangulate,
 };
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use opencv::core::Size;
 use opencv::{
     aruco::{calibrate_camera_charuco, calibrate_camera_charuco_def},
     core::{Mat, MatTraitConst, Point2f, Ptr, Vector},
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct WorkLoadConfig {
-    pub opencv_cams: Vec<OpenCvCameraConfig>,
+    pub opencv_cams: Vec<OpenCvCameraModelConfig>,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub tracker: TrackerConfig,
 }
 
 pub struct WorkLoad {
     pub opencv_cams: Vec<OpenCvCameraModel>,
+    // 設定にあったが開けなかったカメラ。保存時に失わないよう設定ごと残す
+    pub unavailable_cams: Vec<UnavailableCamera>,
+    // 全カメラで同時に撮影したフレーム。framesの添字はopencv_camsの添字に対応する
+    pub synchronized_captures: Vec<SynchronizedCapture>,
+    pub marker_tracker: Tracker,
+    pub marker_options: CorrespondenceOptions,
+    // 最後にトラッカーへ入力した同期セットの結果
+    tracked_markers: Option<TrackedMarkers>,
+    // トラッカーに渡す時刻の基準
+    tracking_epoch: Instant,
+    recorder: Option<SessionRecorder>,
+    sync_config: SyncConfig,
+    // opencv_camsと同じ順番で検出済みフレームを組にする
+    synchronizer: FrameSynchronizer<DetectedFrame>,
+    // 動画の読み込みは遅いので、時刻オフセットは別スレッドで推定する
+    offset_estimation: Option<OffsetEstimation>,
+}
+
+struct OffsetEstimation {
+    // opencv_camsの添字ごとのセッション内のカメラ番号
+    session_cameras: Vec<Option<usize>>,
+    handle: JoinHandle<Result<Vec<Option<TimeOffsetEstimate>>>>,
+}
+
+/// A saved camera that could not be opened, kept with its config so that
+/// saving does not drop it and it can be opened again later.
+#[derive(Clone)]
+pub struct UnavailableCamera {
+    pub config: OpenCvCameraModelConfig,
+    pub error: String,
+}
+
+impl UnavailableCamera {
+    pub fn name(&self) -> &str {
+        &self.config.opencv_camera.camera_stream_config.name
+    }
 }
 
 impl TryFrom<WorkLoadConfig> for WorkLoad {
     type Error = anyhow::Error;
 
     fn try_from(config: WorkLoadConfig) -> Result<Self, Self::Error> {
-        let opencv_cams = config
-            .opencv_cams
-            .into_iter()
-            .map(TryInto::try_into)
-            .map(|c| OpenCvCameraModel::new(c.unwrap()))
-            .collect();
-        Ok(Self { opencv_cams })
+        // 1台が開けなくても他のカメラやキャリブレーションは読み込む
+        let mut opencv_cams = Vec::new();
+        let mut unavailable_cams = Vec::new();
+        for cam in config.opencv_cams {
+            match OpenCvCameraModel::try_from(cam.clone()) {
+                Ok(model) => opencv_cams.push(model),
+                Err(err) => {
+                    eprintln!(
+                        "Failed to open camera {}: {err}",
+                        cam.opencv_camera.camera_stream_config.name
+                    );
+                    unavailable_cams.push(UnavailableCamera {
+                        config: cam,
+                        error: err.to_string(),
+                    });
+                }
+            }
+        }
+        let synchronizer = Self::build_synchronizer(&opencv_cams, config.sync);
+        Ok(Self {
+            opencv_cams,
+            unavailable_cams,
+            synchronized_captures: Vec::new(),
+            marker_tracker: Tracker::new(config.tracker),
+            marker_options: CorrespondenceOptions::default(),
+            tracked_markers: None,
+            tracking_epoch: Instant::now(),
+            recorder: None,
+            sync_config: config.sync,
+            synchronizer,
+            offset_estimation: None,
+        })
     }
 }
 
@@ -39,8 +118,13 @@ impl From<&WorkLoad> for WorkLoadConfig {
             opencv_cams: workload
                 .opencv_cams
                 .iter()
-                .map(|c| (&c.opencv_camera).into())
+                .map(Into::into)
+                .chain(
+                    workload.unavailable_cams.iter().map(|c| c.config.clone()),
+                )
                 .collect(),
+            sync: workload.sync_config,
+            tracker: workload.marker_tracker.config,
         }
     }
 }
@@ -49,22 +133,698 @@ impl WorkLoad {
     pub fn new() -> Self {
         Self {
             opencv_cams: vec![],
+            unavailable_cams: Vec::new(),
+            synchronized_captures: Vec::new(),
+            marker_tracker: Tracker::new(TrackerConfig::default()),
+            marker_options: CorrespondenceOptions::default(),
+            tracked_markers: None,
+            tracking_epoch: Instant::now(),
+            recorder: None,
+            sync_config: SyncConfig::default(),
+            synchronizer: Self::build_synchronizer(&[], SyncConfig::default()),
+            offset_estimation: None,
+        }
+    }
+
+    pub fn add_camera_stream(&mut self, stream: CameraStream) -> Result<()> {
+        let opencv_camera = OpenCvCamera::new(
+            stream,
+            CharucoBoardConfig::default(),
+            MarkerDetectorConfig::default(),
+        )?;
+        self.push_camera(OpenCvCameraModel::new(opencv_camera));
+        Ok(())
+    }
+
+    /// Adds a synthetic camera whose detector uses the rendered board.
+    pub fn add_synthetic_camera(
+        &mut self,
+        name: String,
+        config: SyntheticConfig,
+    ) -> Result<()> {
+        let board = config.board.clone();
+        let stream =
+            CameraStream::new(name, VideoSourceConfig::Synthetic(config))?;
+        let opencv_camera =
+            OpenCvCamera::new(stream, board, MarkerDetectorConfig::default())?;
+        self.push_camera(OpenCvCameraModel::new(opencv_camera));
+        Ok(())
+    }
+
+    /// Adds every camera of a recorded session as a playback source,
+    /// together with the board, detector and calibration it was recorded
+    /// with. Returns the number of cameras added.
+    pub fn open_session(&mut self, dir: impl AsRef<Path>) -> Result<usize> {
+        let dir = dir.as_ref();
+        let session = Session::open(dir)?;
+        let path = dir.to_string_lossy().into_owned();
+
+        for (i, camera) in session.manifest.cameras.iter().enumerate() {
+            let mut config = session
+                .manifest
+                .workload
+                .opencv_cams
+                .get(i)
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow!("session has no configuration for camera {i}")
+                })?;
+            config.opencv_camera.camera_stream_config = CameraStreamConfig {
+                name: format!("{} (playback)", camera.name),
+                video_source_config: VideoSourceConfig::Playback {
+                    path: path.clone(),
+                    camera: i,
+                    looping: true,
+                },
+            };
+            self.push_camera(config.try_into()?);
+        }
+        Ok(session.manifest.cameras.len())
+    }
+
+    /// Sends a transport command to every playback camera so that cameras
+    /// of the same session stay in step.
+    pub fn control_playback(&self, command: PlaybackCommand) {
+        for cam in &self.opencv_cams {
+            if let Some(playback) = &cam.opencv_camera.playback {
+                playback.send(command);
+            }
+        }
+    }
+
+    fn push_camera(&mut self, model: OpenCvCameraModel) {
+        self.opencv_cams.push(model);
+        for capture in &mut self.synchronized_captures {
+            capture.frames.push(None);
+        }
+        self.rebuild_synchronizer();
+    }
+
+    pub fn remove_camera(&mut self, index: usize) {
+        if index >= self.opencv_cams.len() {
+            return;
+        }
+        let removed = self.opencv_cams.remove(index);
+        // 複製が残っていてもデバイスはすぐ開き直せるようにする
+        removed.opencv_camera.close();
+        for capture in &mut self.synchronized_captures {
+            capture.frames.remove(index);
+        }
+        self.rebuild_synchronizer();
+    }
+
+    /// Tries to open every unavailable camera again and returns the number
+    /// opened. Cameras that still fail keep their config and latest error.
+    pub fn retry_unavailable_cameras(&mut self) -> usize {
+        let mut opened = 0;
+        for mut cam in std::mem::take(&mut self.unavailable_cams) {
+            match OpenCvCameraModel::try_from(cam.config.clone()) {
+                Ok(model) => {
+                    self.push_camera(model);
+                    opened += 1;
+                }
+                Err(err) => {
+                    cam.error = err.to_string();
+                    self.unavailable_cams.push(cam);
+                }
+            }
+        }
+        opened
+    }
+
+    /// Forgets an unavailable camera, so it is no longer saved.
+    pub fn remove_unavailable_camera(&mut self, index: usize) {
+        if index < self.unavailable_cams.len() {
+            self.unavailable_cams.remove(index);
+        }
+    }
+
+    fn build_synchronizer(
+        cams: &[OpenCvCameraModel],
+        config: SyncConfig,
+    ) -> FrameSynchronizer<DetectedFrame> {
+        let receivers = cams
+            .iter()
+            .map(|c| c.opencv_camera.subscribe_detected_frames())
+            .collect();
+        let offsets = cams.iter().map(|c| c.time_offset).collect();
+        FrameSynchronizer::new(receivers, offsets, config)
+    }
+
+    // カメラの増減で添字がずれるので作り直す
+    fn rebuild_synchronizer(&mut self) {
+        self.synchronizer =
+            Self::build_synchronizer(&self.opencv_cams, self.sync_config);
+    }
+
+    pub fn sync_config(&self) -> SyncConfig {
+        self.sync_config
+    }
+
+    pub fn set_sync_config(&mut self, config: SyncConfig) -> Result<()> {
+        self.synchronizer.set_config(config)?;
+        self.sync_config = config;
+        Ok(())
+    }
+
+    /// Sets the time offset [s] of every camera, in the order of
+    /// `opencv_cams`. Offsets are saved with the cameras and subtracted from
+    /// capture times when grouping frames. Non-finite offsets are rejected.
+    pub fn set_time_offsets(&mut self, offsets: &[f64]) -> Result<()> {
+        if let Some(offset) = offsets.iter().find(|o| !o.is_finite()) {
+            return Err(anyhow!("invalid time offset {offset} s"));
+        }
+        for (cam, offset) in self.opencv_cams.iter_mut().zip(offsets) {
+            cam.time_offset = *offset;
+        }
+        self.synchronizer.set_offsets(
+            self.opencv_cams.iter().map(|c| c.time_offset).collect(),
+        );
+        Ok(())
+    }
+
+    /// Starts estimating time offsets from a recorded session on a worker
+    /// thread. `reference` indexes `opencv_cams` and must be one of the
+    /// cameras the session was recorded from, or is playing it back.
+    /// Call `poll_time_offsets` to apply the result.
+    pub fn estimate_time_offsets(
+        &mut self,
+        dir: impl AsRef<Path>,
+        reference: usize,
+        options: &TimeOffsetOptions,
+    ) -> Result<()> {
+        if self.offset_estimation.is_some() {
+            return Err(anyhow!("time offsets are already being estimated"));
+        }
+        let dir = dir.as_ref();
+        let session = Session::open(dir)?;
+
+        // 再生中のカメラはセッション内の番号、それ以外は名前で対応を取る
+        let session_cameras: Vec<Option<usize>> =
+            self.opencv_cams
+                .iter()
+                .map(|cam| {
+                    let stream = &cam.opencv_camera.camera_stream_config;
+                    match &stream.video_source_config {
+                        VideoSourceConfig::Playback {
+                            path, camera, ..
+                        } if Path::new(path) == dir => Some(*camera),
+                        _ => session
+                            .manifest
+                            .cameras
+                            .iter()
+                            .position(|c| c.name == stream.name),
+                    }
+                })
+                .collect();
+        let reference = session_cameras
+            .get(reference)
+            .copied()
+            .flatten()
+            .ok_or_else(|| {
+                anyhow!("reference camera {reference} is not in the session")
+            })?;
+        let options = *options;
+        let handle = thread::spawn(move || {
+            estimate_session_offsets(&session, reference, &options)
+        });
+        self.offset_estimation = Some(OffsetEstimation {
+            session_cameras,
+            handle,
+        });
+        Ok(())
+    }
+
+    pub fn is_estimating_time_offsets(&self) -> bool {
+        self.offset_estimation.is_some()
+    }
+
+    /// Applies the time offsets once the estimation started by
+    /// `estimate_time_offsets` has finished, and returns the number of
+    /// cameras updated. Returns `None` while it is still running.
+    pub fn poll_time_offsets(&mut self) -> Option<Result<usize>> {
+        if !self
+            .offset_estimation
+            .as_ref()
+            .is_some_and(|e| e.handle.is_finished())
+        {
+            return None;
+        }
+        let estimation = self.offset_estimation.take()?;
+        let estimates = match estimation.handle.join() {
+            Ok(Ok(estimates)) => estimates,
+            Ok(Err(err)) => return Some(Err(err)),
+            Err(_) => {
+                return Some(Err(anyhow!("time offset estimation panicked")));
+            }
+        };
+        // 推定中にカメラが追加・削除されたら対応が取れない
+        if estimation.session_cameras.len() != self.opencv_cams.len() {
+            return Some(Err(anyhow!(
+                "cameras changed while estimating time offsets"
+            )));
+        }
+
+        let mut offsets: Vec<f64> =
+            self.opencv_cams.iter().map(|c| c.time_offset).collect();
+        let mut updated = 0;
+        for (camera, offset) in
+            estimation.session_cameras.iter().zip(&mut offsets)
+        {
+            if let Some(Some(estimate)) =
+                camera.and_then(|camera| estimates.get(camera))
+            {
+                *offset = estimate.offset;
+                updated += 1;
+            }
+        }
+        Some(self.set_time_offsets(&offsets).map(|()| updated))
+    }
+
+    /// Warnings about cameras that capture from the same physical device,
+    /// which usually means a camera was added twice.
+    pub fn duplicate_device_warnings(&self) -> Vec<String> {
+        let streams: Vec<&CameraStreamConfig> = self
+            .opencv_cams
+            .iter()
+            .map(|c| &c.opencv_camera.camera_stream_config)
+            .collect();
+        let mut warnings = Vec::new();
+        for (i, a) in streams.iter().enumerate() {
+            for b in &streams[i + 1..] {
+                if a.video_source_config
+                    .same_capture_device(&b.video_source_config)
+                {
+                    warnings.push(format!(
+                        "'{}' and '{}' use the same camera device.",
+                        a.name, b.name
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    pub fn sync_stats(&self) -> SyncStats {
+        self.synchronizer.stats()
+    }
+
+    /// The latest set of frames captured at the same instant by every
+    /// camera, with views in the order of `opencv_cams`.
+    pub fn latest_synced_set(&self) -> Option<SyncedFrameSet<DetectedFrame>> {
+        self.synchronizer
+            .latest_set()
+            .filter(|set| set.views.len() == self.opencv_cams.len())
+    }
+
+    /// Captures the latest synchronized set of annotated frames.
+    pub fn capture_synchronized_frames(&mut self) -> Result<()> {
+        let set = self.latest_synced_set().ok_or_else(|| {
+            anyhow!("no set of frames within the sync tolerance yet")
+        })?;
+        let frames = set
+            .views
+            .iter()
+            .map(|view| {
+                let frame = FrameAnnotated::from(view.nearest().clone());
+                (!frame.charuco_ids.empty()).then_some(frame)
+            })
+            .collect();
+        self.synchronized_captures.push(SynchronizedCapture { frames });
+        Ok(())
+    }
+
+    pub fn clear_synchronized_captures(&mut self) {
+        self.synchronized_captures.clear();
+    }
+
+    /// Starts recording every camera into a new session directory under
+    /// `root` and returns its path.
+    pub fn start_recording(
+        &mut self,
+        root: impl AsRef<Path>,
+    ) -> Result<PathBuf> {
+        if self.recorder.is_some() {
+            return Err(anyhow!("already recording"));
         }
+        let recorder = SessionRecorder::start(root, self)?;
+        let dir = recorder.dir().to_path_buf();
+        self.recorder = Some(recorder);
+        Ok(dir)
     }
 
-    pub fn add_camera_stream(&mut self, stream: CameraStream) {
-        self.opencv_cams
-            .push(OpenCvCameraModel::new(OpenCvCamera::new(stream)));
+    /// Stops the current recording, if any.
+    pub fn stop_recording(&mut self) -> Result<Option<RecordingSummary>> {
+        self.recorder.take().map(SessionRecorder::stop).transpose()
     }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Solves every camera's pose relative to `reference` from the
+    /// synchronized captures and stores it on each camera model.
+    /// All cameras must already have intrinsics and share the same board.
+    pub fn calibrate_extrinsics(
+        &mut self,
+        reference: usize,
+    ) -> Result<ExtrinsicCalibration> {
+        let intrinsics = self.calibration_intrinsics(reference)?;
+        let observations = self.synchronized_observations()?;
+
+        let calibration =
+            solve_extrinsics(&observations, &intrinsics, reference)?;
+
+        for (cam, pose) in
+            self.opencv_cams.iter_mut().zip(&calibration.camera_poses)
+        {
+            cam.extrinsic = *pose;
+        }
+
+        Ok(calibration)
+    }
+
+    /// Refines intrinsics, extrinsics and board poses together with bundle
+    /// adjustment. Uses the synchronized captures as well as the frames
+    /// each camera captured for its own intrinsic calibration.
+    /// `calibrate_extrinsics` has to be run first.
+    pub fn refine_calibration(
+        &mut self,
+        reference: usize,
+        options: &BundleAdjustmentOptions,
+    ) -> Result<BundleAdjustmentReport> {
+        let mut intrinsics = self.calibration_intrinsics(reference)?;
+        let mut camera_poses: Vec<Option<Pose>> =
+            self.opencv_cams.iter().map(|c| c.extrinsic).collect();
+        if camera_poses[reference].is_none() {
+            return Err(anyhow!(
+                "reference camera has no extrinsic calibration"
+            ));
+        }
+
+        let mut observations = self.synchronized_observations()?;
+        // カメラ単体で撮影したフレームは同期キャプチャの後ろの番号を振る
+        let mut capture = self.synchronized_captures.len();
+        for (camera, cam) in self.opencv_cams.iter().enumerate() {
+            if camera_poses[camera].is_none() {
+                continue;
+            }
+            let camera_parameter =
+                cam.camera_parameter.as_ref().expect("checked above");
+            for frame in &cam.captured_frame_annotated_vec {
+                if let Some(obs) = BoardObservation::from_charuco(
+                    camera,
+                    capture,
+                    &cam.opencv_camera.charuco_board,
+                    camera_parameter,
+                    &frame.charuco_corners,
+                    &frame.charuco_ids,
+                )? {
+                    observations.push(obs);
+                }
+                capture += 1;
+            }
+        }
+
+        let mut board_poses = initial_board_poses(&observations, &camera_poses);
+        let report = bundle_adjust(
+            &observations,
+            &mut intrinsics,
+            &mut camera_poses,
+            &mut board_poses,
+            reference,
+            options,
+        );
+
+        for ((cam, pose), intrinsics) in
+            self.opencv_cams.iter_mut().zip(&camera_poses).zip(&intrinsics)
+        {
+            cam.extrinsic = *pose;
+            if !options.fix_intrinsics && pose.is_some() {
+                cam.camera_parameter =
+                    Some(CameraParameter::try_from(intrinsics)?);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Triangulates the ChArUco corners currently detected by the
+    /// calibrated cameras. Corner ids serve as the correspondence, which
+    /// makes this a handy check of the calibration against the known board.
+    pub fn triangulate_charuco_corners(
+        &self,
+        options: &TriangulationOptions,
+    ) -> Result<BTreeMap<i32, TriangulatedPoint>> {
+        let mut cameras = Vec::new();
+        let mut observations: BTreeMap<i32, Vec<PointObservation>> =
+            BTreeMap::new();
+        let Some(set) = self.latest_synced_set() else {
+            return Ok(BTreeMap::new());
+        };
+        for (cam, view) in self.opencv_cams.iter().zip(&set.views) {
+            let Some(calibrated) = cam.calibrated_camera() else {
+                continue;
+            };
+            for (id, point) in view.charuco_corners()? {
+                observations.entry(id).or_default().push(PointObservation {
+                    camera: cameras.len(),
+                    point,
+                });
+            }
+            cameras.push(calibrated);
+        }
+
+        Ok(observations
+            .into_iter()
+            .filter_map(|(id, observations)| {
+                Some((id, triangulate(&cameras, &observations, options)?))
+            })
+            .collect())
+    }
+
+    /// Matches the blobs currently detected by the calibrated cameras and
+    /// triangulates them into a marker cloud. Camera indices in the result
+    /// refer to `opencv_cams`.
+    pub fn reconstruct_markers(
+        &self,
+        options: &CorrespondenceOptions,
+    ) -> MarkerCloud {
+        match self.latest_synced_set() {
+            Some(set) => self.reconstruct_markers_in(&set, options),
+            None => MarkerCloud::default(),
+        }
+    }
+
+    fn reconstruct_markers_in(
+        &self,
+        set: &SyncedFrameSet<DetectedFrame>,
+        options: &CorrespondenceOptions,
+    ) -> MarkerCloud {
+        let mut indices = Vec::new();
+        let mut cameras = Vec::new();
+        let mut detections = Vec::new();
+        for (i, (cam, view)) in
+            self.opencv_cams.iter().zip(&set.views).enumerate()
+        {
+            let Some(calibrated) = cam.calibrated_camera() else {
+                continue;
+            };
+            indices.push(i);
+            cameras.push(calibrated);
+            // ブロブはフレーム間で対応が取れないので補間しない
+            detections
+                .push(view.nearest().blobs.iter().map(|b| b.center).collect());
+        }
+
+        let mut cloud = match_markers(&cameras, &detections, options);
+        for marker in &mut cloud.markers {
+            for view in &mut marker.views {
+                view.camera = indices[view.camera];
+            }
+        }
+        cloud
+    }
+
+    /// Feeds the markers of a new synchronized set to the tracker, using
+    /// the capture time of the set. Returns `None` when no new set has
+    /// arrived since the last call or fewer than 2 cameras are calibrated.
+    pub fn update_tracking(&mut self) -> Option<&TrackedMarkers> {
+        let set = self.latest_synced_set()?;
+        if self.tracked_markers.as_ref().is_some_and(|t| t.time == set.time) {
+            return None;
+        }
+        let calibrated = self
+            .opencv_cams
+            .iter()
+            .filter(|c| c.calibrated_camera().is_some())
+            .count();
+        if calibrated < 2 {
+            return None;
+        }
+
+        let cloud = self.reconstruct_markers_in(&set, &self.marker_options);
+        let points: Vec<_> = cloud.markers.iter().map(|m| m.position).collect();
+        // 補正後の時刻は基準より前になりうるので符号付きで求める
+        let timestamp = match set
+            .time
+            .checked_duration_since(self.tracking_epoch)
+        {
+            Some(elapsed) => elapsed.as_secs_f64(),
+            None => -self.tracking_epoch.duration_since(set.time).as_secs_f64(),
+        };
+        let update = self.marker_tracker.update(timestamp, &points);
+        self.tracked_markers = Some(TrackedMarkers {
+            time: set.time,
+            cloud,
+            update,
+        });
+        self.tracked_markers.as_ref()
+    }
+
+    /// Markers of the set last fed to the tracker.
+    pub fn tracked_markers(&self) -> Option<&TrackedMarkers> {
+        self.tracked_markers.as_ref()
+    }
+
+    // 全カメラが同じボードを使っていて、内部パラメータが求まっていることを確認する
+    fn calibration_intrinsics(
+        &self,
+        reference: usize,
+    ) -> Result<Vec<Intrinsics>> {
+        let reference_cam = self
+            .opencv_cams
+            .get(reference)
+            .ok_or_else(|| anyhow!("reference camera {reference} not found"))?;
+        let board_config = &reference_cam.opencv_camera.charuco_board_config;
+
+        let mut intrinsics = Vec::with_capacity(self.opencv_cams.len());
+        for cam in &self.opencv_cams {
+            let name = &cam.opencv_camera.camera_stream_config.name;
+            if &cam.opencv_camera.charuco_board_config != board_config {
+                return Err(anyhow!(
+                    "camera '{name}' uses a different ChArUco board"
+                ));
+            }
+            let camera_parameter =
+                cam.camera_parameter.as_ref().ok_or_else(|| {
+                    anyhow!("camera '{name}' has no intrinsic calibration")
+                })?;
+            intrinsics.push(Intrinsics::try_from(camera_parameter)?);
+        }
+        Ok(intrinsics)
+    }
+
+    fn synchronized_observations(&self) -> Result<Vec<BoardObservation>> {
+        let mut observations = Vec::new();
+        for (capture, synchronized) in
+            self.synchronized_captures.iter().enumerate()
+        {
+            for (camera, frame) in synchronized.frames.iter().enumerate() {
+                let (Some(frame), Some(cam)) =
+                    (frame, self.opencv_cams.get(camera))
+                else {
+                    continue;
+                };
+                let Some(camera_parameter) = cam.camera_parameter.as_ref()
+                else {
+                    continue;
+                };
+                if let Some(obs) = BoardObservation::from_charuco(
+                    camera,
+                    capture,
+                    &cam.opencv_camera.charuco_board,
+                    camera_parameter,
+                    &frame.charuco_corners,
+                    &frame.charuco_ids,
+                )? {
+                    observations.push(obs);
+                }
+            }
+        }
+        Ok(observations)
+    }
+}
+
+#[derive(Clone)]
+pub struct FrameAnnotated {
+    pub frame: Frame,
+    pub charuco_corners: Mat,
+    pub charuco_ids: Mat,
+    pub marker_corners: Vector<Vector<Point2f>>,
+    pub marker_ids: Vector<i32>,
+}
+
+impl From<DetectedFrame> for FrameAnnotated {
+    fn from(detected: DetectedFrame) -> Self {
+        let charuco_marker = detected.charuco_marker;
+        Self {
+            frame: detected.frame,
+            charuco_corners: charuco_marker.charuco_corners,
+            charuco_ids: charuco_marker.charuco_ids,
+            marker_corners: charuco_marker.marker_corners,
+            marker_ids: charuco_marker.marker_ids,
+        }
+    }
+}
+
+/// Markers reconstructed from one synchronized set and the tracker's
+/// assignments for them.
+#[derive(Clone, Debug)]
+pub struct TrackedMarkers {
+    pub time: Instant,
+    pub cloud: MarkerCloud,
+    pub update: TrackerUpdate,
 }
 
 #[derive(Clone)]
-struct FrameAnnotated {
-    frame: Mat,
-    charuco_corners: Mat,
-    charuco_ids: Mat,
-    marker_corners: Vector<Vector<Point2f>>,
-    marker_ids: Vector<i32>,
+pub struct SynchronizedCapture {
+    pub frames: Vec<Option<FrameAnnotated>>,
+}
+
+// カメラ単体の設定に加えてキャリブレーション結果も保存する。
+// flattenしているので以前の設定ファイルもそのまま読める
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpenCvCameraModelConfig {
+    #[serde(flatten)]
+    pub opencv_camera: OpenCvCameraConfig,
+    #[serde(default)]
+    pub camera_parameter: Option<CameraParameterNum>,
+    #[serde(default)]
+    pub extrinsic: Option<Pose>,
+    #[serde(default)]
+    pub time_offset: f64,
+}
+
+impl TryFrom<OpenCvCameraModelConfig> for OpenCvCameraModel {
+    type Error = anyhow::Error;
+
+    fn try_from(config: OpenCvCameraModelConfig) -> Result<Self, Self::Error> {
+        let mut model =
+            OpenCvCameraModel::new(config.opencv_camera.try_into()?);
+        model.camera_parameter = config
+            .camera_parameter
+            .as_ref()
+            .map(TryInto::try_into)
+            .transpose()?;
+        model.extrinsic = config.extrinsic;
+        model.time_offset = config.time_offset;
+        Ok(model)
+    }
+}
+
+impl From<&OpenCvCameraModel> for OpenCvCameraModelConfig {
+    fn from(model: &OpenCvCameraModel) -> Self {
+        Self {
+            opencv_camera: (&model.opencv_camera).into(),
+            camera_parameter: model
+                .camera_parameter
+                .as_ref()
+                .and_then(|p| p.try_into().ok()),
+            extrinsic: model.extrinsic,
+            time_offset: model.time_offset,
+        }
+    }
 }
 
 pub struct OpenCvCameraModel {
@@ -73,20 +833,29 @@ pub struct OpenCvCameraModel {
     pub captured_frame_annotated_vec: Vec<FrameAnnotated>,
     pub camera_parameter: Option<CameraParameter>,
     pub camera_parameter_path: Option<String>,
+    // UIで編集中のボード設定。Applyされるまでopencv_cameraには反映しない
+    pub charuco_board_draft: CharucoBoardConfig,
+    // world(基準カメラの座標系)からこのカメラの座標系への変換
+    pub extrinsic: Option<Pose>,
+    // このカメラの撮影時刻の遅れ [s]。フレームを組にするときに差し引く
+    pub time_offset: f64,
 }
 
 impl OpenCvCameraModel {
     pub fn new(opencv_camera: OpenCvCamera) -> Self {
         Self {
+            charuco_board_draft: opencv_camera.charuco_board_config.clone(),
             opencv_camera,
             on_calibration: false,
             captured_frame_annotated_vec: Vec::new(),
             camera_parameter: None,
             camera_parameter_path: None,
+            extrinsic: None,
+            time_offset: 0.0,
         }
     }
 
-    pub fn get_latest_frame(&self) -> Mat {
+    pub fn get_latest_frame(&self) -> Frame {
         self.opencv_camera.get_latest_frame()
     }
 
@@ -94,15 +863,35 @@ impl OpenCvCameraModel {
         self.opencv_camera.get_latest_charuco_markers()
     }
 
+    pub fn get_latest_blobs(&self) -> Vec<Blob> {
+        self.opencv_camera.get_latest_blobs()
+    }
+
+    /// Intrinsics and extrinsics together, once both are calibrated.
+    pub fn calibrated_camera(&self) -> Option<CalibratedCamera> {
+        Some(CalibratedCamera {
+            intrinsics: Intrinsics::try_from(self.camera_parameter.as_ref()?)
+                .ok()?,
+            pose: self.extrinsic?,
+        })
+    }
+
+    pub fn get_current_frame_with_annotated(&self) -> FrameAnnotated {
+        // フレームと検出結果を別々に取ると食い違うことがあるので組で取る
+        self.opencv_camera.get_latest_detected_frame().into()
+    }
+
     pub fn capture_current_frame_with_annotated(&mut self) {
-        let charuco_marker = self.get_latest_charuco_markers();
-        self.captured_frame_annotated_vec.push(FrameAnnotated {
-            frame: self.get_latest_frame(),
-            charuco_corners: charuco_marker.charuco_corners,
-            charuco_ids: charuco_marker.charuco_ids,
-            marker_corners: charuco_marker.marker_corners,
-            marker_ids: charuco_marker.marker_ids,
-        });
+        let frame_annotated = self.get_current_frame_with_annotated();
+        self.captured_frame_annotated_vec.push(frame_annotated);
+    }
+
+    pub fn apply_charuco_board_draft(&mut self) -> Result<()> {
+        self.opencv_camera
+            .set_charuco_board_config(self.charuco_board_draft.clone())?;
+        // 以前のボードで撮影したフレームは新しいボードでのキャリブレーションに使えない
+        self.clear_captured_frames_with_annotated();
+        Ok(())
     }
 
     pub fn clear_captured_frames_with_annotated(&mut self) {
@@ -118,7 +907,8 @@ impl OpenCvCameraModel {
             charuco_ids.push(frame_annotated.charuco_ids.clone());
         }
 
-        let image_size = self.opencv_camera.get_latest_frame().size().unwrap();
+        let image_size =
+            self.opencv_camera.get_latest_frame().image.size().unwrap();
 
         let camera_parameter = self.opencv_camera.calibrate_camera(
             &mut charuco_corners,
@@ -169,6 +959,9 @@ impl Clone for OpenCvCameraModel {
                 .clone(),
             camera_parameter: self.camera_parameter.clone(),
             camera_parameter_path: self.camera_parameter_path.clone(),
+            charuco_board_draft: self.charuco_board_draft.clone(),
+            extrinsic: self.extrinsic,
+            time_offset: self.time_offset,
         }
     }
 }